@@ -0,0 +1,202 @@
+use reqwest::Url;
+use scraper_core::{crawler::SharedState, download::Downloads};
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    join, pin, select, signal::ctrl_c, spawn, sync::oneshot, task::JoinHandle, time::interval,
+};
+
+use crate::{
+    db::{with_db, CrawlDb},
+    write_new::{write_known_url, write_scraped_url, write_waitlist},
+};
+
+/// interval between two periodic checkpoints
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// everything needed to continue a crawl
+#[derive(Debug, Clone, Default)]
+pub struct Checkpoint {
+    pub known_url: HashMap<Url, bool>,
+    pub scraped_url: HashMap<Url, usize>,
+    /// URL not finished yet, in order
+    pub waitlist: Vec<Url>,
+}
+
+impl Checkpoint {
+    /// take a snapshot of the crawl state to resume from\
+    /// in-flight URL are put back into the waitlist as unchecked
+    pub fn take(state: &SharedState, scraped_url: &Mutex<HashMap<Url, usize>>) -> Checkpoint {
+        let (known_url, waitlist) = state.snapshot();
+        let scraped_url = scraped_url.lock().unwrap().clone();
+
+        Checkpoint {
+            known_url,
+            scraped_url,
+            waitlist,
+        }
+    }
+
+    /// whether a previous run was interrupted and should be resumed
+    pub fn is_resumable(&self) -> bool {
+        !self.waitlist.is_empty()
+    }
+
+    /// start a new round from every scraped URL\
+    /// all `checked` reset to `false`
+    pub fn new_round(&mut self) {
+        for checked in self.known_url.values_mut() {
+            *checked = false;
+        }
+        let mut waitlist: Vec<Url> = self.scraped_url.keys().cloned().collect();
+        waitlist.sort();
+        for url in &waitlist {
+            self.known_url.insert(url.clone(), false);
+        }
+        self.waitlist = waitlist;
+    }
+
+    /// queue `seeds` not queued yet as unchecked
+    pub fn add_seeds(&mut self, seeds: &[Url]) {
+        let mut queued: HashSet<&Url> = self.waitlist.iter().collect();
+        let mut added = Vec::new();
+        for seed in seeds {
+            if queued.insert(seed) {
+                added.push(seed.clone());
+            }
+        }
+        for seed in added {
+            self.known_url.insert(seed.clone(), false);
+            self.waitlist.push(seed);
+        }
+    }
+
+    /// store in `db` without blocking the runtime,
+    /// only what changed since `previous` was stored if any
+    /// # return
+    /// the checkpoint now stored, `previous` if storing failed
+    pub async fn save(
+        self,
+        previous: Option<Checkpoint>,
+        db: &Arc<Mutex<CrawlDb>>,
+    ) -> Option<Checkpoint> {
+        with_db(db, move |db| {
+            let saved = match &previous {
+                Some(previous) => db.save_changes(previous, &self),
+                None => db.save(&self),
+            };
+            match saved {
+                Ok(()) => Some(self),
+                Err(err) => {
+                    println!("Saving checkpoint: {}", err);
+                    previous
+                }
+            }
+        })
+        .await
+    }
+
+    /// write `known_url.txt`, `scraped_url.txt` and `waitlist.txt` in `dir`
+    pub async fn write_text_files(self, dir: &Path) {
+        let Checkpoint {
+            known_url,
+            scraped_url,
+            waitlist,
+        } = self;
+        join!(
+            write_known_url(dir, known_url),
+            write_scraped_url(dir, scraped_url),
+            write_waitlist(dir, waitlist)
+        );
+    }
+}
+
+/// background task writing checkpoints
+pub struct Checkpointer {
+    stop: oneshot::Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl Checkpointer {
+    /// stop after writing a last checkpoint
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        if let Err(err) = self.handle.await {
+            println!("Checkpointer joining handle: {}", err);
+        }
+    }
+}
+
+/// write a checkpoint every `CHECKPOINT_INTERVAL`,
+/// the first in full then only what changed\
+/// write a checkpoint, remove the temporary files of `downloads`
+/// and exit on SIGINT or SIGTERM\
+/// stop the returned `Checkpointer` once the crawl finishes
+pub fn spawn_checkpointer(
+    state: SharedState,
+    scraped_url: Arc<Mutex<HashMap<Url, usize>>>,
+    db: Arc<Mutex<CrawlDb>>,
+    downloads: Arc<Downloads>,
+) -> Checkpointer {
+    let (stop, mut stopped) = oneshot::channel();
+    let handle = spawn(async move {
+        let mut ticker = interval(CHECKPOINT_INTERVAL);
+        ticker.tick().await; // the first tick is immediate
+        let terminate = terminate();
+        pin!(terminate);
+        let mut saved = None;
+        loop {
+            select! {
+                _ = &mut stopped => break,
+                _ = ticker.tick() => {
+                    saved = Checkpoint::take(&state, &scraped_url)
+                        .save(saved, &db)
+                        .await;
+                    println!("Checkpoint saved");
+                }
+                code = &mut terminate => {
+                    Checkpoint::take(&state, &scraped_url)
+                        .save(saved, &db)
+                        .await;
+                    println!("Interrupted, checkpoint saved");
+                    if let Err(e) = downloads.clear().await {
+                        println!("{} clearing {}", e, downloads.dir.display());
+                    }
+                    std::process::exit(code);
+                }
+            }
+        }
+        Checkpoint::take(&state, &scraped_url)
+            .save(saved, &db)
+            .await;
+    });
+
+    Checkpointer { stop, handle }
+}
+
+/// wait for SIGINT or SIGTERM
+/// # return
+/// the exit code for the signal
+#[cfg(unix)]
+async fn terminate() -> i32 {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).unwrap();
+    select! {
+        _ = ctrl_c() => 130,
+        _ = sigterm.recv() => 143,
+    }
+}
+
+/// wait for Ctrl-C
+/// # return
+/// the exit code for the signal
+#[cfg(not(unix))]
+async fn terminate() -> i32 {
+    let _ = ctrl_c().await;
+    130
+}
@@ -0,0 +1,549 @@
+use chrono::{Local, SecondsFormat::Secs};
+use reqwest::{
+    header::{HeaderMap, ETAG, LAST_MODIFIED},
+    Url,
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use scraper_core::content::{sort_clusters, Cluster};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    panic::resume_unwind,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::task::spawn_blocking;
+
+use crate::{
+    checkpoint::Checkpoint,
+    get_existing::{get_known_url, get_scraped_url, get_waitlist},
+};
+
+/// default file name of the crawl-state database
+pub const DB_FILE: &str = "crawl.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS url (
+    url TEXT PRIMARY KEY,
+    checked INTEGER NOT NULL DEFAULT 0,
+    scraped_index INTEGER UNIQUE,
+    fetched_at TEXT,
+    status INTEGER,
+    content_hash TEXT,
+    etag TEXT,
+    last_modified TEXT,
+    aborted TEXT,
+    noindex INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS waitlist (
+    position INTEGER PRIMARY KEY,
+    url TEXT NOT NULL
+);
+";
+
+/// what is known about a URL after fetching it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchRecord {
+    /// RFC 3339 local time of the fetch
+    pub fetched_at: String,
+    /// HTTP status code
+    pub status: u16,
+    /// sha256 of the body, if the body was received
+    pub content_hash: Option<String>,
+    /// why the body was not downloaded in full, see `Aborted`
+    pub aborted: Option<String>,
+    /// the body was stored although its robots directives say `noindex`
+    pub noindex: bool,
+}
+
+/// everything stored about a URL, one line of `export`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UrlRecord {
+    pub url: String,
+    pub checked: bool,
+    pub scraped_index: Option<usize>,
+    pub fetched_at: Option<String>,
+    pub status: Option<u16>,
+    pub content_hash: Option<String>,
+    pub aborted: Option<String>,
+    pub noindex: bool,
+}
+
+/// summary of the crawl state
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrawlStats {
+    pub known: usize,
+    pub checked: usize,
+    pub scraped: usize,
+    pub waiting: usize,
+    pub fetched: usize,
+    /// number of URL by HTTP status of the last fetch, sorted by status
+    pub statuses: Vec<(u16, usize)>,
+    /// distinct bodies fetched
+    pub contents: usize,
+    /// URL whose body another URL also served
+    pub duplicates: usize,
+    /// URL whose last download was aborted
+    pub aborted: usize,
+    /// URL stored although their robots directives say `noindex`
+    pub noindex: usize,
+}
+
+/// one `name: value` each line
+impl fmt::Display for CrawlStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "known URL: {}", self.known)?;
+        writeln!(f, "checked: {}", self.checked)?;
+        writeln!(f, "scraped: {}", self.scraped)?;
+        writeln!(f, "waiting: {}", self.waiting)?;
+        writeln!(f, "fetched: {}", self.fetched)?;
+        for (status, count) in &self.statuses {
+            writeln!(f, "  status {}: {}", status, count)?;
+        }
+        writeln!(f, "distinct bodies: {}", self.contents)?;
+        writeln!(f, "URL with a duplicate body: {}", self.duplicates)?;
+        writeln!(f, "aborted downloads: {}", self.aborted)?;
+        write!(f, "stored noindex pages: {}", self.noindex)
+    }
+}
+
+/// columns added after the first schema, added to older databases on open
+const ADDED_COLUMNS: [(&str, &str); 4] = [
+    ("etag", "TEXT"),
+    ("last_modified", "TEXT"),
+    ("aborted", "TEXT"),
+    ("noindex", "INTEGER NOT NULL DEFAULT 0"),
+];
+
+/// `ETag` and `Last-Modified` of the last stored body of a URL
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    /// read `ETag` and `Last-Modified` from response headers
+    pub fn from_headers(headers: &HeaderMap) -> Validators {
+        let get = |name| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned)
+        };
+        Validators {
+            etag: get(ETAG),
+            last_modified: get(LAST_MODIFIED),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none()
+    }
+}
+
+/// crawl state stored in an SQLite database\
+/// holds known URL, `checked`, scraped index, fetch metadata and waitlist
+pub struct CrawlDb {
+    conn: Connection,
+}
+
+impl CrawlDb {
+    /// open or create the database at `path`
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<CrawlDb> {
+        let conn = Connection::open(path)?;
+        // one fetch record per request, so avoid a full sync each time
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+        conn.execute_batch(SCHEMA)?;
+        add_missing_columns(&conn)?;
+        Ok(CrawlDb { conn })
+    }
+
+    /// open a database in memory
+    pub fn open_in_memory() -> rusqlite::Result<CrawlDb> {
+        let conn = Connection::open_in_memory()?;
+        conn.execute_batch(SCHEMA)?;
+        add_missing_columns(&conn)?;
+        Ok(CrawlDb { conn })
+    }
+
+    /// whether nothing has been stored yet
+    pub fn is_empty(&self) -> rusqlite::Result<bool> {
+        let count: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM url", [], |row| row.get(0))?;
+        Ok(count == 0)
+    }
+
+    /// load the crawl state\
+    /// rows with invalid URL are skipped
+    pub fn load(&self) -> rusqlite::Result<Checkpoint> {
+        let mut checkpoint = Checkpoint::default();
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT url, checked, scraped_index FROM url")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, bool>(1)?,
+                row.get::<_, Option<i64>>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (url, checked, index) = row?;
+            let url = match Url::parse(&url) {
+                Ok(u) => u,
+                Err(e) => {
+                    println!("{} loading {} from database", e, url);
+                    continue;
+                }
+            };
+            if let Some(index) = index {
+                checkpoint.scraped_url.insert(url.clone(), index as usize);
+            }
+            checkpoint.known_url.insert(url, checked);
+        }
+
+        let mut stmt = self
+            .conn
+            .prepare("SELECT url FROM waitlist ORDER BY position")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for row in rows {
+            if let Ok(u) = Url::parse(&row?) {
+                checkpoint.waitlist.push(u);
+            }
+        }
+
+        Ok(checkpoint)
+    }
+
+    /// store the crawl state in one transaction\
+    /// fetch metadata already stored are kept
+    pub fn save(&mut self, checkpoint: &Checkpoint) -> rusqlite::Result<()> {
+        self.store(None, checkpoint)
+    }
+
+    /// store what changed in the crawl state since `previous` was stored,
+    /// in one transaction\
+    /// only the rows of URL whose `checked` or index changed are written,
+    /// the waitlist only if it changed
+    pub fn save_changes(
+        &mut self,
+        previous: &Checkpoint,
+        checkpoint: &Checkpoint,
+    ) -> rusqlite::Result<()> {
+        self.store(Some(previous), checkpoint)
+    }
+
+    /// store `checkpoint`, in full if no `previous`
+    fn store(
+        &mut self,
+        previous: Option<&Checkpoint>,
+        checkpoint: &Checkpoint,
+    ) -> rusqlite::Result<()> {
+        let checked_changed = |url: &Url, checked: &bool| match previous {
+            Some(p) => p.known_url.get(url) != Some(checked),
+            None => true,
+        };
+        let index_changed = |url: &Url| match previous {
+            Some(p) => p.scraped_url.get(url) != checkpoint.scraped_url.get(url),
+            None => true,
+        };
+
+        let tx = self.conn.transaction()?;
+        {
+            // indices may move between URL, so clear them first
+            if previous.is_none() {
+                tx.execute("UPDATE url SET scraped_index = NULL", [])?;
+            } else {
+                let mut clear =
+                    tx.prepare("UPDATE url SET scraped_index = NULL WHERE scraped_index = ?1")?;
+                for (url, index) in &checkpoint.scraped_url {
+                    if index_changed(url) {
+                        clear.execute(params![*index as i64])?;
+                    }
+                }
+            }
+            let mut upsert = tx.prepare(
+                "INSERT INTO url (url, checked, scraped_index) VALUES (?1, ?2, ?3)
+                ON CONFLICT(url) DO UPDATE SET
+                    checked = excluded.checked,
+                    scraped_index = excluded.scraped_index",
+            )?;
+            for (url, checked) in &checkpoint.known_url {
+                if checked_changed(url, checked) || index_changed(url) {
+                    let index = checkpoint.scraped_url.get(url).map(|i| *i as i64);
+                    upsert.execute(params![url.as_str(), checked, index])?;
+                }
+            }
+            // scraped URL not in known_url
+            for (url, index) in &checkpoint.scraped_url {
+                if !checkpoint.known_url.contains_key(url) && index_changed(url) {
+                    upsert.execute(params![url.as_str(), true, *index as i64])?;
+                }
+            }
+
+            let waitlist_changed = match previous {
+                Some(p) => p.waitlist != checkpoint.waitlist,
+                None => true,
+            };
+            if waitlist_changed {
+                tx.execute("DELETE FROM waitlist", [])?;
+                let mut push =
+                    tx.prepare("INSERT INTO waitlist (position, url) VALUES (?1, ?2)")?;
+                for (position, url) in checkpoint.waitlist.iter().enumerate() {
+                    push.execute(params![position as i64, url.as_str()])?;
+                }
+            }
+        }
+        tx.commit()
+    }
+
+    /// record a fetch of `url` at the current time\
+    /// `url` is added as checked if not known\
+    /// the previous `content_hash` is kept if `None`
+    pub fn record_fetch(
+        &self,
+        url: &Url,
+        status: u16,
+        content_hash: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        let fetched_at = Local::now().to_rfc3339_opts(Secs, false);
+        self.conn.execute(
+            "INSERT INTO url (url, checked, fetched_at, status, content_hash)
+            VALUES (?1, 1, ?2, ?3, ?4)
+            ON CONFLICT(url) DO UPDATE SET
+                fetched_at = excluded.fetched_at,
+                status = excluded.status,
+                content_hash = COALESCE(excluded.content_hash, content_hash),
+                aborted = NULL",
+            params![url.as_str(), fetched_at, status, content_hash],
+        )?;
+        Ok(())
+    }
+
+    /// record a fetch of `url` at the current time whose body was aborted
+    /// for `reason`\
+    /// `url` is added as checked if not known, the previous body is kept
+    pub fn record_aborted(&self, url: &Url, status: u16, reason: &str) -> rusqlite::Result<()> {
+        let fetched_at = Local::now().to_rfc3339_opts(Secs, false);
+        self.conn.execute(
+            "INSERT INTO url (url, checked, fetched_at, status, aborted)
+            VALUES (?1, 1, ?2, ?3, ?4)
+            ON CONFLICT(url) DO UPDATE SET
+                fetched_at = excluded.fetched_at,
+                status = excluded.status,
+                aborted = excluded.aborted",
+            params![url.as_str(), fetched_at, status, reason],
+        )?;
+        Ok(())
+    }
+
+    /// mark the body stored for `url` as said `noindex` or not
+    pub fn set_noindex(&self, url: &Url, noindex: bool) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE url SET noindex = ?2 WHERE url = ?1",
+            params![url.as_str(), noindex],
+        )?;
+        Ok(())
+    }
+
+    /// `ETag` and `Last-Modified` stored for `url`
+    pub fn validators(&self, url: &Url) -> rusqlite::Result<Validators> {
+        self.conn
+            .query_row(
+                "SELECT etag, last_modified FROM url WHERE url = ?1",
+                params![url.as_str()],
+                |row| {
+                    Ok(Validators {
+                        etag: row.get(0)?,
+                        last_modified: row.get(1)?,
+                    })
+                },
+            )
+            .optional()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// replace `ETag` and `Last-Modified` stored for `url`
+    pub fn set_validators(&self, url: &Url, validators: &Validators) -> rusqlite::Result<()> {
+        self.conn.execute(
+            "UPDATE url SET etag = ?2, last_modified = ?3 WHERE url = ?1",
+            params![url.as_str(), validators.etag, validators.last_modified],
+        )?;
+        Ok(())
+    }
+
+    /// index of the folder `url` is saved under, if scraped
+    pub fn scraped_index(&self, url: &Url) -> rusqlite::Result<Option<usize>> {
+        let index: Option<Option<i64>> = self
+            .conn
+            .query_row(
+                "SELECT scraped_index FROM url WHERE url = ?1",
+                params![url.as_str()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(index.flatten().map(|i| i as usize))
+    }
+
+    /// the last fetch of `url` if any
+    pub fn fetch_record(&self, url: &Url) -> rusqlite::Result<Option<FetchRecord>> {
+        self.conn
+            .query_row(
+                "SELECT fetched_at, status, content_hash, aborted, noindex FROM url
+                WHERE url = ?1 AND fetched_at IS NOT NULL",
+                params![url.as_str()],
+                |row| {
+                    Ok(FetchRecord {
+                        fetched_at: row.get(0)?,
+                        status: row.get(1)?,
+                        content_hash: row.get(2)?,
+                        aborted: row.get(3)?,
+                        noindex: row.get(4)?,
+                    })
+                },
+            )
+            .optional()
+    }
+
+    /// URL whose last fetched body is the same, see `sort_clusters`
+    pub fn duplicate_clusters(&self) -> rusqlite::Result<Vec<Cluster>> {
+        let mut statement = self.conn.prepare(
+            "SELECT content_hash, url FROM url WHERE content_hash IN (
+                SELECT content_hash FROM url
+                GROUP BY content_hash HAVING COUNT(*) > 1
+            ) ORDER BY url",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+
+        let mut urls: HashMap<String, Vec<Url>> = HashMap::new();
+        for row in rows {
+            let (hash, url) = row?;
+            if let Ok(url) = Url::parse(&url) {
+                urls.entry(hash).or_default().push(url);
+            }
+        }
+        let mut clusters: Vec<Cluster> = urls
+            .into_iter()
+            .map(|(hash, urls)| Cluster { hash, urls })
+            .collect();
+        sort_clusters(&mut clusters);
+        Ok(clusters)
+    }
+
+    /// every URL stored, sorted
+    pub fn records(&self) -> rusqlite::Result<Vec<UrlRecord>> {
+        let mut statement = self.conn.prepare(
+            "SELECT url, checked, scraped_index, fetched_at, status, content_hash, aborted,
+                noindex
+            FROM url ORDER BY url",
+        )?;
+        let rows = statement.query_map([], |row| {
+            Ok(UrlRecord {
+                url: row.get(0)?,
+                checked: row.get(1)?,
+                scraped_index: row.get::<_, Option<i64>>(2)?.map(|i| i as usize),
+                fetched_at: row.get(3)?,
+                status: row.get(4)?,
+                content_hash: row.get(5)?,
+                aborted: row.get(6)?,
+                noindex: row.get(7)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    pub fn stats(&self) -> rusqlite::Result<CrawlStats> {
+        let count = |sql: &str| -> rusqlite::Result<usize> {
+            let count: i64 = self.conn.query_row(sql, [], |row| row.get(0))?;
+            Ok(count as usize)
+        };
+        let mut statement = self.conn.prepare(
+            "SELECT status, COUNT(*) FROM url WHERE status IS NOT NULL
+            GROUP BY status ORDER BY status",
+        )?;
+        let statuses = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? as usize)))?
+            .collect::<rusqlite::Result<_>>()?;
+
+        Ok(CrawlStats {
+            known: count("SELECT COUNT(*) FROM url")?,
+            checked: count("SELECT COUNT(*) FROM url WHERE checked")?,
+            scraped: count("SELECT COUNT(*) FROM url WHERE scraped_index IS NOT NULL")?,
+            waiting: count("SELECT COUNT(*) FROM waitlist")?,
+            fetched: count("SELECT COUNT(*) FROM url WHERE fetched_at IS NOT NULL")?,
+            statuses,
+            contents: count("SELECT COUNT(DISTINCT content_hash) FROM url")?,
+            duplicates: count(
+                "SELECT COUNT(*) FROM url WHERE content_hash IN (
+                    SELECT content_hash FROM url
+                    GROUP BY content_hash HAVING COUNT(*) > 1
+                )",
+            )?,
+            aborted: count("SELECT COUNT(*) FROM url WHERE aborted IS NOT NULL")?,
+            noindex: count("SELECT COUNT(*) FROM url WHERE noindex")?,
+        })
+    }
+}
+
+/// run `f` with `db` on a blocking thread, not on the runtime workers
+pub async fn with_db<T, F>(db: &Arc<Mutex<CrawlDb>>, f: F) -> T
+where
+    F: FnOnce(&mut CrawlDb) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let db = Arc::clone(db);
+    match spawn_blocking(move || f(&mut db.lock().unwrap())).await {
+        Ok(result) => result,
+        Err(err) => resume_unwind(err.into_panic()),
+    }
+}
+
+/// add the columns in `ADDED_COLUMNS` missing from table `url`
+fn add_missing_columns(conn: &Connection) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('url')")?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<String>>>()?;
+
+    for (name, kind) in ADDED_COLUMNS {
+        if !columns.iter().any(|c| c == name) {
+            conn.execute_batch(&format!("ALTER TABLE url ADD COLUMN {} {}", name, kind))?;
+        }
+    }
+    Ok(())
+}
+
+/// import `known_url.txt`, `scraped_url.txt` and `waitlist.txt`
+/// from `dir` into `db`\
+/// missing files are treated as empty
+/// # return
+/// `true` if any file was imported
+pub async fn import_text_files(db: &mut CrawlDb, dir: &Path) -> Result<bool, Box<dyn Error>> {
+    let mut checkpoint = Checkpoint::default();
+    let mut imported = false;
+
+    if dir.join("known_url.txt").exists() {
+        checkpoint.known_url = get_known_url(dir).await;
+        imported = true;
+    }
+    if dir.join("scraped_url.txt").exists() {
+        checkpoint.scraped_url = get_scraped_url(dir).await;
+        imported = true;
+    }
+    if dir.join("waitlist.txt").exists() {
+        checkpoint.waitlist = get_waitlist(dir).await;
+        imported = true;
+    }
+
+    if imported {
+        db.save(&checkpoint)?;
+    }
+    Ok(imported)
+}
@@ -1,66 +1,188 @@
 use chrono::{Local, SecondsFormat::Secs};
 use once_cell::sync::Lazy;
 use reqwest::Url;
-use std::{error::Error, path::Path};
+use std::{
+    error::Error,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 use tokio::{
-    fs::{create_dir_all, File},
+    fs::{create_dir_all, hard_link, metadata, remove_file, rename, File},
     io::{AsyncReadExt, AsyncWriteExt},
+    time::sleep,
 };
 
-static TIME: Lazy<String> = Lazy::new(|| get_time());
+static TIME: Lazy<String> = Lazy::new(get_time);
+
+/// extension of the empty marker saved instead of a snapshot
+/// identical to the last one
+pub const UNCHANGED_EXTENSION: &str = ".unchanged";
 
-pub async fn read_file(filename: &str) -> Result<String, Box<dyn Error>> {
+/// folder in the root holding every distinct body once,
+/// named after its content hash
+pub const OBJECTS_DIR: &str = "objects";
+
+pub async fn read_file<P: AsRef<Path>>(filename: P) -> Result<String, Box<dyn Error>> {
     let mut f = File::open(filename).await?;
     let mut b = String::new();
     f.read_to_string(&mut b).await?;
     Ok(b)
 }
 
-pub async fn write_file(filename: &str, content: &str) -> Result<(), Box<dyn Error>> {
-    let path = Path::new(filename);
+pub async fn write_file<P: AsRef<Path>>(filename: P, content: &str) -> Result<(), Box<dyn Error>> {
+    let path = filename.as_ref();
     create_dir_all(path.parent().unwrap()).await?;
-    let mut f = File::create(filename).await?;
-    f.write_all(&content.as_bytes()).await?;
+    let mut f = File::create(path).await?;
+    f.write_all(content.as_bytes()).await?;
+    // tokio finishes the write in the background unless flushed
+    f.flush().await?;
     Ok(())
 }
 
-pub async fn write_file_bytes(filename: &str, content: &[u8]) -> Result<(), Box<dyn Error>> {
-    let path = Path::new(filename);
+pub async fn write_file_bytes<P: AsRef<Path>>(
+    filename: P,
+    content: &[u8],
+) -> Result<(), Box<dyn Error>> {
+    let path = filename.as_ref();
     create_dir_all(path.parent().unwrap()).await?;
-    let mut f = File::create(filename).await?;
+    let mut f = File::create(path).await?;
     f.write_all(content).await?;
+    f.flush().await?;
     Ok(())
 }
 
-/// save to file under folder named after `index`\
-/// named time and date
+/// write to `filename.tmp` then rename it to `filename`\
+/// so `filename` is never left half written
+pub async fn replace_file(filename: &Path, content: &str) -> Result<(), Box<dyn Error>> {
+    let mut tmp = filename.as_os_str().to_owned();
+    tmp.push(".tmp");
+    write_file(&tmp, content).await?;
+    rename(&tmp, filename).await?;
+    Ok(())
+}
+
+/// content of a snapshot
+#[derive(Debug, Clone, Copy)]
+pub enum Content<'a> {
+    /// stored in `OBJECTS_DIR`, hard linked into place
+    Link(&'a Path),
+    Bytes(&'a [u8]),
+}
+
+impl Content<'_> {
+    /// replaces the snapshot saved as `filename` earlier in this run if any,
+    /// without writing through its link to the stored body
+    async fn put(self, filename: &Path) -> Result<(), Box<dyn Error>> {
+        match remove_file(filename).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        match self {
+            Content::Link(object) => {
+                create_dir_all(filename.parent().unwrap()).await?;
+                hard_link(object, filename).await?;
+                Ok(())
+            }
+            Content::Bytes(bytes) => write_file_bytes(filename, bytes).await,
+        }
+    }
+}
+
+/// move the body downloaded to `path` into `OBJECTS_DIR` in `root`,
+/// named `hash` and `file_extention`\
+/// dropped if a body with that hash is stored already
+/// # return
+/// path of the stored body
+pub async fn store_object(
+    root: &Path,
+    hash: &str,
+    file_extention: &str,
+    path: &Path,
+) -> Result<PathBuf, String> {
+    let object = root
+        .join(OBJECTS_DIR)
+        .join(hash.to_owned() + file_extention);
+    let stored = match metadata(&object).await {
+        Ok(_) => remove_file(path).await,
+        _ => match create_dir_all(object.parent().unwrap()).await {
+            Ok(()) => rename(path, &object).await,
+            Err(e) => Err(e),
+        },
+    };
+    match stored {
+        Ok(()) => Ok(object),
+        Err(e) => Err(format!("{} storing {}", e, object.display())),
+    }
+}
+
+/// attempts to save each snapshot, see `save_attempts` and `save_retry_secs`
+#[derive(Debug, Clone, Copy)]
+pub struct SaveRetry {
+    pub attempts: usize,
+    /// pause between two attempts
+    pub delay: Duration,
+}
+
+/// save to file under folder named after `index` in `root`\
+/// named time and date, tried `retry.attempts` times
+/// # return
+/// path of the file
 pub async fn save_file(
+    root: &Path,
     scraped: bool,
     url: &Url,
     index: usize,
     file_extention: &str,
-    content: &[u8],
-) -> Result<(), String> {
-    let filename = index.to_string() + "/" + &TIME + file_extention;
-    for _ in 0..=2 {
-        match write_file_bytes(&filename, content).await {
-            Ok(()) => {
-                if scraped {
-                    return Ok(());
+    content: Content<'_>,
+    retry: SaveRetry,
+) -> Result<PathBuf, String> {
+    let dir = root.join(index.to_string());
+    let filename = dir.join(TIME.to_owned() + file_extention);
+    let mut saved = false;
+    for attempt in 0..retry.attempts {
+        if attempt > 0 {
+            sleep(retry.delay).await;
+        }
+        if !saved {
+            match content.put(&filename).await {
+                Ok(()) => saved = true,
+                Err(e) => {
+                    println!("{} saving {}", e, filename.display());
+                    continue;
                 }
             }
-            Err(e) => {
-                println!("{} saving {}", e, filename);
-                continue;
-            }
         }
-        let url_txt = index.to_string() + "/url.txt";
-        match write_file(&url_txt, url.as_str()).await {
-            Ok(()) => return Ok(()),
-            Err(e) => println!("{} writing url.txt for {}", e, filename),
+        if scraped {
+            return Ok(filename);
+        }
+        match write_file(dir.join("url.txt"), url.as_str()).await {
+            Ok(()) => return Ok(filename),
+            Err(e) => println!("{} writing url.txt for {}", e, filename.display()),
         }
     }
-    Err(format!("failed to save {}", filename))
+    Err(format!("failed to save {}", filename.display()))
+}
+
+/// mark the URL under folder named after `index` in `root` as unchanged in this run\
+/// saves an empty `TIME.unchanged` instead of another copy of the snapshot
+pub async fn save_unchanged(
+    root: &Path,
+    url: &Url,
+    index: usize,
+    retry: SaveRetry,
+) -> Result<(), String> {
+    save_file(
+        root,
+        true,
+        url,
+        index,
+        UNCHANGED_EXTENSION,
+        Content::Bytes(&[]),
+        retry,
+    )
+    .await
+    .map(drop)
 }
 
 fn get_time() -> String {
@@ -1,46 +1,15 @@
 use crate::file_dealer::read_file;
-use regex::Regex;
 use reqwest::Url;
-use std::collections::HashMap;
+use std::{collections::HashMap, path::Path};
 
-/// get blacklist from blacklist.txt
-pub async fn get_blacklist() -> Regex {
-    let r;
-    loop {
-        match read_file("blacklist.txt").await {
-            Ok(re) => {
-                r = re;
-                break;
-            }
-            Err(e) => println!("{} getting blacklist", e),
-        }
-    }
-    Regex::new(&r).unwrap()
-}
-
-/// get whitelist from whitelist.txt
-pub async fn get_whitelist() -> Regex {
-    let r;
-    loop {
-        match read_file("whitelist.txt").await {
-            Ok(re) => {
-                r = re;
-                break;
-            }
-            Err(e) => println!("{} getting whitelist", e),
-        }
-    }
-    Regex::new(&r).unwrap()
-}
-
-/// get the map of known URL from file `known_url.txt`\
-/// the file must contain `URL` each line\
-/// the URL must be valid\
-/// all bool `checked` initialized as `false`
-pub async fn get_known_url() -> HashMap<Url, bool> {
+/// get the map of known URL from file `known_url.txt` in `dir`\
+/// the file must contain `URL` or `URL<checked` each line\
+/// invalid URL are skipped\
+/// `checked` is `false` if missing
+pub async fn get_known_url(dir: &Path) -> HashMap<Url, bool> {
     let s;
     loop {
-        match read_file("known_url.txt").await {
+        match read_file(dir.join("known_url.txt")).await {
             Ok(str) => {
                 s = str;
                 break;
@@ -51,21 +20,41 @@ pub async fn get_known_url() -> HashMap<Url, bool> {
 
     let mut m = HashMap::new();
     for line in s.split_whitespace() {
-        if let Ok(u) = Url::parse(line) {
-            m.insert(u, false); // push URL
+        let (url, checked) = match line.rsplit_once('<') {
+            Some((url, checked)) => (url, checked == "true"),
+            None => (line, false),
+        };
+        if let Ok(u) = Url::parse(url) {
+            m.insert(u, checked); // push URL
         }
     }
 
     m
 }
 
-/// get the map of scraped URL from file `scraped_url.txt`\
+/// get the waitlist left by an interrupted run from file `waitlist.txt` in `dir`\
+/// the file must contain `URL` each line\
+/// empty if the file does not exist
+pub async fn get_waitlist(dir: &Path) -> Vec<Url> {
+    match read_file(dir.join("waitlist.txt")).await {
+        Ok(s) => s
+            .split_whitespace()
+            .filter_map(|line| Url::parse(line).ok())
+            .collect(),
+        Err(e) => {
+            println!("{} getting waitlist, starting a new round", e);
+            Vec::new()
+        }
+    }
+}
+
+/// get the map of scraped URL from file `scraped_url.txt` in `dir`\
 /// the file must contain `url<index` each line\
-/// the URL must be valid; `index` ≥ 0
-pub async fn get_scraped_url() -> HashMap<Url, usize> {
+/// lines with invalid URL or `index` are skipped
+pub async fn get_scraped_url(dir: &Path) -> HashMap<Url, usize> {
     let s;
     loop {
-        match read_file("scraped_url.txt").await {
+        match read_file(dir.join("scraped_url.txt")).await {
             Ok(str) => {
                 s = str;
                 break;
@@ -76,14 +65,19 @@ pub async fn get_scraped_url() -> HashMap<Url, usize> {
 
     let mut m = HashMap::new();
     for line in s.split_whitespace() {
-        let mut ele = line.split("<");
-        let url;
-        match ele.next() {
-            Some(u) => url = Url::parse(u).unwrap(),
-            None => continue,
+        let (url, index) = match line.rsplit_once('<') {
+            Some((u, i)) => (Url::parse(u), i.parse::<usize>()),
+            None => {
+                println!("missing index in scraped_url: {}", line);
+                continue;
+            }
+        };
+        match (url, index) {
+            (Ok(url), Ok(index)) => {
+                m.insert(url, index);
+            }
+            _ => println!("malformed scraped_url: {}", line),
         }
-        let index: usize = ele.next().unwrap().parse().unwrap();
-        m.insert(url, index);
     }
 
     m
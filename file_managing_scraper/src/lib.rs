@@ -1,8 +1,15 @@
+pub mod checkpoint;
+pub mod db;
 pub mod file_dealer;
 pub mod get_existing;
+pub mod plugins;
 pub mod scrape;
-pub mod scraper;
+pub mod search_api;
+pub mod search_index;
+pub mod snapshot_diff;
 pub mod write_new;
 
+#[cfg(test)]
+mod mock_site;
 #[cfg(test)]
 mod tests;
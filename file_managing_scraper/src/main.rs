@@ -1,51 +1,427 @@
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use file_managing_scraper::{
-    get_existing::{get_blacklist, get_known_url, get_scraped_url, get_whitelist},
+    db::{import_text_files, CrawlDb, DB_FILE},
     scrape::scrape,
-    write_new::{write_known_url, write_scraped_url},
+    search_api::{serve, SearchApi, DEFAULT_ADDR},
+    search_index::{collect_docs, SearchFilter, SearchIndex, INDEX_DIR},
+    snapshot_diff::{diff_runs, list_runs, scan_pages},
 };
-use tokio::spawn;
+use image_scraper::save_all_image;
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use scraper_core::{
+    cli::{exit_with, Workdir},
+    config::Config,
+    content::report,
+    seen::SeenBackend,
+};
+use std::{
+    error::Error,
+    fs::File,
+    io::{stdout, BufWriter, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::task::spawn_blocking;
+
+/// crawl a site into a working directory, then inspect and search what was saved
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// holding the crawl database and search index too
+    #[command(flatten)]
+    workdir: Workdir,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// start a new round from the seeds and every URL scraped before
+    Crawl {
+        /// URL to start from, replacing `seeds` of the config
+        seeds: Vec<String>,
+        #[command(flatten)]
+        settings: CrawlSettings,
+    },
+    /// continue the crawl interrupted last
+    Resume {
+        #[command(flatten)]
+        settings: CrawlSettings,
+    },
+    /// write everything stored about each URL
+    Export {
+        #[arg(short, long, value_enum, default_value_t = Format::Jsonl)]
+        format: Format,
+        /// file to write JSON lines to [default: stdout]
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// summarize the crawl state
+    Stats,
+    /// save the images of pages as their sha256
+    Images {
+        /// pages to scan [default: `seeds` of the config]
+        urls: Vec<String>,
+    },
+    /// print the status and headers of URL without following redirects
+    Headers {
+        #[arg(required = true)]
+        urls: Vec<String>,
+    },
+    /// print which rules allow or reject URL, robots.txt aside
+    Explain {
+        #[arg(required = true)]
+        urls: Vec<Url>,
+    },
+    /// print the best matches in the search index
+    Search {
+        query: String,
+        /// number of matches
+        #[arg(short = 'n', long, default_value_t = 10)]
+        limit: usize,
+    },
+    /// index the latest snapshot of every URL and the files of the mappings given
+    Index {
+        /// `fixed_concurrent_scraper` output listing `"URL": "FILE"` each line
+        mappings: Vec<PathBuf>,
+    },
+    /// rebuild the search index like `index`, then serve the search API over HTTP
+    Serve {
+        /// address to listen on
+        #[arg(short, long, default_value = DEFAULT_ADDR)]
+        addr: String,
+        /// `fixed_concurrent_scraper` output listing `"URL": "FILE"` each line
+        mappings: Vec<PathBuf>,
+    },
+    /// print the pages changed between two runs as JSON lines, or the runs found
+    Diff {
+        #[arg(requires = "new")]
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// print the URL whose last fetched body is the same
+    Duplicates,
+}
+
+/// settings replacing those of the config for one crawl
+#[derive(Debug, Args)]
+struct CrawlSettings {
+    /// number of workers
+    #[arg(short = 'j', long)]
+    concurrency: Option<usize>,
+    /// seconds between two requests to a host, replacing every other crawl delay
+    #[arg(long)]
+    crawl_delay: Option<f64>,
+    /// seen set: exact, bloom or bloom:FALSE_POSITIVE_RATE, bloom cannot resume
+    #[arg(long)]
+    seen: Option<String>,
+    /// near-duplicate detection: MAX_DISTANCE[:FAMILY_LIMIT]
+    #[arg(long)]
+    near_dup: Option<String>,
+}
+
+impl CrawlSettings {
+    fn apply(self, config: &mut Config) {
+        if let Some(concurrency) = self.concurrency {
+            config.concurrency = concurrency;
+        }
+        if let Some(crawl_delay) = self.crawl_delay {
+            config.crawl_delay_secs = Some(crawl_delay);
+        }
+        if let Some(seen) = self.seen {
+            config.seen = seen;
+        }
+        if let Some(near_dup) = self.near_dup {
+            config.near_dup = Some(near_dup);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    /// one JSON object each URL
+    Jsonl,
+    /// `known_url.txt`, `scraped_url.txt` and `waitlist.txt` in the workdir
+    Text,
+}
 
 #[tokio::main]
 async fn main() {
-    let process_num: usize;
-    let blacklist;
-    let whitelist;
-    let known_url;
-    let scraped_url;
+    let cli = Cli::parse();
+    let workdir = &cli.workdir;
+    let mut config = workdir.load_config().unwrap_or_else(|e| exit_with(&e));
 
-    // get existing data
+    let done = match cli.command {
+        Command::Crawl { seeds, settings } => {
+            if !seeds.is_empty() {
+                config.seeds = seeds;
+            }
+            settings.apply(&mut config);
+            crawl(config, workdir, false).await
+        }
+        Command::Resume { settings } => {
+            settings.apply(&mut config);
+            crawl(config, workdir, true).await
+        }
+        Command::Export { format, output } => export(workdir, format, output).await,
+        Command::Stats => stats(workdir),
+        Command::Images { urls } => {
+            if !urls.is_empty() {
+                config.seeds = urls;
+            }
+            images(config).await
+        }
+        Command::Headers { urls } => headers(&config, &urls).await,
+        Command::Explain { urls } => explain(config, workdir, &urls),
+        Command::Search { query, limit } => search(workdir, &query, limit),
+        Command::Index { mappings } => index(&config, workdir, &mappings).map(drop),
+        Command::Serve { addr, mappings } => serve_search(&config, workdir, &addr, &mappings).await,
+        Command::Diff { old, new } => diff(&config, old.zip(new)),
+        Command::Duplicates => duplicates(workdir),
+    };
+    if let Err(e) = done {
+        exit_with(&e.to_string());
+    }
+}
+
+/// the crawl-state database of the workdir, which must exist
+fn open_db(workdir: &Workdir) -> Result<CrawlDb, Box<dyn Error>> {
+    let path = workdir.join(DB_FILE);
+    if !path.exists() {
+        return Err(format!("no {} in the workdir, run `crawl` first", DB_FILE).into());
+    }
+    Ok(CrawlDb::open(path)?)
+}
+
+/// crawl with `config`, storing the state in `DB_FILE` of the workdir\
+/// continue the waitlist left if `resume`,
+/// otherwise start a new round from the scraped URL and the seeds
+async fn crawl(mut config: Config, workdir: &Workdir, resume: bool) -> Result<(), Box<dyn Error>> {
+    add_legacy_rules(&mut config, workdir);
+    config.validate()?;
+
+    // open the crawl-state database
+    let mut db = CrawlDb::open(workdir.join(DB_FILE))?;
+    if db.is_empty()? {
+        // carry over a crawl directory from before the database
+        match import_text_files(&mut db, &workdir.dir).await {
+            Ok(true) => println!("Imported known_url.txt, scraped_url.txt and waitlist.txt"),
+            Ok(false) => {}
+            Err(e) => println!("{} importing text files", e),
+        }
+    }
+    let mut checkpoint = db.load()?;
+    if resume {
+        if !checkpoint.is_resumable() {
+            return Err("no interrupted crawl to resume, start one with `crawl`".into());
+        }
+        // its checkpoints lack the URL checked, those would be crawled again
+        if let SeenBackend::Bloom { .. } = config.seen_backend() {
+            return Err("cannot resume with the bloom seen set, set `seen = \"exact\"`".into());
+        }
+    } else {
+        if checkpoint.is_resumable() {
+            println!(
+                "Dropping {} URL waiting from the interrupted crawl",
+                checkpoint.waitlist.len()
+            );
+        }
+        checkpoint.new_round();
+        checkpoint.add_seeds(&config.seed_urls());
+        if checkpoint.waitlist.is_empty() {
+            return Err("nothing to crawl, give seed URL or set `seeds` in the config".into());
+        }
+    }
+
+    // scrape new data, the state is written as it goes
+    scrape(&config, checkpoint, Arc::new(Mutex::new(db))).await?;
+    Ok(())
+}
+
+/// use the rule files of a crawl directory from before the config file,
+/// if the config has no rule
+fn add_legacy_rules(config: &mut Config, workdir: &Workdir) {
+    let (exclude, include) = (workdir.join("blacklist.txt"), workdir.join("whitelist.txt"));
+    if config.include.is_empty()
+        && config.exclude.is_empty()
+        && config.include_files.is_empty()
+        && config.exclude_files.is_empty()
+        && exclude.exists()
+        && include.exists()
     {
-        let process_num_handle = spawn(async {
-            let mut args = std::env::args();
-            args.next();
-            args.next().unwrap().parse().unwrap()
-        }); // get args[1]: number of process
-        let blacklist_handle = spawn(async { get_blacklist().await });
-        let whitelist_handle = spawn(async { get_whitelist().await });
-        let known_url_handle = spawn(async { get_known_url().await });
-        let scraped_url_handle = spawn(async { get_scraped_url().await });
-
-        process_num = process_num_handle.await.unwrap();
-        blacklist = blacklist_handle.await.unwrap();
-        whitelist = whitelist_handle.await.unwrap();
-        known_url = known_url_handle.await.unwrap();
-        scraped_url = scraped_url_handle.await.unwrap();
-    }
-
-    // println!("blacklist:\n{}\n\nwhitelist:\n{}", blacklist, whitelist); //DEBUG
-
-    // scrape new data
-    let (known_url, scraped_url) =
-        scrape(process_num, blacklist, whitelist, known_url, scraped_url).await;
-
-    // write new data
-    let write_new_url_handle = spawn(async move {
-        write_known_url(known_url).await;
-    });
-    let write_scraped_url_handle = spawn(async move {
-        write_scraped_url(scraped_url).await;
-    });
-
-    write_new_url_handle.await.unwrap();
-    write_scraped_url_handle.await.unwrap();
+        config.exclude_files = vec![exclude];
+        config.include_files = vec![include];
+    }
+}
+
+async fn export(
+    workdir: &Workdir,
+    format: Format,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let db = open_db(workdir)?;
+    match format {
+        Format::Jsonl => {
+            let mut out: Box<dyn Write> = match output {
+                Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+                None => Box::new(stdout().lock()),
+            };
+            for record in db.records()? {
+                writeln!(out, "{}", serde_json::to_string(&record)?)?;
+            }
+            out.flush()?;
+        }
+        Format::Text => {
+            if output.is_some() {
+                return Err("--output is only for JSON lines".into());
+            }
+            db.load()?.write_text_files(&workdir.dir).await;
+        }
+    }
+    Ok(())
+}
+
+fn stats(workdir: &Workdir) -> Result<(), Box<dyn Error>> {
+    let stats = open_db(workdir)?.stats()?;
+    println!("{}", stats);
+    Ok(())
+}
+
+/// save the images of every seed of `config` in `output_dir`\
+/// print `{image URL}:{sha256}` of each
+async fn images(config: Config) -> Result<(), Box<dyn Error>> {
+    config.validate()?;
+    if config.seeds.is_empty() {
+        return Err("no page to scan, give URL or set `seeds` in the config".into());
+    }
+    // `save_all_image` starts its own runtime
+    let records = spawn_blocking(move || {
+        let mut records = String::new();
+        for url in &config.seeds {
+            match save_all_image(url, &config) {
+                Ok(record) => records.push_str(&record),
+                Err(e) => eprintln!("{} scanning {}", e, url),
+            }
+        }
+        records
+    })
+    .await?;
+    print!("{}", records);
+    Ok(())
+}
+
+async fn headers(config: &Config, urls: &[String]) -> Result<(), Box<dyn Error>> {
+    config.validate()?;
+    let client = config.client_builder().redirect(Policy::none()).build()?;
+    for url in urls {
+        let response = match client.get(url).send().await {
+            Ok(response) => response,
+            Err(e) => {
+                eprintln!("{}: {}\n", url, e);
+                continue;
+            }
+        };
+        println!("{}\n{:?} {}", url, response.version(), response.status());
+        for (name, value) in response.headers() {
+            println!("{}: {}", name, String::from_utf8_lossy(value.as_bytes()));
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// print whether each of `urls` is crawled once canonicalized
+/// and the rules matching it
+fn explain(mut config: Config, workdir: &Workdir, urls: &[Url]) -> Result<(), Box<dyn Error>> {
+    add_legacy_rules(&mut config, workdir);
+    config.validate()?;
+    let canonicalizer = config.canonicalizer();
+    let filter = config.host_rules(config.rule_filter()?);
+    for url in urls {
+        let canonical = canonicalizer.canonicalize(url);
+        println!("{}", url);
+        if canonical != *url {
+            println!("canonical {}", canonical);
+        }
+        let mut explanation = filter.inner.explain(&canonical);
+        explanation
+            .excluded_by
+            .extend(filter.excluded_by(&canonical));
+        println!("{}\n", explanation);
+    }
+    Ok(())
+}
+
+/// print URL, title and highlighted snippet of the best matches of a query
+fn search(workdir: &Workdir, query: &str, limit: usize) -> Result<(), Box<dyn Error>> {
+    let results = SearchIndex::open_or_create(workdir.join(INDEX_DIR))?.search(
+        query,
+        &SearchFilter::default(),
+        limit,
+        0,
+    )?;
+    for hit in results.hits {
+        println!("{:.3} {}\n  {}", hit.score, hit.url, hit.title);
+        println!(
+            "  {}\n",
+            hit.highlight("\x1b[1m", "\x1b[0m").replace('\n', " ")
+        );
+    }
+    Ok(())
+}
+
+/// rebuild the search index from the snapshots under `output_dir` and `mappings`
+fn index(
+    config: &Config,
+    workdir: &Workdir,
+    mappings: &[PathBuf],
+) -> Result<SearchIndex, Box<dyn Error>> {
+    let docs = collect_docs(&config.output_dir, mappings)?;
+    let index = SearchIndex::open_or_create(workdir.join(INDEX_DIR))?;
+    let count = index.rebuild(docs)?;
+    println!("Indexed {} pages into {}", count, INDEX_DIR);
+    Ok(index)
+}
+
+/// serve the search API on `addr` with a new index
+async fn serve_search(
+    config: &Config,
+    workdir: &Workdir,
+    addr: &str,
+    mappings: &[PathBuf],
+) -> Result<(), Box<dyn Error>> {
+    let db = open_db(workdir)?;
+    let index = index(config, workdir, mappings)?;
+    let api = Arc::new(SearchApi::new(index, config.output_dir.clone(), db));
+    let listener = TcpListener::bind(addr)?;
+    println!("Serving on http://{}", listener.local_addr()?);
+    serve(api, listener).await?;
+    Ok(())
+}
+
+fn diff(config: &Config, runs: Option<(String, String)>) -> Result<(), Box<dyn Error>> {
+    let pages = scan_pages(&config.output_dir)?;
+    match runs {
+        Some((old, new)) => {
+            for page_diff in diff_runs(&pages, &old, &new)? {
+                println!("{}", serde_json::to_string(&page_diff)?);
+            }
+        }
+        None => {
+            eprintln!("Runs found:");
+            for run in list_runs(&pages) {
+                eprintln!("{}", run);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn duplicates(workdir: &Workdir) -> Result<(), Box<dyn Error>> {
+    let clusters = open_db(workdir)?.duplicate_clusters()?;
+    print!("{}", report(&clusters));
+    eprintln!("{} clusters of identical pages", clusters.len());
+    Ok(())
 }
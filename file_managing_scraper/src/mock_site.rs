@@ -0,0 +1,163 @@
+use hyper::{
+    header::{CONTENT_TYPE, LOCATION, RETRY_AFTER},
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use reqwest::Url;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    net::TcpListener,
+    sync::{Arc, Mutex},
+};
+use tokio::spawn;
+
+/// one response of the mock site
+#[derive(Debug, Clone)]
+pub enum MockPage {
+    /// 200 with `body`, without `Content-Type` if `content_type` is `None`
+    Ok {
+        content_type: Option<&'static str>,
+        body: Vec<u8>,
+    },
+    /// redirection with `status` to `location`
+    Redirect { status: u16, location: &'static str },
+    /// `status` with an empty body
+    Status(u16),
+    /// `status` with `Retry-After: retry_after` if set the first `times` requests,
+    /// then `page`
+    Flaky {
+        status: u16,
+        retry_after: Option<&'static str>,
+        times: usize,
+        page: Box<MockPage>,
+    },
+    /// `page` with the header `name: value` added
+    Header {
+        name: &'static str,
+        value: &'static str,
+        page: Box<MockPage>,
+    },
+}
+
+impl MockPage {
+    /// `text/html` linking to every `href` with `<a>` and every `src` with `<img>`
+    pub fn html(hrefs: &[&str], srcs: &[&str]) -> MockPage {
+        let mut body = String::from("<html><head><title>mock</title></head><body>");
+        for href in hrefs {
+            body.push_str(&format!("<a href=\"{}\">{}</a>", href, href));
+        }
+        for src in srcs {
+            body.push_str(&format!("<img src=\"{}\">", src));
+        }
+        body.push_str("</body></html>");
+        MockPage::ok("text/html", body.as_bytes())
+    }
+
+    /// 200 with `content_type` and `body`
+    pub fn ok(content_type: &'static str, body: &[u8]) -> MockPage {
+        MockPage::Ok {
+            content_type: Some(content_type),
+            body: body.to_vec(),
+        }
+    }
+
+    /// response to the request after `served` earlier ones to the same path
+    fn response(&self, served: usize) -> Response<Body> {
+        let mut response = Response::new(Body::empty());
+        match self {
+            MockPage::Ok { content_type, body } => {
+                if let Some(content_type) = content_type {
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_TYPE, content_type.parse().unwrap());
+                }
+                *response.body_mut() = Body::from(body.to_owned());
+            }
+            MockPage::Redirect { status, location } => {
+                *response.status_mut() = StatusCode::from_u16(*status).unwrap();
+                response
+                    .headers_mut()
+                    .insert(LOCATION, location.parse().unwrap());
+            }
+            MockPage::Status(status) => {
+                *response.status_mut() = StatusCode::from_u16(*status).unwrap();
+            }
+            MockPage::Flaky {
+                status,
+                retry_after,
+                times,
+                page,
+            } => {
+                if served >= *times {
+                    return page.response(served - times);
+                }
+                *response.status_mut() = StatusCode::from_u16(*status).unwrap();
+                if let Some(retry_after) = retry_after {
+                    response
+                        .headers_mut()
+                        .insert(RETRY_AFTER, retry_after.parse().unwrap());
+                }
+            }
+            MockPage::Header { name, value, page } => {
+                response = page.response(served);
+                response.headers_mut().append(*name, value.parse().unwrap());
+            }
+        }
+        response
+    }
+}
+
+/// website served on localhost for crawl tests\
+/// records every path requested
+pub struct MockSite {
+    pub base: Url,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl MockSite {
+    /// serve `pages` by path on a free localhost port until the runtime stops\
+    /// paths not in `pages` are 404
+    pub fn start(pages: HashMap<&'static str, MockPage>) -> MockSite {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+        let pages = Arc::new(pages);
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let requests_clone = Arc::clone(&requests);
+        let make_service = make_service_fn(move |_| {
+            let pages = Arc::clone(&pages);
+            let requests = Arc::clone(&requests_clone);
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                    let path = request.uri().path().to_owned();
+                    let served = {
+                        let mut requests = requests.lock().unwrap();
+                        let served = requests.iter().filter(|p| **p == path).count();
+                        requests.push(path.to_owned());
+                        served
+                    };
+                    let response = match pages.get(path.as_str()) {
+                        Some(page) => page.response(served),
+                        None => MockPage::Status(404).response(served),
+                    };
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+        let server = Server::from_tcp(listener).unwrap().serve(make_service);
+        spawn(server);
+
+        MockSite { base, requests }
+    }
+
+    /// absolute URL of `path` on this site
+    pub fn url(&self, path: &str) -> Url {
+        self.base.join(path).unwrap()
+    }
+
+    /// every path requested so far, in order
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
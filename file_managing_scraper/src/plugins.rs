@@ -0,0 +1,210 @@
+use async_trait::async_trait;
+use reqwest::{
+    header::{IF_MODIFIED_SINCE, IF_NONE_MATCH},
+    Client, Response, StatusCode, Url,
+};
+use scraper_core::{
+    download::Aborted,
+    fetcher::Fetcher,
+    sink::{text_path, Fetched, StorageSink},
+};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    db::{with_db, CrawlDb, Validators},
+    file_dealer::{save_file, save_unchanged, store_object, write_file, Content, SaveRetry},
+};
+
+/// GET with `client`, conditional if the URL was scraped before
+pub struct ConditionalFetcher {
+    pub client: Client,
+    pub db: Arc<Mutex<CrawlDb>>,
+    pub scraped_url: Arc<Mutex<HashMap<Url, usize>>>,
+}
+
+impl ConditionalFetcher {
+    /// `ETag` and `Last-Modified` of the last snapshot of `url`\
+    /// empty if `url` was never scraped
+    async fn previous_validators(&self, url: &Url) -> Validators {
+        if !self.scraped_url.lock().unwrap().contains_key(url) {
+            return Validators::default();
+        }
+        let url = url.clone();
+        with_db(&self.db, move |db| match db.validators(&url) {
+            Ok(validators) => validators,
+            Err(err) => {
+                println!("Validators: {} | {}", err, url);
+                Validators::default()
+            }
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl Fetcher for ConditionalFetcher {
+    async fn fetch(&self, url: &Url) -> reqwest::Result<Response> {
+        let mut request = self.client.get(url.clone());
+        let previous = self.previous_validators(url).await;
+        if let Some(etag) = previous.etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(last_modified) = previous.last_modified {
+            request = request.header(IF_MODIFIED_SINCE, last_modified);
+        }
+        request.send().await
+    }
+}
+
+/// save snapshots under `root` as `index/time.ext`,
+/// hard links to their body stored once as `objects/hash.ext`,
+/// their text if any as `index/time.ext.text`\
+/// and record every fetch in `db`
+pub struct SnapshotSink {
+    /// folder the snapshots are saved under
+    pub root: PathBuf,
+    pub retry: SaveRetry,
+    pub db: Arc<Mutex<CrawlDb>>,
+    pub scraped_url: Arc<Mutex<HashMap<Url, usize>>>,
+}
+
+impl SnapshotSink {
+    /// record the fetch of `url` in the database\
+    /// with `content_hash`, `validators` and `noindex` if the body was received
+    /// # return
+    /// `true` if the body has the same hash as the last fetch
+    async fn record_fetch(
+        &self,
+        url: &Url,
+        status: u16,
+        content_hash: Option<&str>,
+        validators: Validators,
+        noindex: bool,
+    ) -> bool {
+        let url = url.clone();
+        let content_hash = content_hash.map(str::to_owned);
+        with_db(&self.db, move |db| {
+            let content_hash = content_hash.as_deref();
+            let previous_hash = match db.fetch_record(&url) {
+                Ok(record) => record.and_then(|r| r.content_hash),
+                Err(_) => None,
+            };
+            let mut result = db.record_fetch(&url, status, content_hash);
+            if result.is_ok() && content_hash.is_some() {
+                result = db
+                    .set_validators(&url, &validators)
+                    .and_then(|()| db.set_noindex(&url, noindex));
+            }
+            if let Err(err) = result {
+                println!("Record fetch: {} | {}", err, url);
+            }
+
+            content_hash.is_some() && content_hash == previous_hash.as_deref()
+        })
+        .await
+    }
+
+    /// add `url` to scraped_url
+    /// # return
+    /// if it has been scraped 'scraped'\
+    /// its place in scraped_url as its newly obtained `index`
+    fn record_scraped(&self, url: &Url) -> (bool, usize) {
+        // obtain the scraped_url lock
+        let mut scraped_url = self.scraped_url.lock().unwrap();
+
+        if let Some(index) = scraped_url.get(url) {
+            return (true, *index); // URL scraped before
+        }
+
+        let index = scraped_url.len();
+        scraped_url.insert(url.clone(), index);
+
+        (false, index) // new URL
+    } // scraped_url lock drop
+}
+
+#[async_trait]
+impl StorageSink for SnapshotSink {
+    async fn save(&self, page: &Fetched<'_>) -> Result<(), String> {
+        let validators = Validators::from_headers(page.headers);
+        let unchanged = self
+            .record_fetch(
+                page.final_url,
+                page.status.as_u16(),
+                Some(&page.body.content_hash),
+                validators,
+                page.noindex,
+            )
+            .await;
+
+        // record URL as scraped
+        let (scraped, index) = self.record_scraped(page.final_url);
+
+        // same body as the last snapshot, keep it
+        if scraped && unchanged {
+            return save_unchanged(&self.root, page.final_url, index, self.retry)
+                .await
+                .map_err(|e| e.to_string());
+        }
+
+        // the body is stored once, each snapshot of it is a hard link
+        let object = store_object(
+            &self.root,
+            &page.body.content_hash,
+            page.extension,
+            &page.body.path,
+        )
+        .await?;
+        let path = save_file(
+            &self.root,
+            scraped,
+            page.final_url,
+            index,
+            page.extension,
+            Content::Link(&object),
+            self.retry,
+        )
+        .await?;
+        if let Some(text) = page.text {
+            write_file(text_path(&path), text)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// unchanged since the last snapshot, keep it
+    async fn not_modified(&self, url: &Url) -> Result<(), String> {
+        self.record_fetch(
+            url,
+            StatusCode::NOT_MODIFIED.as_u16(),
+            None,
+            Validators::default(),
+            false,
+        )
+        .await;
+        let (_, index) = self.record_scraped(url);
+        save_unchanged(&self.root, url, index, self.retry)
+            .await
+            .map_err(|e| e.to_string())
+    }
+
+    async fn failed(&self, url: &Url, status: StatusCode) {
+        self.record_fetch(url, status.as_u16(), None, Validators::default(), false)
+            .await;
+    }
+
+    async fn aborted(&self, url: &Url, status: StatusCode, reason: Aborted) {
+        let url = url.clone();
+        with_db(&self.db, move |db| {
+            if let Err(err) = db.record_aborted(&url, status.as_u16(), &reason.to_string()) {
+                println!("Record aborted: {} | {}", err, url);
+            }
+        })
+        .await;
+    }
+}
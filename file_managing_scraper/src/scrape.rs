@@ -1,80 +1,144 @@
 use hhmmss::Hhmmss;
-use regex::Regex;
-use reqwest::Url;
-use std::{
-    collections::{HashMap, VecDeque},
-    sync::{Arc, Mutex},
+use image_scraper::ImageSink;
+use scraper_core::{
+    config::Config,
+    crawler::{crawl_parallel, Plugins, SharedState},
+    filter::Polite,
+    frontier::ShardedFrontier,
+    links::AllLinks,
+    near_dup::NearDuplicates,
+    seen::SeenSet,
 };
-use tokio::{spawn, time::Instant};
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
 
-use crate::scraper::CrawlerParallel;
+use crate::{
+    checkpoint::{spawn_checkpointer, Checkpoint},
+    db::CrawlDb,
+    file_dealer::SaveRetry,
+    plugins::{ConditionalFetcher, SnapshotSink},
+};
 
+/// scrape with the settings of `config`\
+/// resume from the waitlist of `checkpoint` if any,
+/// otherwise start a new round from the scraped URL\
+/// checkpoints, the last once finished, and fetch records are stored in `db`,
+/// snapshots under `output_dir`
+/// # return
+/// the last checkpoint, or the problem building the client or the rules
 pub async fn scrape(
-    process_num: usize,
-    blacklist: Regex,
-    whitelist: Regex,
-    known_url: HashMap<Url, bool>,
-    scraped_url: HashMap<Url, usize>,
-) -> (HashMap<Url, bool>, HashMap<Url, usize>) {
+    config: &Config,
+    mut checkpoint: Checkpoint,
+    db: Arc<Mutex<CrawlDb>>,
+) -> Result<Checkpoint, String> {
     let start_time = Instant::now();
+    let client = config.crawler_client().map_err(|e| e.to_string())?;
+    let robots = Arc::new(config.robots_cache().map_err(|e| e.to_string())?);
+    let rules = config.rule_filter()?;
 
-    let link_waitlist: VecDeque<Url> = scraped_url.keys().map(|u| Url::clone(u)).collect();
-    let known_url = Arc::new(Mutex::new(known_url));
-    let link_waitlist = Arc::new(Mutex::new(link_waitlist));
-    let scraped_url = Arc::new(Mutex::new(scraped_url));
-
-    // spawn `process_num` async processes
-    let mut handles = Vec::new();
-    let active_process_count = 0usize;
-    let active_process_count = Arc::new(Mutex::new(active_process_count));
+    if checkpoint.is_resumable() {
+        println!("Resuming with {} URL waiting", checkpoint.waitlist.len());
+    } else {
+        checkpoint.new_round();
+    }
+    let Checkpoint {
+        known_url,
+        scraped_url,
+        waitlist,
+    } = checkpoint;
 
-    for process_id in 0..process_num {
-        let link_waitlist_clone = Arc::clone(&link_waitlist);
-        let known_url_clone = Arc::clone(&known_url);
-        let active_process_count_clone = Arc::clone(&active_process_count);
-        let scraped_url_clone = Arc::clone(&scraped_url);
-        let blacklist_clone = blacklist.clone();
-        let whitelist_clone = whitelist.clone();
+    let filter = config.link_kinds(config.host_rules(Polite {
+        inner: rules,
+        robots,
+    }));
+    let link_waitlist = ShardedFrontier::new(config.crawl_delay());
+    for url in waitlist {
+        link_waitlist.push_back(url);
+    }
 
-        handles.push(spawn(async move {
-            // create a crawler and crawl
-            let mut crawler = CrawlerParallel::new(
-                link_waitlist_clone,
-                known_url_clone,
-                active_process_count_clone,
-                scraped_url_clone,
-                process_id,
-                blacklist_clone,
-                whitelist_clone,
-            )
-            .unwrap();
-            crawler.crawl().await;
+    let seen = config.seen_backend();
+    let state = SharedState::new(SeenSet::new(seen, known_url), link_waitlist);
+    let scraped_url = Arc::new(Mutex::new(scraped_url));
 
-            crawler.processed_count // return the processed count
-        }));
+    // bodies left half downloaded by an interrupted crawl
+    let downloads = Arc::new(config.downloads());
+    if let Err(e) = downloads.clear().await {
+        println!("{} clearing {}", e, downloads.dir.display());
     }
 
-    // count total URL processed successfully
-    let mut total_processed_count = 0usize;
-    for handle in handles {
-        match handle.await {
-            Ok(processed_count) => {
-                total_processed_count += processed_count;
-            }
-            Err(err) => {
-                println!("Master joining handle: {}", err)
-            }
-        }
-    }
+    // save the crawl state periodically and on interruption
+    let checkpointer = spawn_checkpointer(
+        state.clone(),
+        Arc::clone(&scraped_url),
+        Arc::clone(&db),
+        Arc::clone(&downloads),
+    );
+
+    let plugins = Plugins {
+        fetcher: Arc::new(ConditionalFetcher {
+            client,
+            db: Arc::clone(&db),
+            scraped_url: Arc::clone(&scraped_url),
+        }),
+        extractor: Arc::new(AllLinks),
+        filter: Arc::new(filter),
+        sink: Arc::new(SnapshotSink {
+            root: config.output_dir.clone(),
+            retry: SaveRetry {
+                attempts: config.save_attempts,
+                delay: config.save_retry_delay(),
+            },
+            db,
+            scraped_url: Arc::clone(&scraped_url),
+        }),
+        canonicalizer: Arc::new(config.canonicalizer()),
+        near_duplicates: config
+            .near_dup_config()
+            .map(|near_dup| Arc::new(NearDuplicates::new(near_dup))),
+        retry: config.retry_policy(),
+        downloads,
+        routes: Arc::new(config.routes()),
+        images: Some(Arc::new(ImageSink {
+            dir: config.output_dir.join("images"),
+        })),
+        robots_meta: config.robots_meta().map(Arc::new),
+    };
+    let total_processed_count = crawl_parallel(config.concurrency, &state, &plugins).await;
 
     let used_time = start_time.elapsed().hhmmssxxx();
     println!(
         "Finished scraping {} in {}",
         total_processed_count, used_time
     );
+    for (host, waited) in state.link_waitlist.wait_times() {
+        println!("Waited {} for {}", waited.hhmmssxxx(), host);
+    }
+    println!(
+        "Seen {} URL using about {:.1} MiB ({} set)",
+        state.known_url.len(),
+        state.known_url.memory_bytes() as f64 / (1024.0 * 1024.0),
+        seen
+    );
+    println!(
+        "Fetched {} distinct bodies, {} served by several URL",
+        state.content.len(),
+        state.content.clusters().len()
+    );
+    let budget = &plugins.downloads.budget;
+    println!("Downloaded {} bytes", budget.used());
+    if budget.is_exhausted() {
+        println!("Stopped with the byte budget exhausted, resume to go on");
+    }
+    if let Some(near_duplicates) = &plugins.near_duplicates {
+        for (family, count) in near_duplicates.exhausted_families() {
+            println!(
+                "Stopped following {} after {} near-duplicates",
+                family, count
+            );
+        }
+    }
 
-    let known_url = known_url.lock().unwrap().to_owned();
-    let scraped_url = scraped_url.lock().unwrap().to_owned();
+    checkpointer.stop().await;
 
-    (known_url, scraped_url)
+    Ok(Checkpoint::take(&state, &scraped_url))
 }
@@ -0,0 +1,254 @@
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use reqwest::Url;
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    convert::Infallible,
+    fs::read,
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use tokio::task::spawn_blocking;
+use url::form_urlencoded;
+
+use crate::{
+    db::CrawlDb,
+    search_index::{content_type_of, SearchFilter, SearchIndex},
+    snapshot_diff::PageHistory,
+};
+
+/// default address of the search server, localhost only
+pub const DEFAULT_ADDR: &str = "127.0.0.1:8080";
+
+/// hits per page if `size` is not given
+pub const DEFAULT_PAGE_SIZE: usize = 10;
+
+/// largest `size` allowed
+pub const MAX_PAGE_SIZE: usize = 100;
+
+/// a response, or the status and message of an error
+type Reply = Result<Response<Body>, (StatusCode, String)>;
+
+/// one hit in the `/search` response
+#[derive(Debug, Serialize)]
+struct HitJson {
+    score: f32,
+    url: String,
+    title: String,
+    host: String,
+    content_type: String,
+    /// HTML with matched terms in `<b>`
+    snippet: String,
+}
+
+/// HTTP API over the search index and stored snapshots
+/// - `GET /search?q=QUERY[&page=N][&size=N][&host=HOST][&path=PREFIX][&type=PREFIX]`
+/// - `GET /page?url=URL[&raw]`
+pub struct SearchApi {
+    index: SearchIndex,
+    /// folder the snapshots are saved under
+    root: PathBuf,
+    db: Mutex<CrawlDb>,
+}
+
+impl SearchApi {
+    pub fn new(index: SearchIndex, root: PathBuf, db: CrawlDb) -> SearchApi {
+        SearchApi {
+            index,
+            root,
+            db: Mutex::new(db),
+        }
+    }
+
+    /// answer one request\
+    /// blocks on the index and the database
+    pub fn respond(&self, method: &Method, path: &str, query: Option<&str>) -> Response<Body> {
+        let params: HashMap<String, String> =
+            form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect();
+        let reply = match (method, path) {
+            (&Method::GET, "/search") => self.search(&params),
+            (&Method::GET, "/page") => self.page(&params),
+            (_, "/search" | "/page") => Err((
+                StatusCode::METHOD_NOT_ALLOWED,
+                "only GET is supported".to_owned(),
+            )),
+            _ => Err((StatusCode::NOT_FOUND, format!("no endpoint {}", path))),
+        };
+        reply.unwrap_or_else(|(status, message)| json_response(status, json!({ "error": message })))
+    }
+
+    /// best matches of `q`, `size` each `page`
+    fn search(&self, params: &HashMap<String, String>) -> Reply {
+        let query = params
+            .get("q")
+            .ok_or_else(|| bad_request("missing parameter q"))?;
+        let page = number(params, "page", 1)?.max(1);
+        let size = number(params, "size", DEFAULT_PAGE_SIZE)?.clamp(1, MAX_PAGE_SIZE);
+        let filter = SearchFilter {
+            host: params.get("host").cloned(),
+            path_prefix: params.get("path").cloned(),
+            content_type: params.get("type").cloned(),
+        };
+
+        let results = self
+            .index
+            .search(query, &filter, size, (page - 1) * size)
+            .map_err(|e| bad_request(&e.to_string()))?;
+        let hits: Vec<HitJson> = results
+            .hits
+            .iter()
+            .map(|hit| HitJson {
+                score: hit.score,
+                url: hit.url.to_owned(),
+                title: hit.title.to_owned(),
+                host: hit.host.to_owned(),
+                content_type: hit.content_type.to_owned(),
+                snippet: hit.highlight_html(),
+            })
+            .collect();
+
+        Ok(json_response(
+            StatusCode::OK,
+            json!({
+                "query": query,
+                "page": page,
+                "size": size,
+                "total": results.total,
+                "hits": hits,
+            }),
+        ))
+    }
+
+    /// latest snapshot of `url` and what is known about it\
+    /// the snapshot itself with `raw`
+    fn page(&self, params: &HashMap<String, String>) -> Reply {
+        let url = params
+            .get("url")
+            .ok_or_else(|| bad_request("missing parameter url"))?;
+        let url = Url::parse(url).map_err(|e| bad_request(&format!("{} parsing url", e)))?;
+
+        let (index, record, validators) = {
+            let db = self.db.lock().unwrap();
+            let lookup = || {
+                Ok((
+                    db.scraped_index(&url)?,
+                    db.fetch_record(&url)?,
+                    db.validators(&url)?,
+                ))
+            };
+            lookup().map_err(|e: rusqlite::Error| internal_error(&e.to_string()))?
+        }; // db unlock
+        let not_found = || (StatusCode::NOT_FOUND, format!("{} not scraped", url));
+        let index = index.ok_or_else(not_found)?;
+        let history = PageHistory::load(&self.root, index)
+            .map_err(|e| internal_error(&e.to_string()))?
+            .ok_or_else(not_found)?;
+        let snapshot = history.latest().ok_or_else(not_found)?;
+        let bytes = read(snapshot).map_err(|e| internal_error(&e.to_string()))?;
+        let content_type = content_type_of(snapshot);
+
+        if params.contains_key("raw") {
+            return Response::builder()
+                .header(CONTENT_TYPE, content_type)
+                .body(Body::from(bytes))
+                .map_err(|e| internal_error(&e.to_string()));
+        }
+
+        let saved_at = history
+            .snapshots
+            .iter()
+            .find(|(_, path)| path == snapshot)
+            .map(|(time, _)| time.to_owned());
+        let content = content_type
+            .starts_with("text/")
+            .then(|| String::from_utf8_lossy(&bytes).into_owned());
+        Ok(json_response(
+            StatusCode::OK,
+            json!({
+                "url": history.url,
+                "index": index,
+                "snapshot": snapshot.to_string_lossy(),
+                "saved_at": saved_at,
+                "content_type": content_type,
+                "size": bytes.len(),
+                "fetched_at": record.as_ref().map(|r| &r.fetched_at),
+                "status": record.as_ref().map(|r| r.status),
+                "content_hash": record.as_ref().and_then(|r| r.content_hash.as_ref()),
+                "aborted": record.as_ref().and_then(|r| r.aborted.as_ref()),
+                "noindex": matches!(&record, Some(r) if r.noindex),
+                "etag": validators.etag,
+                "last_modified": validators.last_modified,
+                "content": content,
+            }),
+        ))
+    }
+}
+
+fn json_response(status: StatusCode, value: serde_json::Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(value.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+fn bad_request(message: &str) -> (StatusCode, String) {
+    (StatusCode::BAD_REQUEST, message.to_owned())
+}
+
+fn internal_error(message: &str) -> (StatusCode, String) {
+    (StatusCode::INTERNAL_SERVER_ERROR, message.to_owned())
+}
+
+/// parameter `name` as a number, `default` if not given
+fn number(
+    params: &HashMap<String, String>,
+    name: &str,
+    default: usize,
+) -> Result<usize, (StatusCode, String)> {
+    match params.get(name) {
+        None => Ok(default),
+        Some(n) => n
+            .parse()
+            .map_err(|_| bad_request(&format!("{} is not a number", name))),
+    }
+}
+
+/// serve `api` on `listener` until the process exits
+pub async fn serve(api: Arc<SearchApi>, listener: TcpListener) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let api = Arc::clone(&api);
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let api = Arc::clone(&api);
+                async move {
+                    let response = spawn_blocking(move || {
+                        api.respond(
+                            request.method(),
+                            request.uri().path(),
+                            request.uri().query(),
+                        )
+                    })
+                    .await
+                    .unwrap_or_else(|e| {
+                        json_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            json!({ "error": e.to_string() }),
+                        )
+                    });
+                    Ok::<_, Infallible>(response)
+                }
+            }))
+        }
+    });
+    Server::from_tcp(listener)?.serve(make_service).await
+}
@@ -0,0 +1,381 @@
+use reqwest::Url;
+use scraper_core::{
+    extract::{headings, text_of, title},
+    mime::extension_mime,
+    sink::text_path,
+};
+use select::document::Document;
+use std::{
+    error::Error,
+    fs::{create_dir_all, read, read_to_string},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+use tantivy::{
+    collector::{Count, TopDocs},
+    directory::MmapDirectory,
+    doc,
+    query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, RegexQuery, TermQuery},
+    schema::{Field, IndexRecordOption, Schema, Value, STORED, STRING, TEXT},
+    snippet::SnippetGenerator,
+    Index, IndexReader, IndexWriter, TantivyDocument, Term,
+};
+
+use crate::snapshot_diff::scan_pages;
+
+/// default folder of the search index
+pub const INDEX_DIR: &str = "search_index";
+
+/// memory for the index writer: 50 MB
+const WRITER_MEMORY: usize = 50_000_000;
+
+/// content type of a stored snapshot, guessed from its extension
+pub fn content_type_of(path: &Path) -> &'static str {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .and_then(extension_mime)
+        .unwrap_or("application/octet-stream")
+}
+
+/// one stored snapshot ready to be indexed\
+/// title, headings and body are empty if not HTML
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageDoc {
+    pub url: String,
+    pub host: String,
+    pub path: String,
+    pub content_type: String,
+    pub title: String,
+    pub headings: String,
+    pub body: String,
+    /// path of the snapshot file
+    pub snapshot: String,
+}
+
+impl PageDoc {
+    /// a document with URL metadata only
+    fn new(url: &str, snapshot: &Path) -> PageDoc {
+        let parsed = Url::parse(url).ok();
+        PageDoc {
+            url: url.to_owned(),
+            host: parsed
+                .as_ref()
+                .and_then(|u| u.host_str())
+                .unwrap_or_default()
+                .to_owned(),
+            path: parsed.map(|u| u.path().to_owned()).unwrap_or_default(),
+            content_type: content_type_of(snapshot).to_owned(),
+            title: String::new(),
+            headings: String::new(),
+            body: String::new(),
+            snapshot: snapshot.to_string_lossy().into_owned(),
+        }
+    }
+
+    /// extract title, headings and body text from `html`
+    pub fn from_html(url: &str, html: &str, snapshot: &Path) -> PageDoc {
+        let document = Document::from(html);
+        PageDoc {
+            title: title(&document),
+            headings: headings(&document),
+            body: text_of(&document),
+            content_type: "text/html".to_owned(),
+            ..PageDoc::new(url, snapshot)
+        }
+    }
+
+    /// read and extract the snapshot at `path`\
+    /// only HTML is read, other files are indexed by URL
+    /// and the text extracted from them if saved
+    pub fn read(url: &str, path: &Path) -> Result<PageDoc, Box<dyn Error>> {
+        if content_type_of(path) != "text/html" {
+            return Ok(PageDoc {
+                body: read_to_string(text_path(path)).unwrap_or_default(),
+                ..PageDoc::new(url, path)
+            });
+        }
+        let html = String::from_utf8_lossy(&read(path)?).into_owned();
+        Ok(PageDoc::from_html(url, &html, path))
+    }
+}
+
+/// the latest snapshot of every URL in the `index/TIME.ext` layout under `root`
+pub fn snapshots_in_index_layout(root: &Path) -> Result<Vec<(String, PathBuf)>, Box<dyn Error>> {
+    let mut snapshots = Vec::new();
+    for page in scan_pages(root)? {
+        if let Some(path) = page.latest() {
+            snapshots.push((page.url.to_owned(), path.to_owned()));
+        }
+    }
+    Ok(snapshots)
+}
+
+/// the snapshots listed in the output of `fixed_concurrent_scraper`\
+/// `mapping` holds `"final_url": "path"` each line,
+/// `path` relative to the folder of `mapping`
+pub fn snapshots_in_mapping(mapping: &Path) -> Result<Vec<(String, PathBuf)>, Box<dyn Error>> {
+    let folder = mapping.parent().unwrap_or_else(|| Path::new("."));
+    let mut snapshots = Vec::new();
+    for line in read_to_string(mapping)?.lines() {
+        let (url, path) = match line.trim().split_once("\": \"") {
+            Some(pair) => pair,
+            None => continue,
+        };
+        let url = url.trim_start_matches('"');
+        let path = path.trim_end_matches('"');
+        snapshots.push((url.to_owned(), folder.join(path)));
+    }
+    Ok(snapshots)
+}
+
+/// read every snapshot in the layout under `root` and listed in `mappings`\
+/// unreadable snapshots are skipped
+pub fn collect_docs(root: &Path, mappings: &[PathBuf]) -> Result<Vec<PageDoc>, Box<dyn Error>> {
+    let mut snapshots = snapshots_in_index_layout(root)?;
+    for mapping in mappings {
+        match snapshots_in_mapping(mapping) {
+            Ok(s) => snapshots.extend(s),
+            Err(e) => println!("{} reading {}", e, mapping.display()),
+        }
+    }
+
+    let mut docs = Vec::new();
+    for (url, path) in snapshots {
+        match PageDoc::read(&url, &path) {
+            Ok(doc) => docs.push(doc),
+            Err(e) => println!("{} reading {}", e, path.display()),
+        }
+    }
+    Ok(docs)
+}
+
+/// restrict a search, every field set must match
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchFilter {
+    /// exact host, e.g. `dukekunshan.edu.cn`
+    pub host: Option<String>,
+    /// start of the URL path, e.g. `/news/`
+    pub path_prefix: Option<String>,
+    /// start of the content type, e.g. `image/` or `text/html`
+    pub content_type: Option<String>,
+}
+
+/// one search result
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub score: f32,
+    pub url: String,
+    pub title: String,
+    pub host: String,
+    pub content_type: String,
+    pub snapshot: String,
+    /// part of the body around the matched terms
+    pub fragment: String,
+    /// byte ranges in `fragment` of the matched terms
+    pub highlighted: Vec<Range<usize>>,
+}
+
+impl SearchHit {
+    /// `fragment` with every matched term wrapped in `before` and `after`
+    pub fn highlight(&self, before: &str, after: &str) -> String {
+        self.join_highlighted(before, after, str::to_owned)
+    }
+
+    /// `fragment` escaped for HTML with every matched term in `<b>`
+    pub fn highlight_html(&self) -> String {
+        self.join_highlighted("<b>", "</b>", escape_html)
+    }
+
+    fn join_highlighted(&self, before: &str, after: &str, escape: fn(&str) -> String) -> String {
+        let mut snippet = String::new();
+        let mut start = 0;
+        for range in &self.highlighted {
+            snippet.push_str(&escape(&self.fragment[start..range.start]));
+            snippet.push_str(before);
+            snippet.push_str(&escape(&self.fragment[range.clone()]));
+            snippet.push_str(after);
+            start = range.end;
+        }
+        snippet.push_str(&escape(&self.fragment[start..]));
+        snippet
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// one page of search results
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResults {
+    /// number of documents matching in total
+    pub total: usize,
+    pub hits: Vec<SearchHit>,
+}
+
+#[derive(Clone, Copy)]
+struct Fields {
+    url: Field,
+    /// the URL tokenized, so files are found by name
+    url_text: Field,
+    host: Field,
+    path: Field,
+    content_type: Field,
+    title: Field,
+    headings: Field,
+    body: Field,
+    snapshot: Field,
+}
+
+/// full-text index of stored snapshots, ranked by BM25
+pub struct SearchIndex {
+    index: Index,
+    reader: IndexReader,
+    fields: Fields,
+}
+
+impl SearchIndex {
+    fn schema() -> (Schema, Fields) {
+        let mut builder = Schema::builder();
+        let fields = Fields {
+            url: builder.add_text_field("url", STRING | STORED),
+            url_text: builder.add_text_field("url_text", TEXT),
+            host: builder.add_text_field("host", STRING | STORED),
+            path: builder.add_text_field("path", STRING),
+            content_type: builder.add_text_field("content_type", STRING | STORED),
+            title: builder.add_text_field("title", TEXT | STORED),
+            headings: builder.add_text_field("headings", TEXT),
+            body: builder.add_text_field("body", TEXT | STORED),
+            snapshot: builder.add_text_field("snapshot", STORED),
+        };
+        (builder.build(), fields)
+    }
+
+    fn from_index(index: Index, fields: Fields) -> tantivy::Result<SearchIndex> {
+        let reader = index.reader()?;
+        Ok(SearchIndex {
+            index,
+            reader,
+            fields,
+        })
+    }
+
+    /// open the index in folder `dir`, create it if none
+    pub fn open_or_create<P: AsRef<Path>>(dir: P) -> tantivy::Result<SearchIndex> {
+        create_dir_all(&dir)?;
+        let (schema, fields) = SearchIndex::schema();
+        let directory = MmapDirectory::open(dir)?;
+        SearchIndex::from_index(Index::open_or_create(directory, schema)?, fields)
+    }
+
+    /// create an index in memory
+    pub fn create_in_ram() -> tantivy::Result<SearchIndex> {
+        let (schema, fields) = SearchIndex::schema();
+        SearchIndex::from_index(Index::create_in_ram(schema), fields)
+    }
+
+    /// replace everything in the index with `docs`
+    /// # return
+    /// number of documents indexed
+    pub fn rebuild(&self, docs: impl IntoIterator<Item = PageDoc>) -> tantivy::Result<usize> {
+        let mut writer: IndexWriter = self.index.writer(WRITER_MEMORY)?;
+        writer.delete_all_documents()?;
+        let f = self.fields;
+        let mut count = 0;
+        for page in docs {
+            writer.add_document(doc!(
+                f.url => page.url.as_str(),
+                f.url_text => page.url,
+                f.host => page.host,
+                f.path => page.path,
+                f.content_type => page.content_type,
+                f.title => page.title,
+                f.headings => page.headings,
+                f.body => page.body,
+                f.snapshot => page.snapshot,
+            ))?;
+            count += 1;
+        }
+        writer.commit()?;
+        self.reader.reload()?;
+        Ok(count)
+    }
+
+    /// search title, headings, body and URL for `query`
+    /// among the documents passing `filter`\
+    /// title matches weigh double, an empty `query` matches everything
+    /// # return
+    /// at most `limit` hits after skipping `offset`, best first
+    pub fn search(
+        &self,
+        query: &str,
+        filter: &SearchFilter,
+        limit: usize,
+        offset: usize,
+    ) -> Result<SearchResults, Box<dyn Error>> {
+        let f = self.fields;
+        let searcher = self.reader.searcher();
+
+        let text_query: Box<dyn Query> = if query.trim().is_empty() {
+            Box::new(AllQuery)
+        } else {
+            let mut parser =
+                QueryParser::for_index(&self.index, vec![f.title, f.headings, f.body, f.url_text]);
+            parser.set_field_boost(f.title, 2.0);
+            parser.parse_query(query)?
+        };
+        let mut clauses = vec![(Occur::Must, text_query.box_clone())];
+        if let Some(host) = &filter.host {
+            let term = Term::from_field_text(f.host, &host.to_ascii_lowercase());
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+            ));
+        }
+        for (field, prefix) in [
+            (f.path, &filter.path_prefix),
+            (f.content_type, &filter.content_type),
+        ] {
+            if let Some(prefix) = prefix {
+                let pattern = format!("{}.*", regex::escape(prefix));
+                clauses.push((
+                    Occur::Must,
+                    Box::new(RegexQuery::from_pattern(&pattern, field)?),
+                ));
+            }
+        }
+        let query = BooleanQuery::new(clauses);
+
+        let (top_docs, total) = searcher.search(
+            &query,
+            &(TopDocs::with_limit(limit).and_offset(offset), Count),
+        )?;
+        let snippets = SnippetGenerator::create(&searcher, &*text_query, f.body)?;
+
+        let mut hits = Vec::new();
+        for (score, address) in top_docs {
+            let doc: TantivyDocument = searcher.doc(address)?;
+            let get = |field| {
+                doc.get_first(field)
+                    .and_then(|v| v.as_str())
+                    .unwrap_or_default()
+                    .to_owned()
+            };
+            let snippet = snippets.snippet_from_doc(&doc);
+            hits.push(SearchHit {
+                score,
+                url: get(f.url),
+                title: get(f.title),
+                host: get(f.host),
+                content_type: get(f.content_type),
+                snapshot: get(f.snapshot),
+                fragment: snippet.fragment().to_owned(),
+                highlighted: snippet.highlighted().to_vec(),
+            });
+        }
+        Ok(SearchResults { total, hits })
+    }
+}
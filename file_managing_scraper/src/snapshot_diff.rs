@@ -0,0 +1,176 @@
+use scraper_core::{extract::visible_text, sink::TEXT_SUFFIX};
+use serde::Serialize;
+use similar::TextDiff;
+use std::{
+    error::Error,
+    fs::{read, read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+use crate::file_dealer::UNCHANGED_EXTENSION;
+
+/// stored history of one URL under folder `index`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageHistory {
+    pub index: usize,
+    pub url: String,
+    /// `(time, path)` of every snapshot or unchanged marker, sorted by time
+    pub snapshots: Vec<(String, PathBuf)>,
+}
+
+impl PageHistory {
+    /// the snapshot holding the content seen in `run`\
+    /// follows unchanged markers back to the last real snapshot
+    /// # return
+    /// `None` if the URL was not saved in `run`
+    pub fn content_at(&self, run: &str) -> Option<&Path> {
+        let at = self.snapshots.iter().position(|(time, _)| time == run)?;
+        self.snapshots[..=at]
+            .iter()
+            .rev()
+            .map(|(_, path)| path.as_path())
+            .find(|path| !is_unchanged_marker(path))
+    }
+
+    /// the snapshot holding the content seen last
+    pub fn latest(&self) -> Option<&Path> {
+        let (run, _) = self.snapshots.last()?;
+        self.content_at(run)
+    }
+
+    /// read the history saved in folder `index` under `root`
+    /// # return
+    /// `None` if the folder has no `url.txt`
+    pub fn load(root: &Path, index: usize) -> Result<Option<PageHistory>, Box<dyn Error>> {
+        let dir = root.join(index.to_string());
+        let url = match read_to_string(dir.join("url.txt")) {
+            Ok(u) => u.trim().to_owned(),
+            Err(_) => return Ok(None),
+        };
+
+        let mut snapshots = Vec::new();
+        for file in read_dir(&dir)? {
+            let path = file?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(n) if n != "url.txt" && !n.ends_with(TEXT_SUFFIX) => n,
+                _ => continue,
+            };
+            // `TIME.ext`, TIME has no `.` in it
+            let time = name.split('.').next().unwrap_or_default().to_owned();
+            snapshots.push((time, path));
+        }
+        snapshots.sort();
+
+        Ok(Some(PageHistory {
+            index,
+            url,
+            snapshots,
+        }))
+    }
+}
+
+/// kind of change of a page between two runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Change {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// one line of the diff output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PageDiff {
+    pub change: Change,
+    pub url: String,
+    pub index: usize,
+    /// unified diff of the visible text, for changed HTML only
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+fn is_unchanged_marker(path: &Path) -> bool {
+    path.to_string_lossy().ends_with(UNCHANGED_EXTENSION)
+}
+
+/// read the history of every URL saved under `root`\
+/// folders that are not a number or have no `url.txt` are skipped
+pub fn scan_pages(root: &Path) -> Result<Vec<PageHistory>, Box<dyn Error>> {
+    let mut pages = Vec::new();
+    for entry in read_dir(root)? {
+        let dir = entry?.path();
+        let index = match dir.file_name().and_then(|n| n.to_str()?.parse().ok()) {
+            Some(i) if dir.is_dir() => i,
+            _ => continue,
+        };
+        if let Some(page) = PageHistory::load(root, index)? {
+            pages.push(page);
+        }
+    }
+    pages.sort_by_key(|p| p.index);
+    Ok(pages)
+}
+
+/// every run time found in `pages`, sorted
+pub fn list_runs(pages: &[PageHistory]) -> Vec<String> {
+    let mut runs: Vec<String> = pages
+        .iter()
+        .flat_map(|p| p.snapshots.iter().map(|(time, _)| time.to_owned()))
+        .collect();
+    runs.sort();
+    runs.dedup();
+    runs
+}
+
+/// compare the pages saved in run `old` and run `new`
+/// # return
+/// added, removed and changed pages, by index
+pub fn diff_runs(
+    pages: &[PageHistory],
+    old: &str,
+    new: &str,
+) -> Result<Vec<PageDiff>, Box<dyn Error>> {
+    let mut diffs = Vec::new();
+    for page in pages {
+        let page_diff = |change, diff| PageDiff {
+            change,
+            url: page.url.to_owned(),
+            index: page.index,
+            diff,
+        };
+        match (page.content_at(old), page.content_at(new)) {
+            (None, Some(_)) => diffs.push(page_diff(Change::Added, None)),
+            (Some(_), None) => diffs.push(page_diff(Change::Removed, None)),
+            (Some(old_path), Some(new_path)) if old_path != new_path => {
+                let old_bytes = read(old_path)?;
+                let new_bytes = read(new_path)?;
+                if old_bytes == new_bytes {
+                    continue;
+                }
+                let diff = if is_html(new_path) {
+                    let old_text = visible_text(&String::from_utf8_lossy(&old_bytes));
+                    let new_text = visible_text(&String::from_utf8_lossy(&new_bytes));
+                    if old_text == new_text {
+                        // only markup changed
+                        continue;
+                    }
+                    Some(
+                        TextDiff::from_lines(&old_text, &new_text)
+                            .unified_diff()
+                            .header(old, new)
+                            .to_string(),
+                    )
+                } else {
+                    None
+                };
+                diffs.push(page_diff(Change::Changed, diff));
+            }
+            _ => {}
+        }
+    }
+    Ok(diffs)
+}
+
+fn is_html(path: &Path) -> bool {
+    matches!(path.extension(), Some(ext) if ext == "html")
+}
@@ -1,54 +1,1367 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    env::temp_dir,
+    fs::{create_dir_all, read, read_dir, read_to_string, remove_dir_all, write},
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-use reqwest::Url;
+use reqwest::{
+    header::{HeaderMap, HeaderValue, ETAG},
+    Url,
+};
+use scraper_core::{
+    config::{Config, HostConfig},
+    crawler::SharedState,
+    extract::visible_text,
+    frontier::{Pop, ShardedFrontier},
+    seen::{SeenBackend, SeenSet},
+};
+use tokio::time::Instant;
 
-use crate::{file_dealer::write_file_bytes, get_existing::*, write_new::*};
+use crate::{
+    checkpoint::Checkpoint,
+    db::{CrawlDb, CrawlStats, Validators},
+    file_dealer::{save_file, write_file_bytes, Content, SaveRetry, OBJECTS_DIR},
+    get_existing::*,
+    mock_site::{MockPage, MockSite},
+    scrape::scrape,
+    search_api::{serve, SearchApi},
+    search_index::{
+        collect_docs, content_type_of, snapshots_in_mapping, PageDoc, SearchFilter, SearchIndex,
+    },
+    snapshot_diff::{diff_runs, list_runs, scan_pages, Change, PageHistory},
+    write_new::*,
+};
 
-#[tokio::main]
-#[test]
+/// a new empty directory named after the test, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = temp_dir().join(format!("{}_{}", name, process::id()));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.0);
+    }
+}
+
+/// crawl `site` only, saving to `root`
+fn site_config(site: &MockSite, root: &Path) -> Config {
+    Config {
+        include: vec![format!("^{}", regex::escape(site.base.as_str()))],
+        output_dir: root.to_owned(),
+        ..Config::default()
+    }
+}
+
+/// a first round starting from `seeds`
+fn seeded(seeds: &[Url]) -> Checkpoint {
+    let mut checkpoint = Checkpoint::default();
+    checkpoint.add_seeds(seeds);
+    checkpoint
+}
+
+fn memory_db() -> Arc<Mutex<CrawlDb>> {
+    Arc::new(Mutex::new(CrawlDb::open_in_memory().unwrap()))
+}
+
+fn dku(path: &str) -> Url {
+    Url::parse("https://dukekunshan.edu.cn/")
+        .unwrap()
+        .join(path)
+        .unwrap()
+}
+
+#[tokio::test]
 async fn test_get_existing() {
-    println!(
-        "blacklist: {}\nwhitelist: {}\nknown_url: {:?}\nscraped_url: {:?}",
-        get_blacklist().await,
-        get_whitelist().await,
-        get_known_url().await,
-        get_scraped_url().await
+    let dir = TempDir::new("get_existing");
+    write(
+        dir.join("known_url.txt"),
+        "https://dukekunshan.edu.cn/<true\nhttps://dukekunshan.edu.cn/about\nnot a url<true\n",
     )
+    .unwrap();
+    write(
+        dir.join("scraped_url.txt"),
+        "https://dukekunshan.edu.cn/<0\nhttps://dukekunshan.edu.cn/about\nhttps://dukekunshan.edu.cn/x<y\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        get_known_url(&dir).await,
+        HashMap::from([(dku("/"), true), (dku("/about"), false)])
+    );
+    assert_eq!(get_scraped_url(&dir).await, HashMap::from([(dku("/"), 0)]));
+    assert_eq!(get_waitlist(&dir).await, Vec::<Url>::new());
 }
 
-#[tokio::main]
-#[test]
+#[tokio::test]
 async fn test_write_new() {
-    let mut known_url = HashMap::new();
-    known_url.insert(Url::parse("https://dukekunshan.edu.cn/contact-us").unwrap(), true);
-    known_url.insert(Url::parse("https://dukekunshan.edu.cn/").unwrap(), true);
-    known_url.insert(
-        Url::parse("https://dukekunshan.edu.cn/about").unwrap(),
-        false,
+    let dir = TempDir::new("write_new");
+    let known_url = HashMap::from([
+        (dku("/contact-us"), true),
+        (dku("/"), true),
+        (dku("/about"), false),
+    ]);
+    write_known_url(&dir, known_url).await;
+
+    let scraped_url = HashMap::from([(dku("/contact-us"), 2), (dku("/"), 0), (dku("/about"), 1)]);
+    write_scraped_url(&dir, scraped_url).await;
+
+    assert_eq!(
+        read_to_string(dir.join("known_url.txt")).unwrap(),
+        "https://dukekunshan.edu.cn/<true\n\
+        https://dukekunshan.edu.cn/about<false\n\
+        https://dukekunshan.edu.cn/contact-us<true\n"
     );
-    write_known_url(known_url).await;
+    let scraped = read_to_string(dir.join("scraped_url.txt")).unwrap();
+    let mut lines: Vec<&str> = scraped.lines().collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        vec![
+            "https://dukekunshan.edu.cn/<0",
+            "https://dukekunshan.edu.cn/about<1",
+            "https://dukekunshan.edu.cn/contact-us<2"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_write_bytes() {
+    let dir = TempDir::new("write_bytes");
+    write_file_bytes(dir.join("001/test.txt"), String::from("test").as_bytes())
+        .await
+        .unwrap();
+    assert_eq!(read_to_string(dir.join("001/test.txt")).unwrap(), "test");
+}
+
+#[tokio::test]
+async fn test_save_file_twice() {
+    let root = TempDir::new("save_file_twice");
+    let retry = SaveRetry {
+        attempts: 1,
+        delay: Duration::ZERO,
+    };
+    let (a, b) = (root.join("objects/a.html"), root.join("objects/b.html"));
+    write_file_bytes(&a, b"a").await.unwrap();
+    write_file_bytes(&b, b"b").await.unwrap();
+    // the same page saved again in one run replaces its snapshot
+    let first = save_file(
+        &root,
+        false,
+        &dku("/"),
+        0,
+        ".html",
+        Content::Link(&a),
+        retry,
+    )
+    .await
+    .unwrap();
+    let second = save_file(&root, true, &dku("/"), 0, ".html", Content::Link(&b), retry)
+        .await
+        .unwrap();
+    assert_eq!(first, second);
+    assert_eq!(read_to_string(&second).unwrap(), "b");
+    assert_eq!(read_to_string(&a).unwrap(), "a");
+    // bytes are not written through the link
+    save_file(
+        &root,
+        true,
+        &dku("/"),
+        0,
+        ".html",
+        Content::Bytes(b"c"),
+        retry,
+    )
+    .await
+    .unwrap();
+    assert_eq!(read_to_string(&b).unwrap(), "b");
+}
+
+#[tokio::test]
+async fn test_read_new() {
+    let dir = TempDir::new("read_new");
+    let known_url = HashMap::from([(dku("/"), true), (dku("/about"), false)]);
+    let scraped_url = HashMap::from([(dku("/"), 0), (dku("/about"), 1)]);
+    write_known_url(&dir, known_url.clone()).await;
+    write_scraped_url(&dir, scraped_url.clone()).await;
+    write_waitlist(&dir, vec![dku("/about")]).await;
 
+    assert_eq!(get_known_url(&dir).await, known_url);
+    assert_eq!(get_scraped_url(&dir).await, scraped_url);
+    assert_eq!(get_waitlist(&dir).await, vec![dku("/about")]);
+}
+
+#[test]
+fn test_checkpoint_take() {
+    let a = Url::parse("https://a.example.com/").unwrap();
+    let mut known_url = HashMap::new();
+    known_url.insert(a.clone(), true);
+    known_url.insert(a.join("/1").unwrap(), false);
+    known_url.insert(a.join("/2").unwrap(), false);
+    // seen by a worker which has not pushed it yet
+    known_url.insert(a.join("/3").unwrap(), false);
+    let link_waitlist = ShardedFrontier::default();
+    link_waitlist.push_back(a.join("/1").unwrap());
+    link_waitlist.push_back(a.join("/2").unwrap());
+    let state = SharedState::new(SeenSet::new(SeenBackend::Exact, known_url), link_waitlist);
+    assert_eq!(
+        state
+            .link_waitlist
+            .pop(Instant::now(), 0, |u| state.known_url.check(u)
+                == Some(true)),
+        Pop::Ready(a.join("/1").unwrap())
+    );
     let mut scraped_url = HashMap::new();
-    scraped_url.insert(Url::parse("https://dukekunshan.edu.cn/contact-us").unwrap(), 2);
-    scraped_url.insert(Url::parse("https://dukekunshan.edu.cn/").unwrap(), 0);
-    scraped_url.insert(Url::parse("https://dukekunshan.edu.cn/about").unwrap(), 1);
-    write_scraped_url(scraped_url).await;
+    scraped_url.insert(a.clone(), 0);
+
+    let checkpoint = Checkpoint::take(&state, &Mutex::new(scraped_url));
+    // the in-flight URL goes first and is unchecked again
+    assert_eq!(
+        checkpoint.waitlist,
+        vec![
+            a.join("/1").unwrap(),
+            a.join("/2").unwrap(),
+            a.join("/3").unwrap()
+        ]
+    );
+    assert!(!checkpoint.known_url[&a.join("/1").unwrap()]);
+    assert!(checkpoint.known_url[&a]);
+    assert!(checkpoint.is_resumable());
 }
 
-#[tokio::main]
 #[test]
-async fn test_write_bytes() {
-    write_file_bytes("001/test.txt", String::from("test").as_bytes())
-        .await
-        .unwrap_or_else(|e| println!("{}", e))
+fn test_checkpoint_new_round() {
+    let a = Url::parse("https://a.example.com/").unwrap();
+    let mut checkpoint = Checkpoint::default();
+    checkpoint.known_url.insert(a.join("/1").unwrap(), true);
+    checkpoint.scraped_url.insert(a.clone(), 0);
+    assert!(!checkpoint.is_resumable());
+
+    checkpoint.new_round();
+    assert_eq!(checkpoint.waitlist, vec![a.clone()]);
+    assert!(checkpoint.known_url.values().all(|checked| !checked));
+    assert!(checkpoint.known_url.contains_key(&a));
+
+    // seeds already queued are not queued twice
+    let seed = a.join("/seed").unwrap();
+    checkpoint.add_seeds(&[a.clone(), seed.clone()]);
+    assert_eq!(checkpoint.waitlist, vec![a.clone(), seed.clone()]);
+    assert!(!checkpoint.known_url[&seed]);
 }
 
-#[tokio::main]
 #[test]
-async fn test_read_new() {
-    let known_url = get_known_url().await;
-    println!("known_url:\n{:?}", known_url);
+fn test_db_round_trip() {
+    let a = Url::parse("https://a.example.com/").unwrap();
+    let mut db = CrawlDb::open_in_memory().unwrap();
+    assert!(db.is_empty().unwrap());
+
+    let mut checkpoint = Checkpoint::default();
+    checkpoint.known_url.insert(a.clone(), true);
+    checkpoint.known_url.insert(a.join("/1").unwrap(), false);
+    checkpoint.scraped_url.insert(a.clone(), 0);
+    checkpoint.waitlist = vec![a.join("/1").unwrap()];
+    db.save(&checkpoint).unwrap();
+    assert!(!db.is_empty().unwrap());
+
+    db.record_fetch(&a, 200, Some("abc")).unwrap();
+    // fetch records survive a later save
+    checkpoint.waitlist.clear();
+    db.save(&checkpoint).unwrap();
+
+    let loaded = db.load().unwrap();
+    assert_eq!(loaded.known_url, checkpoint.known_url);
+    assert_eq!(loaded.scraped_url, checkpoint.scraped_url);
+    assert!(loaded.waitlist.is_empty());
+
+    let record = db.fetch_record(&a).unwrap().unwrap();
+    assert_eq!(record.status, 200);
+    assert_eq!(record.content_hash.as_deref(), Some("abc"));
+    assert_eq!(db.fetch_record(&a.join("/1").unwrap()).unwrap(), None);
+
+    // export and stats
+    db.record_fetch(&a.join("/2").unwrap(), 404, Some("abc"))
+        .unwrap();
+    let records = db.records().unwrap();
+    assert_eq!(
+        records.iter().map(|r| r.url.as_str()).collect::<Vec<_>>(),
+        vec![
+            "https://a.example.com/",
+            "https://a.example.com/1",
+            "https://a.example.com/2"
+        ]
+    );
+    assert_eq!(records[0].scraped_index, Some(0));
+    assert_eq!(records[0].status, Some(200));
+    assert_eq!(records[1].fetched_at, None);
+    assert_eq!(
+        db.stats().unwrap(),
+        CrawlStats {
+            known: 3,
+            checked: 2,
+            scraped: 1,
+            waiting: 0,
+            fetched: 2,
+            statuses: vec![(200, 1), (404, 1)],
+            contents: 1,
+            duplicates: 2,
+            aborted: 0,
+            noindex: 0,
+        }
+    );
+
+    // an aborted download keeps the last body, until the next full one
+    db.record_aborted(&a, 200, "larger than 10 bytes").unwrap();
+    let record = db.fetch_record(&a).unwrap().unwrap();
+    assert_eq!(record.aborted.as_deref(), Some("larger than 10 bytes"));
+    assert_eq!(record.content_hash.as_deref(), Some("abc"));
+    assert_eq!(db.stats().unwrap().aborted, 1);
+    db.record_fetch(&a, 200, Some("def")).unwrap();
+    assert_eq!(db.fetch_record(&a).unwrap().unwrap().aborted, None);
+
+    db.set_noindex(&a, true).unwrap();
+    assert!(db.fetch_record(&a).unwrap().unwrap().noindex);
+    assert_eq!(db.stats().unwrap().noindex, 1);
+}
+
+#[test]
+fn test_db_save_changes() {
+    let a = Url::parse("https://a.example.com/").unwrap();
+    let b = a.join("/b").unwrap();
+    let c = a.join("/c").unwrap();
+    let mut db = CrawlDb::open_in_memory().unwrap();
+
+    let mut previous = Checkpoint::default();
+    previous.known_url.insert(a.clone(), true);
+    previous.known_url.insert(b.clone(), false);
+    previous.scraped_url.insert(a.clone(), 0);
+    previous.waitlist = vec![b.clone()];
+    db.save(&previous).unwrap();
+
+    // `b` checked, `c` new and index 0 moved from `a` to `c`
+    let mut checkpoint = previous.clone();
+    checkpoint.known_url.insert(b.clone(), true);
+    checkpoint.known_url.insert(c.clone(), false);
+    checkpoint.scraped_url = HashMap::from([(c.clone(), 0), (a.clone(), 1)]);
+    db.save_changes(&previous, &checkpoint).unwrap();
+    let loaded = db.load().unwrap();
+    assert_eq!(loaded.known_url, checkpoint.known_url);
+    assert_eq!(loaded.scraped_url, checkpoint.scraped_url);
+    assert_eq!(loaded.waitlist, vec![b.clone()]);
+
+    let previous = checkpoint.clone();
+    checkpoint.waitlist = vec![c.clone()];
+    db.save_changes(&previous, &checkpoint).unwrap();
+    assert_eq!(db.load().unwrap().waitlist, vec![c]);
+}
+
+#[test]
+fn test_db_validators() {
+    let a = Url::parse("https://a.example.com/").unwrap();
+    let db = CrawlDb::open_in_memory().unwrap();
+    assert!(db.validators(&a).unwrap().is_empty());
+
+    let mut headers = HeaderMap::new();
+    headers.insert(ETAG, HeaderValue::from_static("\"v1\""));
+    let validators = Validators::from_headers(&headers);
+    assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+    assert_eq!(validators.last_modified, None);
+
+    db.record_fetch(&a, 200, Some("abc")).unwrap();
+    db.set_validators(&a, &validators).unwrap();
+    // a 304 keeps the content hash and validators
+    db.record_fetch(&a, 304, None).unwrap();
+    assert_eq!(db.validators(&a).unwrap(), validators);
+    let record = db.fetch_record(&a).unwrap().unwrap();
+    assert_eq!(record.status, 304);
+    assert_eq!(record.content_hash.as_deref(), Some("abc"));
+}
+
+#[test]
+fn test_db_add_missing_columns() {
+    let dir = TempDir::new("db_add_missing_columns");
+    let path = dir.join("crawl.db");
+    {
+        // schema before `etag` and `last_modified`
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE url (url TEXT PRIMARY KEY, checked INTEGER NOT NULL DEFAULT 0,
+            scraped_index INTEGER UNIQUE, fetched_at TEXT, status INTEGER, content_hash TEXT);
+            INSERT INTO url (url, checked) VALUES ('https://a.example.com/', 1);",
+        )
+        .unwrap();
+    }
+
+    let a = Url::parse("https://a.example.com/").unwrap();
+    let db = CrawlDb::open(&path).unwrap();
+    let validators = Validators {
+        etag: None,
+        last_modified: Some("Mon, 01 Jan 2022 00:00:00 GMT".to_owned()),
+    };
+    db.set_validators(&a, &validators).unwrap();
+    assert_eq!(db.validators(&a).unwrap(), validators);
+    assert!(db.load().unwrap().known_url[&a]);
+}
+
+#[test]
+fn test_visible_text() {
+    let html = "<html><head><title>T</title><style>p {}</style></head>\
+        <body><h1>Hello</h1><script>var x;</script><p>a   b\n c</p></body></html>";
+    assert_eq!(visible_text(html), "Hello\na b c\n");
+}
+
+#[test]
+fn test_diff_runs() {
+    const OLD: &str = "2022-01-01T00:00:00+08:00";
+    const NEW: &str = "2022-02-01T00:00:00+08:00";
+    let root = TempDir::new("snapshot_diff");
+    let save = |index: usize, url: &str, files: &[(&str, &str)]| {
+        let dir = root.join(index.to_string());
+        create_dir_all(&dir).unwrap();
+        write(dir.join("url.txt"), url).unwrap();
+        for (name, content) in files {
+            write(dir.join(name), content).unwrap();
+        }
+    };
+    let page = |body: &str| format!("<html><body><p>{}</p></body></html>", body);
+
+    save(
+        0,
+        "https://a.example.com/removed",
+        &[(&format!("{}.html", OLD), &page("x"))],
+    );
+    save(
+        1,
+        "https://a.example.com/added",
+        &[(&format!("{}.html", NEW), &page("x"))],
+    );
+    save(
+        2,
+        "https://a.example.com/changed",
+        &[
+            (&format!("{}.html", OLD), &page("old")),
+            (&format!("{}.html", NEW), &page("new")),
+        ],
+    );
+    // unchanged marker points back to the old snapshot
+    save(
+        3,
+        "https://a.example.com/same",
+        &[
+            (&format!("{}.html", OLD), &page("same")),
+            (&format!("{}.unchanged", NEW), ""),
+        ],
+    );
+    // only markup changed
+    save(
+        4,
+        "https://a.example.com/markup",
+        &[
+            (&format!("{}.html", OLD), &page("m")),
+            (&format!("{}.html", NEW), "<p class=\"x\">m</p>"),
+        ],
+    );
+
+    let pages = scan_pages(&root).unwrap();
+    assert_eq!(list_runs(&pages), vec![OLD.to_owned(), NEW.to_owned()]);
+
+    let diffs = diff_runs(&pages, OLD, NEW).unwrap();
+    let summary: Vec<(Change, usize)> = diffs.iter().map(|d| (d.change, d.index)).collect();
+    assert_eq!(
+        summary,
+        vec![
+            (Change::Removed, 0),
+            (Change::Added, 1),
+            (Change::Changed, 2)
+        ]
+    );
+    let diff = diffs[2].diff.as_deref().unwrap();
+    assert!(diff.contains("-old\n") && diff.contains("+new\n"));
+
+    let json = serde_json::to_string(&diffs[0]).unwrap();
+    assert_eq!(
+        json,
+        r#"{"change":"removed","url":"https://a.example.com/removed","index":0}"#
+    );
+}
+
+#[test]
+fn test_search_index() {
+    let root = TempDir::new("search_index");
+    write(
+        root.join("mapping.txt"),
+        "\"https://a.example.com/\": \"abc.html\"\n\"https://a.example.com/x.pdf\": \"def.pdf\"\n",
+    )
+    .unwrap();
+    let mapped = snapshots_in_mapping(&root.join("mapping.txt")).unwrap();
+    assert_eq!(
+        mapped,
+        vec![
+            ("https://a.example.com/".to_owned(), root.join("abc.html")),
+            (
+                "https://a.example.com/x.pdf".to_owned(),
+                root.join("def.pdf")
+            )
+        ]
+    );
+
+    let page = |url: &str, title: &str, body: &str| {
+        let html = format!(
+            "<html><head><title>{}</title></head><body><h1>{}</h1><p>{}</p></body></html>",
+            title, title, body
+        );
+        PageDoc::from_html(url, &html, Path::new("x.html"))
+    };
+    let index = SearchIndex::create_in_ram().unwrap();
+    let count = index
+        .rebuild(vec![
+            page(
+                "https://a.example.com/1",
+                "Library",
+                "Opening hours of the campus.",
+            ),
+            page(
+                "https://a.example.com/2",
+                "Admissions",
+                "Visit the library today.",
+            ),
+            page("https://b.example.com/3", "Library", "Menus and prices."),
+            PageDoc::read(
+                "https://a.example.com/files/library.pdf",
+                Path::new("library.pdf"),
+            )
+            .unwrap(),
+        ])
+        .unwrap();
+    assert_eq!(count, 4);
+
+    let filter = SearchFilter {
+        host: Some("a.example.com".to_owned()),
+        ..SearchFilter::default()
+    };
+    let results = index.search("library", &filter, 10, 0).unwrap();
+    let hits = results.hits;
+    let mut urls: Vec<&str> = hits.iter().map(|h| h.url.as_str()).collect();
+    // title match ranks first
+    assert_eq!(urls[0], "https://a.example.com/1");
+    urls.sort();
+    assert_eq!(
+        urls,
+        vec![
+            "https://a.example.com/1",
+            "https://a.example.com/2",
+            "https://a.example.com/files/library.pdf"
+        ]
+    );
+    assert_eq!(results.total, 3);
+    let admissions = hits.iter().find(|h| h.title == "Admissions").unwrap();
+    assert!(admissions
+        .highlight("[", "]")
+        .contains("Visit the [library] today"));
+    let page_two = index.search("library", &filter, 2, 2).unwrap();
+    assert_eq!(page_two.hits.len(), 1);
+
+    let filter = SearchFilter {
+        path_prefix: Some("/files/".to_owned()),
+        content_type: Some("application/".to_owned()),
+        ..SearchFilter::default()
+    };
+    let results = index.search("", &filter, 10, 0).unwrap();
+    assert_eq!(results.total, 1);
+    assert_eq!(results.hits[0].content_type, "application/pdf");
+}
+
+#[tokio::test]
+async fn test_search_api() {
+    let root = TempDir::new("search_api");
+    let dir = root.join("0");
+    create_dir_all(&dir).unwrap();
+    write(dir.join("url.txt"), "https://a.example.com/").unwrap();
+    write(
+        dir.join("2022-01-01T00:00:00+08:00.html"),
+        "<html><head><title>Home</title></head><body><p>Campus <news></p></body></html>",
+    )
+    .unwrap();
+    write(dir.join("2022-02-01T00:00:00+08:00.unchanged"), "").unwrap();
+
+    let url = Url::parse("https://a.example.com/").unwrap();
+    let mut db = CrawlDb::open_in_memory().unwrap();
+    let mut checkpoint = Checkpoint::default();
+    checkpoint.scraped_url.insert(url.clone(), 0);
+    db.save(&checkpoint).unwrap();
+    db.record_fetch(&url, 200, Some("abc")).unwrap();
+
+    let index = SearchIndex::create_in_ram().unwrap();
+    index.rebuild(collect_docs(&root, &[]).unwrap()).unwrap();
+    let api = Arc::new(SearchApi::new(index, root.to_owned(), db));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(serve(api, listener));
+
+    let get = |path: &str| reqwest::get(format!("{}{}", base, path));
+    let response = get("/search?q=campus&type=text/").await.unwrap();
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(body["total"], 1);
+    assert_eq!(body["hits"][0]["url"], "https://a.example.com/");
+    assert_eq!(body["hits"][0]["title"], "Home");
+    assert_eq!(body["hits"][0]["snippet"], "<b>Campus</b>");
+
+    let response = get("/search?q=campus&host=b.example.com").await.unwrap();
+    let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(body["total"], 0);
+    assert_eq!(get("/search").await.unwrap().status(), 400);
+    assert_eq!(get("/search?q=x&page=a").await.unwrap().status(), 400);
+
+    let response = get("/page?url=https%3A%2F%2Fa.example.com%2F")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+    let body: serde_json::Value = serde_json::from_str(&response.text().await.unwrap()).unwrap();
+    assert_eq!(body["index"], 0);
+    assert_eq!(body["saved_at"], "2022-01-01T00:00:00+08:00");
+    assert_eq!(body["status"], 200);
+    assert_eq!(body["content_hash"], "abc");
+    assert!(body["content"].as_str().unwrap().contains("Campus"));
+
+    let response = get("/page?url=https://a.example.com/&raw").await.unwrap();
+    assert_eq!(response.headers()["content-type"], "text/html");
+    let unknown = get("/page?url=https://b.example.com/").await.unwrap();
+    assert_eq!(unknown.status(), 404);
+    assert_eq!(get("/nothing").await.unwrap().status(), 404);
+}
+
+/// every kind of page a crawl runs into
+fn mock_pages() -> HashMap<&'static str, MockPage> {
+    HashMap::from([
+        (
+            "/robots.txt",
+            MockPage::ok("text/plain", b"User-agent: *\nDisallow: /private\n"),
+        ),
+        (
+            "/",
+            MockPage::html(
+                &[
+                    "/a",
+                    "/a#top",
+                    "/a/?utm_source=mail",
+                    "/print-view",
+                    "/mirror1/page",
+                    "/cycle1",
+                    "/redirect",
+                    "/missing",
+                    "/error",
+                    "/no-type",
+                    "/bad-type.html",
+                    "/blocked/page",
+                    "/private/page",
+                    "http://example.invalid/",
+                ],
+                &["/image.png"],
+            ),
+        ),
+        ("/a", MockPage::html(&["/", "/b", "/files/report.pdf"], &[])),
+        ("/b", MockPage::html(&["/a"], &[])),
+        (
+            "/print-view",
+            MockPage::ok(
+                "text/html",
+                b"<html><head><link rel=\"canonical\" href=\"/canonical-view\"></head></html>",
+            ),
+        ),
+        // same body, the relative link is only followed from the first
+        (
+            "/mirror1/page",
+            MockPage::ok("text/html", b"<a href=\"next\">next</a>"),
+        ),
+        ("/mirror1/next", MockPage::html(&["/mirror2/page"], &[])),
+        (
+            "/mirror2/page",
+            MockPage::ok("text/html", b"<a href=\"next\">next</a>"),
+        ),
+        ("/mirror2/next", MockPage::html(&[], &[])),
+        ("/cycle1", MockPage::html(&["/cycle2"], &[])),
+        ("/cycle2", MockPage::html(&["/cycle1"], &[])),
+        (
+            "/redirect",
+            MockPage::Redirect {
+                status: 301,
+                location: "/target",
+            },
+        ),
+        ("/target", MockPage::html(&["/"], &[])),
+        ("/missing", MockPage::Status(404)),
+        ("/error", MockPage::Status(500)),
+        (
+            "/image.png",
+            MockPage::ok("image/png", b"\x89PNG\r\n\x1a\n"),
+        ),
+        (
+            "/files/report.pdf",
+            MockPage::ok("application/pdf", b"%PDF-1.4\n"),
+        ),
+        (
+            "/no-type",
+            MockPage::Ok {
+                content_type: None,
+                body: b"<a href=\"/hidden-no-type\">x</a>".to_vec(),
+            },
+        ),
+        (
+            "/bad-type.html",
+            MockPage::ok("text/plain", b"<a href=\"/hidden-bad-type\">x</a>"),
+        ),
+    ])
+}
+
+#[tokio::test]
+async fn test_crawl_mock_site() {
+    let site = MockSite::start(mock_pages());
+    let root = TempDir::new("crawl_mock_site");
+
+    let config = Config {
+        exclude: vec!["/blocked".to_owned()],
+        concurrency: 2,
+        // `/error` fetched once
+        retry_attempts: 1,
+        ..site_config(&site, &root)
+    };
+    let checkpoint = seeded(&[site.url("/")]);
+    let db = memory_db();
+
+    let start = Instant::now();
+    let checkpoint = scrape(&config, checkpoint, Arc::clone(&db)).await.unwrap();
+    // idle workers exit as soon as the last URL is finished
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    // visited: every reachable URL once, robots.txt first
+    let requests = site.requests();
+    assert_eq!(requests[0], "/robots.txt");
+    let mut visited = requests.clone();
+    visited.sort();
+    visited.dedup();
+    assert_eq!(
+        visited.len(),
+        requests.len(),
+        "fetched twice: {:?}",
+        requests
+    );
+    assert_eq!(
+        visited,
+        vec![
+            "/",
+            "/a",
+            "/b",
+            "/bad-type.html",
+            "/cycle1",
+            "/cycle2",
+            "/error",
+            "/files/report.pdf",
+            "/image.png",
+            "/mirror1/next",
+            "/mirror1/page",
+            "/mirror2/page",
+            "/missing",
+            "/no-type",
+            "/print-view",
+            "/redirect",
+            "/robots.txt",
+            "/target",
+        ]
+    );
+
+    // saved: successful responses under their final URL
+    let mut saved: Vec<(&str, &str)> = Vec::new();
+    for (url, index) in &checkpoint.scraped_url {
+        let page = PageHistory::load(&root, *index).unwrap().unwrap();
+        assert_eq!(page.url, url.as_str());
+        assert_eq!(page.snapshots.len(), 1);
+        saved.push((url.path(), content_type_of(page.latest().unwrap())));
+    }
+    saved.sort();
+    assert_eq!(
+        saved,
+        vec![
+            ("/", "text/html"),
+            ("/a", "text/html"),
+            ("/b", "text/html"),
+            // saved as what the content type says
+            ("/bad-type.html", "text/plain"),
+            // under the URL of `<link rel="canonical">`
+            ("/canonical-view", "text/html"),
+            ("/cycle1", "text/html"),
+            ("/cycle2", "text/html"),
+            ("/files/report.pdf", "application/pdf"),
+            ("/image.png", "image/png"),
+            ("/mirror1/next", "text/html"),
+            ("/mirror1/page", "text/html"),
+            ("/mirror2/page", "text/html"),
+            // sniffed, but not processed as HTML
+            ("/no-type", "text/html"),
+            ("/target", "text/html"),
+        ]
+    );
+    let image = checkpoint.scraped_url[&site.url("/image.png")];
+    let image = PageHistory::load(&root, image).unwrap().unwrap();
+    assert_eq!(read(image.latest().unwrap()).unwrap(), b"\x89PNG\r\n\x1a\n");
+
+    // filtered: known and checked but never requested
+    for url in [
+        site.url("/blocked/page"),
+        site.url("/private/page"),
+        Url::parse("http://example.invalid/").unwrap(),
+    ] {
+        assert_eq!(checkpoint.known_url.get(&url), Some(&true), "{}", url);
+    }
+    // links in responses not processed as HTML are not followed
+    assert!(!checkpoint
+        .known_url
+        .contains_key(&site.url("/hidden-no-type")));
+    assert!(!checkpoint
+        .known_url
+        .contains_key(&site.url("/hidden-bad-type")));
+    assert!(checkpoint.waitlist.is_empty());
+
+    // errors are recorded
+    let db = db.lock().unwrap();
+    let status = |path| db.fetch_record(&site.url(path)).unwrap().unwrap().status;
+    assert_eq!(status("/missing"), 404);
+    assert_eq!(status("/error"), 500);
+    assert_eq!(status("/target"), 200);
+    let clusters = db.duplicate_clusters().unwrap();
+    assert_eq!(clusters.len(), 1);
+    assert_eq!(
+        clusters[0].urls,
+        vec![site.url("/mirror1/page"), site.url("/mirror2/page")]
+    );
+}
+
+#[tokio::test]
+async fn test_crawl_near_duplicates() {
+    // every event page is the same text but the date and links to the next
+    const EVENTS: [&str; 6] = [
+        "/events/1",
+        "/events/2",
+        "/events/3",
+        "/events/4",
+        "/events/5",
+        "/events/6",
+    ];
+    let mut pages = HashMap::from([
+        ("/robots.txt", MockPage::Status(404)),
+        ("/", MockPage::html(&[EVENTS[0], "/about"], &[])),
+        ("/about", MockPage::html(&[], &[])),
+    ]);
+    let template: Vec<String> = (0..200).map(|i| format!("event{}", i)).collect();
+    for (i, path) in EVENTS.iter().enumerate() {
+        let next = EVENTS.get(i + 1).unwrap_or(&"/");
+        let body = format!(
+            "<html><body><p>{}</p><p>2022-01-{:02}</p><a href=\"{}\">next</a></body></html>",
+            template.join(" "),
+            i + 1,
+            next
+        );
+        pages.insert(path, MockPage::ok("text/html", body.as_bytes()));
+    }
+    let site = MockSite::start(pages);
+    let root = TempDir::new("crawl_near_duplicates");
+
+    let config = Config {
+        concurrency: 1,
+        near_dup: Some("3:2".to_owned()),
+        ..site_config(&site, &root)
+    };
+    let checkpoint = seeded(&[site.url("/")]);
+    let db = memory_db();
+    let checkpoint = scrape(&config, checkpoint, db).await.unwrap();
+
+    // the family stops being followed after the second near-duplicate
+    let mut requests = site.requests();
+    requests.sort();
+    assert_eq!(
+        requests,
+        vec![
+            "/",
+            "/about",
+            "/events/1",
+            "/events/2",
+            "/events/3",
+            "/robots.txt"
+        ]
+    );
+    assert_eq!(
+        checkpoint.known_url.get(&site.url("/events/4")),
+        Some(&true)
+    );
+}
+
+#[tokio::test]
+async fn test_robots_cache_failures() {
+    let robots = |times| MockPage::Flaky {
+        status: 503,
+        retry_after: None,
+        times,
+        page: Box::new(MockPage::ok(
+            "text/plain",
+            b"User-agent: *\nDisallow: /private",
+        )),
+    };
+    let retrying = MockSite::start(HashMap::from([("/robots.txt", robots(1))]));
+    let failing = MockSite::start(HashMap::from([("/robots.txt", robots(1))]));
+    let cache = |attempts| {
+        let config = Config {
+            retry_attempts: attempts,
+            retry_base_secs: 0.01,
+            retry_max_secs: 0.01,
+            ..Config::default()
+        };
+        config.robots_cache().unwrap()
+    };
+    let count = |site: &MockSite| {
+        let requests = site.requests();
+        requests
+            .iter()
+            .filter(|path| *path == "/robots.txt")
+            .count()
+    };
+
+    // lookups of one host wait for a single fetch, retried after a 5xx
+    let retried = cache(2);
+    let (page, private) = (retrying.url("/page"), retrying.url("/private"));
+    let (page, private) = tokio::join!(retried.is_allowed(&page), retried.is_allowed(&private));
+    assert!(page && !private);
+    assert!(retried.is_allowed(&retrying.url("/other")).await);
+    assert_eq!(count(&retrying), 2);
+
+    // out of attempts, the failure is not cached
+    let single = cache(1);
+    assert!(!single.is_allowed(&failing.url("/page")).await);
+    assert!(single.is_allowed(&failing.url("/page")).await);
+    assert_eq!(count(&failing), 2);
+}
+
+#[tokio::test]
+async fn test_crawl_seed_delay() {
+    let site = MockSite::start(HashMap::from([
+        ("/robots.txt", MockPage::Status(404)),
+        ("/a", MockPage::html(&[], &[])),
+        ("/b", MockPage::html(&[], &[])),
+    ]));
+    let root = TempDir::new("crawl_seed_delay");
+
+    let host = site.base.host_str().unwrap().to_owned();
+    let config = Config {
+        concurrency: 2,
+        hosts: HashMap::from([(
+            host,
+            HostConfig {
+                crawl_delay_secs: Some(0.3),
+                ..HostConfig::default()
+            },
+        )]),
+        ..site_config(&site, &root)
+    };
+    let checkpoint = seeded(&[site.url("/a"), site.url("/b")]);
+    let db = memory_db();
+    let start = Instant::now();
+    let checkpoint = scrape(&config, checkpoint, db).await.unwrap();
+    // the seeds of one host are fetched the crawl delay apart too
+    assert_eq!(checkpoint.scraped_url.len(), 2);
+    assert!(start.elapsed() >= Duration::from_millis(300));
+}
+
+#[tokio::test]
+async fn test_crawl_retries() {
+    let flaky = |status, retry_after, times| MockPage::Flaky {
+        status,
+        retry_after,
+        times,
+        page: Box::new(MockPage::html(&[], &[])),
+    };
+    let site = MockSite::start(HashMap::from([
+        ("/robots.txt", MockPage::Status(404)),
+        (
+            "/",
+            MockPage::html(&["/busy", "/flaky", "/down", "/gone", "/later"], &[]),
+        ),
+        ("/busy", flaky(429, Some("0"), 1)),
+        ("/flaky", flaky(503, None, 2)),
+        ("/down", MockPage::Status(503)),
+        ("/gone", MockPage::Status(410)),
+        ("/later", flaky(503, Some("3600"), 1)),
+    ]));
+    let root = TempDir::new("crawl_retries");
+
+    let config = Config {
+        concurrency: 2,
+        retry_attempts: 3,
+        retry_base_secs: 0.01,
+        retry_max_secs: 0.1,
+        ..site_config(&site, &root)
+    };
+    let checkpoint = seeded(&[site.url("/")]);
+    let db = memory_db();
+    let start = Instant::now();
+    let checkpoint = scrape(&config, checkpoint, Arc::clone(&db)).await.unwrap();
+    assert!(start.elapsed() < Duration::from_secs(2));
+
+    let requests = site.requests();
+    let fetches = |path: &str| requests.iter().filter(|p| *p == path).count();
+    // retryable until it works or attempts run out
+    assert_eq!(fetches("/busy"), 2);
+    assert_eq!(fetches("/flaky"), 3);
+    assert_eq!(fetches("/down"), 3);
+    // permanent, or asked to wait longer than `retry_max_secs`
+    assert_eq!(fetches("/gone"), 1);
+    assert_eq!(fetches("/later"), 1);
+
+    let mut saved: Vec<&str> = checkpoint.scraped_url.keys().map(|u| u.path()).collect();
+    saved.sort();
+    assert_eq!(saved, vec!["/", "/busy", "/flaky"]);
+    let db = db.lock().unwrap();
+    for (path, status) in [("/down", 503), ("/gone", 410), ("/later", 503)] {
+        let record = db.fetch_record(&site.url(path)).unwrap().unwrap();
+        assert_eq!(record.status, status, "{}", path);
+    }
+    assert!(!checkpoint.is_resumable());
+}
+
+#[tokio::test]
+async fn test_crawl_size_limits() {
+    let site = MockSite::start(HashMap::from([
+        ("/robots.txt", MockPage::Status(404)),
+        (
+            "/",
+            MockPage::html(&["/video.mp4", "/large.pdf", "/small.pdf"], &[]),
+        ),
+        ("/video.mp4", MockPage::ok("video/mp4", &[0; 2000])),
+        ("/large.pdf", MockPage::ok("application/pdf", &[b'%'; 5000])),
+        ("/small.pdf", MockPage::ok("application/pdf", b"%PDF-1.4\n")),
+    ]));
+    let root = TempDir::new("crawl_size_limits");
+
+    let config = Config {
+        max_body_bytes: 1000,
+        max_body_bytes_by_type: HashMap::from([
+            ("text/html".to_owned(), 10_000),
+            ("video/*".to_owned(), 100),
+        ]),
+        ..site_config(&site, &root)
+    };
+    let start_checkpoint = || seeded(&[site.url("/")]);
+    // left by an interrupted crawl
+    create_dir_all(root.join(".partial")).unwrap();
+    write(root.join(".partial/0-0.part"), b"stale").unwrap();
+    let db = memory_db();
+    let checkpoint = scrape(&config, start_checkpoint(), Arc::clone(&db))
+        .await
+        .unwrap();
+
+    let mut saved: Vec<&str> = checkpoint.scraped_url.keys().map(|u| u.path()).collect();
+    saved.sort();
+    assert_eq!(saved, vec!["/", "/small.pdf"]);
+    {
+        let db = db.lock().unwrap();
+        let aborted = |path| db.fetch_record(&site.url(path)).unwrap().unwrap().aborted;
+        assert_eq!(
+            aborted("/video.mp4").as_deref(),
+            Some("larger than 100 bytes")
+        );
+        assert_eq!(
+            aborted("/large.pdf").as_deref(),
+            Some("larger than 1000 bytes")
+        );
+        assert_eq!(aborted("/small.pdf"), None);
+    }
+    // every partial download moved or removed, stale ones too
+    assert_eq!(read_dir(root.join(".partial")).unwrap().count(), 0);
+    assert!(!checkpoint.is_resumable());
+
+    // the budget runs out on the first page, kept for the next run
+    let config = Config {
+        byte_budget: Some(10),
+        ..config
+    };
+    let db = memory_db();
+    let checkpoint = scrape(&config, start_checkpoint(), Arc::clone(&db))
+        .await
+        .unwrap();
+    assert!(checkpoint.scraped_url.is_empty());
+    assert_eq!(checkpoint.waitlist, vec![site.url("/")]);
+    assert_eq!(
+        db.lock().unwrap().fetch_record(&site.url("/")).unwrap(),
+        None
+    );
+
+    // the temporary file cannot be written, recorded as aborted
+    remove_dir_all(root.join(".partial")).unwrap();
+    write(root.join(".partial"), b"not a folder").unwrap();
+    let config = Config {
+        byte_budget: None,
+        ..config
+    };
+    let db = memory_db();
+    let checkpoint = scrape(&config, start_checkpoint(), Arc::clone(&db))
+        .await
+        .unwrap();
+    assert!(checkpoint.scraped_url.is_empty());
+    let record = db.lock().unwrap().fetch_record(&site.url("/")).unwrap();
+    let aborted = record.unwrap().aborted.unwrap();
+    assert!(aborted.starts_with("not written, "), "{}", aborted);
+}
+
+#[tokio::test]
+async fn test_crawl_routes() {
+    let site = MockSite::start(HashMap::from([
+        ("/robots.txt", MockPage::Status(404)),
+        (
+            "/",
+            MockPage::html(&["/style.css", "/app.js", "/notes.txt"], &[]),
+        ),
+        (
+            "/style.css",
+            MockPage::ok("text/css", b"body { background: url(\"from-css\") }"),
+        ),
+        ("/from-css", MockPage::ok("text/plain", b"from css")),
+        (
+            "/app.js",
+            MockPage::ok("application/javascript", b"fetch(\"/from-js\")"),
+        ),
+        ("/notes.txt", MockPage::ok("text/plain", b"some notes")),
+    ]));
+    let root = TempDir::new("crawl_routes");
+
+    let config = Config {
+        routes: HashMap::from([
+            ("text/css".to_owned(), "links".to_owned()),
+            ("text/*".to_owned(), "text".to_owned()),
+            ("application/javascript".to_owned(), "skip".to_owned()),
+        ]),
+        ..site_config(&site, &root)
+    };
+    let checkpoint = seeded(&[site.url("/")]);
+    let db = memory_db();
+    let checkpoint = scrape(&config, checkpoint, Arc::clone(&db)).await.unwrap();
+
+    let mut saved: Vec<&str> = checkpoint.scraped_url.keys().map(|u| u.path()).collect();
+    saved.sort();
+    assert_eq!(saved, vec!["/", "/from-css", "/notes.txt", "/style.css"]);
+    // requested, but neither downloaded nor followed
+    assert!(site.requests().contains(&"/app.js".to_owned()));
+    assert!(!site.requests().contains(&"/from-js".to_owned()));
+    assert_eq!(
+        db.lock()
+            .unwrap()
+            .fetch_record(&site.url("/app.js"))
+            .unwrap()
+            .unwrap()
+            .aborted
+            .as_deref(),
+        Some("skipped by the routing table")
+    );
+
+    // the text is saved next to the snapshot and indexed
+    let index = checkpoint.scraped_url[&site.url("/notes.txt")];
+    let dir = root.join(index.to_string());
+    let mut names: Vec<String> = read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names.len(), 3, "{:?}", names);
+    assert!(names[0].ends_with(".txt"));
+    assert!(names[1].ends_with(".txt.text"));
+    assert_eq!(names[2], "url.txt");
+    let snapshot = dir.join(&names[0]);
+    assert_eq!(read_to_string(dir.join(&names[1])).unwrap(), "some notes");
+    let doc = PageDoc::read(site.url("/notes.txt").as_str(), &snapshot).unwrap();
+    assert_eq!(doc.body, "some notes");
+    // a single snapshot, the text is not another one
+    let history = PageHistory::load(&root, index).unwrap().unwrap();
+    assert_eq!(history.snapshots.len(), 1);
+}
+
+#[tokio::test]
+async fn test_crawl_robots_meta() {
+    let html = |body: &str| MockPage::ok("text/html", body.as_bytes());
+    let site = MockSite::start(HashMap::from([
+        ("/robots.txt", MockPage::Status(404)),
+        (
+            "/",
+            html(
+                "<a href=\"/noindex\">1</a><a href=\"/nofollow\">2</a>\
+                <a href=\"/none\">3</a><a href=\"/other-bot\">4</a>\
+                <a href=\"/hidden\" rel=\"nofollow\">5</a>",
+            ),
+        ),
+        (
+            "/noindex",
+            html(
+                "<meta name=\"robots\" content=\"noindex\">\
+                <a href=\"/from-noindex\">a</a>",
+            ),
+        ),
+        ("/from-noindex", MockPage::html(&[], &[])),
+        (
+            "/nofollow",
+            html(
+                "<meta name=\"search_so_scraper\" content=\"nofollow\">\
+                <a href=\"/from-nofollow\">a</a>",
+            ),
+        ),
+        (
+            "/none",
+            MockPage::Header {
+                name: "x-robots-tag",
+                value: "none",
+                page: Box::new(MockPage::html(&["/from-none"], &[])),
+            },
+        ),
+        (
+            "/other-bot",
+            MockPage::Header {
+                name: "x-robots-tag",
+                value: "otherbot: none",
+                page: Box::new(MockPage::html(&[], &[])),
+            },
+        ),
+    ]));
+    let root = TempDir::new("crawl_robots_meta");
+
+    let config = site_config(&site, &root);
+    let start_checkpoint = || seeded(&[site.url("/")]);
+    let db = memory_db();
+    let checkpoint = scrape(&config, start_checkpoint(), Arc::clone(&db))
+        .await
+        .unwrap();
+
+    let mut saved: Vec<&str> = checkpoint.scraped_url.keys().map(|u| u.path()).collect();
+    saved.sort();
+    assert_eq!(saved, vec!["/", "/from-noindex", "/nofollow", "/other-bot"]);
+    let requests = site.requests();
+    for path in ["/from-nofollow", "/from-none", "/hidden"] {
+        assert!(!requests.contains(&path.to_owned()), "{}", path);
+    }
+    {
+        let db = db.lock().unwrap();
+        let record = |path| db.fetch_record(&site.url(path)).unwrap().unwrap();
+        for path in ["/noindex", "/none"] {
+            assert_eq!(
+                record(path).aborted.as_deref(),
+                Some("noindex by robots directives")
+            );
+        }
+        assert!(!record("/nofollow").noindex);
+    }
+
+    // stored and marked instead
+    let config = Config {
+        save_noindex: true,
+        ..config
+    };
+    let db = memory_db();
+    let checkpoint = scrape(&config, start_checkpoint(), Arc::clone(&db))
+        .await
+        .unwrap();
+    assert!(checkpoint.scraped_url.contains_key(&site.url("/noindex")));
+    assert!(checkpoint.scraped_url.contains_key(&site.url("/none")));
+    assert_eq!(db.lock().unwrap().stats().unwrap().noindex, 2);
+    let _ = remove_dir_all(&*root);
+
+    // or ignored
+    let config = Config {
+        robots_meta: false,
+        ..config
+    };
+    let db = memory_db();
+    scrape(&config, start_checkpoint(), db).await.unwrap();
+    let requests = site.requests();
+    for path in ["/from-nofollow", "/from-none", "/hidden"] {
+        assert!(requests.contains(&path.to_owned()), "{}", path);
+    }
+}
+
+#[tokio::test]
+async fn test_crawl_relative_links_of_directory() {
+    let site = MockSite::start(HashMap::from([
+        ("/robots.txt", MockPage::Status(404)),
+        ("/", MockPage::html(&["/dir/"], &[])),
+        // fetched as `/dir` once canonicalized
+        (
+            "/dir",
+            MockPage::Redirect {
+                status: 301,
+                location: "/dir/",
+            },
+        ),
+        (
+            "/dir/",
+            MockPage::ok(
+                "text/html",
+                b"<a href=\"page\">page</a><img src=\"img.png\">",
+            ),
+        ),
+        ("/dir/page", MockPage::html(&[], &[])),
+        (
+            "/dir/img.png",
+            MockPage::ok("image/png", b"\x89PNG\r\n\x1a\n"),
+        ),
+    ]));
+    let root = TempDir::new("crawl_relative_links");
+
+    let config = site_config(&site, &root);
+    let checkpoint = seeded(&[site.url("/")]);
+    let db = memory_db();
+    let checkpoint = scrape(&config, checkpoint, db).await.unwrap();
+
+    let mut saved: Vec<&str> = checkpoint.scraped_url.keys().map(|u| u.path()).collect();
+    saved.sort();
+    assert_eq!(saved, vec!["/", "/dir", "/dir/img.png", "/dir/page"]);
+    let requests = site.requests();
+    assert!(!requests.contains(&"/page".to_owned()));
+    assert!(!requests.contains(&"/img.png".to_owned()));
+}
+
+#[tokio::test]
+async fn test_crawl_stores_identical_bodies_once() {
+    let site = MockSite::start(HashMap::from([
+        ("/robots.txt", MockPage::Status(404)),
+        ("/", MockPage::html(&["/a", "/b"], &[])),
+        ("/a", MockPage::ok("text/html", b"<p>same</p>")),
+        ("/b", MockPage::ok("text/html", b"<p>same</p>")),
+    ]));
+    let root = TempDir::new("crawl_identical_bodies");
+
+    let config = site_config(&site, &root);
+    let checkpoint = seeded(&[site.url("/")]);
+    let db = memory_db();
+    scrape(&config, checkpoint, db).await.unwrap();
 
-    let scraped_url = get_scraped_url().await;
-    println!("scraped_url:\n{:?}", scraped_url);
+    // `/` and the body shared by `/a` and `/b`
+    let objects = read_dir(root.join(OBJECTS_DIR)).unwrap().count();
+    assert_eq!(objects, 2);
+    let pages = scan_pages(&root).unwrap();
+    assert_eq!(pages.len(), 3);
+    for page in pages.iter().filter(|p| !p.url.ends_with('/')) {
+        let (_, path) = &page.snapshots[0];
+        assert_eq!(read(path).unwrap(), b"<p>same</p>");
+    }
 }
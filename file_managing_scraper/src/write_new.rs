@@ -1,26 +1,34 @@
-use crate::file_dealer::write_file;
+use crate::file_dealer::replace_file;
 use reqwest::Url;
-use std::collections::{BTreeMap, HashMap};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
 
-pub async fn write_known_url(known_url: HashMap<Url, bool>) {
+/// write `known_url.txt` in `dir` as `url<checked` each line
+pub async fn write_known_url(dir: &Path, known_url: HashMap<Url, bool>) {
     let mut s = String::new();
 
-    let mut sorted: Vec<String> = known_url.into_keys().map(|u| u.to_string()).collect();
-    sorted.sort();
+    let known_url: BTreeMap<String, bool> = known_url
+        .into_iter()
+        .map(|(u, checked)| (u.to_string(), checked))
+        .collect();
 
-    for url in sorted {
+    for (url, checked) in known_url {
         s.push_str(&url);
+        s.push('<');
+        s.push_str(&checked.to_string());
         s.push('\n');
     }
     loop {
-        match write_file("known_url.txt", &s).await {
+        match replace_file(&dir.join("known_url.txt"), &s).await {
             Ok(()) => break,
             Err(e) => println!("{} saving known url", e),
         }
     }
 }
 
-pub async fn write_scraped_url(scraped_url: HashMap<Url, usize>) {
+pub async fn write_scraped_url(dir: &Path, scraped_url: HashMap<Url, usize>) {
     let mut s = String::new();
 
     let scraped_url: BTreeMap<String, usize> = scraped_url
@@ -35,9 +43,25 @@ pub async fn write_scraped_url(scraped_url: HashMap<Url, usize>) {
         s.push('\n');
     }
     loop {
-        match write_file("scraped_url.txt", &s).await {
+        match replace_file(&dir.join("scraped_url.txt"), &s).await {
             Ok(()) => break,
             Err(e) => println!("{} saving known url", e),
         }
     }
 }
+
+/// write `waitlist.txt` in `dir` as `URL` each line in waitlist order
+pub async fn write_waitlist(dir: &Path, waitlist: Vec<Url>) {
+    let mut s = String::new();
+
+    for url in waitlist {
+        s.push_str(url.as_str());
+        s.push('\n');
+    }
+    loop {
+        match replace_file(&dir.join("waitlist.txt"), &s).await {
+            Ok(()) => break,
+            Err(e) => println!("{} saving waitlist", e),
+        }
+    }
+}
@@ -1,19 +1,47 @@
+use clap::Parser;
 use fixed_concurrent_scraper::crawl_links_r;
-use url::Url;
+use scraper_core::cli::{exit_with, join, Workdir};
+
+/// crawl from the seeds, saving each body once as its sha256\
+/// print `"URL": "FILE"` of each file saved
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    workdir: Workdir,
+    /// number of workers
+    #[arg(short = 'j', long)]
+    concurrency: Option<usize>,
+    /// URL to start from, replacing `seeds` of the config
+    seeds: Vec<String>,
+}
 
 #[tokio::main]
 async fn main() {
     // let url = Url::parse("https://dukekunshan.edu.cn").unwrap();
     // let url = Url::parse("http://mp.weixin.qq.com/mp/homepage?__biz=MzU3ODg4NTQxMg==&hid=1&sn=97027463b2605823ee4aefdea01efaf9&scene=18#wechat_redirect").unwrap();
 
-    let mut args = std::env::args();
-    args.next();
-    let url_string = args.next().unwrap();
-    let url = Url::parse(&url_string).unwrap();
-
-    let process_num: usize = args.next().unwrap().parse().unwrap();
+    let cli = Cli::parse();
+    let mut config = cli.workdir.load_config().unwrap_or_else(|e| exit_with(&e));
+    if !cli.seeds.is_empty() {
+        config.seeds = cli.seeds;
+    }
+    if let Some(concurrency) = cli.concurrency {
+        config.concurrency = concurrency;
+    }
+    if let Err(e) = config.validate() {
+        exit_with(&e);
+    }
+    if config.seeds.is_empty() {
+        exit_with("no seed URL, give one or set `seeds` in the config");
+    }
 
-    let handle = tokio::task::spawn_blocking(move || crawl_links_r(url, process_num).unwrap());
-    let total_processed_count = handle.await.unwrap();
-    eprintln!("\n\nSummary: {} files downloaded", total_processed_count);
+    let handle =
+        tokio::task::spawn_blocking(move || crawl_links_r(&config).map_err(|e| e.to_string()));
+    match join(handle).await {
+        Ok(total_processed_count) => {
+            eprintln!("\n\nSummary: {} files downloaded", total_processed_count)
+        }
+        Err(e) => exit_with(&e),
+    }
 }
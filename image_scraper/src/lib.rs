@@ -1,29 +1,40 @@
+use async_trait::async_trait;
 use bytes::Bytes;
-use image;
-use reqwest::{redirect::Policy, Client};
-use select::{document::Document, predicate::Name};
+use reqwest::redirect::Policy;
+use scraper_core::{
+    client::client_builder,
+    config::Config,
+    links::{Images, LinkExtractor},
+    sink::{Fetched, StorageSink},
+};
 use sha256::digest_bytes;
-use std::{error::Error, time::Duration};
+use std::{
+    error::Error,
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{create_dir_all, read},
+    task::spawn_blocking,
+};
 use url::Url;
 
+/// save the images of the page at `url` in `output_dir`
+/// # return
+/// `{image URL}:{sha256}` of each, one each line
 #[tokio::main]
-pub async fn save_all_image(url: &str) -> Result<String, Box<dyn Error>> {
+pub async fn save_all_image(url: &str, config: &Config) -> Result<String, Box<dyn Error>> {
     eprintln!("Scanning for {}", url);
     // let body = request(url)?;
-    let client = Client::builder()
-        .connect_timeout(Duration::from_secs(5))
-        .redirect(Policy::none())
-        .build()?;
+    let client = config.client_builder().redirect(Policy::none()).build()?;
     let body = client.get(url).send().await?.text().await?;
+    create_dir_all(&config.output_dir).await?;
     let mut record = String::new();
-    for img in Document::from(body.as_str())
-        .find(Name("img"))
-        .filter_map(|n| n.attr("src"))
-    {
-        let img_url = String::from(Url::parse(url)?.join(img)?.as_str());
+    for img in Images.extract(&Url::parse(url)?, &body) {
+        let img_url = String::from(img.url.as_str());
         println!("img = {}", &img_url);
         let img_bytes = client.get(&img_url).send().await?.bytes().await?;
-        record.push_str(format!("{{{}}}:{{{}}}\n", img_url, save_image(&img_bytes)?).as_str());
+        let hash = save_image_in(&config.output_dir, &img_bytes)?;
+        record.push_str(format!("{{{}}}:{{{}}}\n", img_url, hash).as_str());
         // record.push_str(format!("{{{}}}:{{{}}}\n", img_url, save_image(&img_url)?).as_str());
     }
     Ok(record)
@@ -31,6 +42,13 @@ pub async fn save_all_image(url: &str) -> Result<String, Box<dyn Error>> {
 
 pub fn save_image(img_bytes: &Bytes) -> Result<String, Box<dyn Error>> {
     // let img_bytes = request_as_bytes(url)?;
+    save_image_in(Path::new("."), img_bytes)
+}
+
+/// decode `img_bytes` and save it as `sha256(img_bytes).jpg` in `dir`
+/// # return
+/// the hash, empty if `img_bytes` is not an image
+pub fn save_image_in(dir: &Path, img_bytes: &[u8]) -> Result<String, Box<dyn Error>> {
     match image::load_from_memory(img_bytes) {
         Err(err) => {
             eprintln!("{}", err);
@@ -38,16 +56,41 @@ pub fn save_image(img_bytes: &Bytes) -> Result<String, Box<dyn Error>> {
         }
         Ok(img) => {
             let hash = digest_bytes(img_bytes);
-            img.save(format!("{}.jpg", hash))?;
+            img.save(dir.join(format!("{}.jpg", hash)))?;
             Ok(hash)
         }
     }
 }
 
+/// the image pipeline of a crawl: every image saved as JPEG in `dir`,
+/// see `save_image_in`\
+/// outputs `"final_url": "path"` to stdout like `HashedFileSink`
+#[derive(Debug, Clone)]
+pub struct ImageSink {
+    pub dir: PathBuf,
+}
+
+#[async_trait]
+impl StorageSink for ImageSink {
+    async fn save(&self, page: &Fetched<'_>) -> Result<(), String> {
+        let bytes = read(&page.body.path).await.map_err(|e| e.to_string())?;
+        create_dir_all(&self.dir).await.map_err(|e| e.to_string())?;
+        let dir = self.dir.clone();
+        // decoding and encoding take a while
+        let hash = spawn_blocking(move || save_image_in(&dir, &bytes).map_err(|e| e.to_string()))
+            .await
+            .map_err(|e| e.to_string())??;
+        if hash.is_empty() {
+            return Err("not a readable image".to_owned());
+        }
+        println!("\"{}\": \"{}.jpg\"", page.final_url, hash);
+        Ok(())
+    }
+}
+
 #[tokio::main]
 pub async fn request(url: &str) -> Result<String, Box<dyn Error>> {
-    Ok(Client::builder()
-        .connect_timeout(Duration::from_secs(5))
+    Ok(client_builder()
         .redirect(Policy::none())
         .build()?
         .get(url)
@@ -59,8 +102,7 @@ pub async fn request(url: &str) -> Result<String, Box<dyn Error>> {
 
 #[tokio::main]
 pub async fn request_as_bytes(url: &str) -> Result<Bytes, Box<dyn Error>> {
-    Ok(Client::builder()
-        .connect_timeout(Duration::from_secs(5))
+    Ok(client_builder()
         .redirect(Policy::none())
         .build()?
         .get(url)
@@ -1,10 +1,33 @@
+use clap::Parser;
 use image_scraper::save_all_image;
+use scraper_core::cli::{exit_with, Workdir};
+
+/// save the images of pages as their sha256 in `output_dir`\
+/// print `{image URL}:{sha256}` of each
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    workdir: Workdir,
+    /// pages to scan [default: `seeds` of the config]
+    urls: Vec<String>,
+}
 
 fn main() {
-    let url0 = "https://dukekunshan.edu.cn";
-    let record = save_all_image(url0).unwrap_or_else(|err| {
-        eprintln!("{}", err);
-        String::from("nothing")
-    });
-    println!("{}", record);
+    let cli = Cli::parse();
+    let mut config = cli.workdir.load_config().unwrap_or_else(|e| exit_with(&e));
+    if !cli.urls.is_empty() {
+        config.seeds = cli.urls;
+    }
+    if config.seeds.is_empty() {
+        exit_with("no page to scan, give URL or set `seeds` in the config");
+    }
+
+    for url0 in &config.seeds {
+        let record = save_all_image(url0, &config).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            String::from("nothing")
+        });
+        println!("{}", record);
+    }
 }
@@ -1,25 +1,22 @@
 use bytes::Bytes;
-use reqwest::{redirect::Policy, Client};
-use select::{document::Document, predicate::Name};
+use reqwest::redirect::Policy;
+use scraper_core::{
+    client::client_builder,
+    links::{Images, LinkExtractor},
+};
 use sha256::digest_bytes;
-use std::{error::Error, fs::File, io::Write, time::Duration};
+use std::{error::Error, fs::File, io::Write};
 use url::Url;
 
 #[tokio::main]
 pub async fn save_all_image(url: &str) -> Result<String, Box<dyn Error>> {
     eprintln!("Scanning for {}", url);
-    let client = Client::builder()
-        .connect_timeout(Duration::from_secs(5)) // timeout: 5 sec
-        .redirect(Policy::none())
-        .build()?;
+    let client = client_builder().redirect(Policy::none()).build()?;
     let body = client.get(url).send().await?.text().await?;
     let mut record = String::new();
-    for img in Document::from(body.as_str())
-        .find(Name("img"))
-        .filter_map(|n| n.attr("src"))
-    {
-        let img_url = String::from(Url::parse(url)?.join(img)?.as_str());
-        let img_format = match img.split(".").last() {
+    for img in Images.extract(&Url::parse(url)?, &body) {
+        let img_url = String::from(img.url.as_str());
+        let img_format = match img_url.split('.').next_back() {
             None => {
                 eprintln!("file format unknown");
                 String::new()
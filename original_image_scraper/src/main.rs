@@ -7,7 +7,7 @@ use std::io::{self, BufRead};
 /// preserve the file extension and name the file as its sha256
 /// **return** a string of {URL}:{sha256.extension} pair
 fn main() {
-    let stdin =io::stdin();
+    let stdin = io::stdin();
     let input = stdin.lock().lines();
     let mut record = String::new();
     for line in input {
@@ -1,7 +1,7 @@
 use bytes::Bytes;
-use image;
-use reqwest::{redirect::Policy, Client};
-use std::{error::Error, process::exit, time::Duration};
+use reqwest::redirect::Policy;
+use scraper_core::client::client_builder;
+use std::{error::Error, process::exit};
 
 fn main() {
     let url1 = "https://archlinux.org";
@@ -28,8 +28,7 @@ fn main() {
 
 #[tokio::main]
 async fn request(url: &str) -> Result<String, Box<dyn Error>> {
-    Ok(Client::builder()
-        .connect_timeout(Duration::from_secs(5))
+    Ok(client_builder()
         .redirect(Policy::none())
         .build()?
         .get(url)
@@ -41,8 +40,7 @@ async fn request(url: &str) -> Result<String, Box<dyn Error>> {
 
 #[tokio::main]
 async fn request_as_bytes(url: &str) -> Result<Bytes, Box<dyn Error>> {
-    Ok(Client::builder()
-        .connect_timeout(Duration::from_secs(5))
+    Ok(client_builder()
         .redirect(Policy::none())
         .build()?
         .get(url)
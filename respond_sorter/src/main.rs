@@ -1,12 +1,10 @@
-use reqwest::{redirect::Policy, Client};
-use std::{error::Error, time::Duration};
+use reqwest::redirect::Policy;
+use scraper_core::client::client_builder;
+use std::error::Error;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    let client = Client::builder()
-        .connect_timeout(Duration::from_secs(5)) // timeout: 5 sec
-        .redirect(Policy::none())
-        .build()?;
+    let client = client_builder().redirect(Policy::none()).build()?;
 
     let url0 = "https://dukekunshan.edu.cn";
     let response0 = client.get(url0).send().await?;
@@ -0,0 +1,141 @@
+//! crawl a generated site on localhost with more and more workers
+//!
+//! `cargo bench -p scraper_core --bench crawl_throughput [-- PAGES LATENCY_MS]`
+
+use async_trait::async_trait;
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server,
+};
+use reqwest::Url;
+use scraper_core::{
+    canonical::Canonicalizer,
+    client::client_builder,
+    crawler::{crawl_parallel, Plugins, SharedState},
+    download::{Downloads, SizeLimits},
+    fetcher::ClientFetcher,
+    filter::AllowAll,
+    links::AnchorsAndImages,
+    retry::RetryPolicy,
+    routes::Routes,
+    sink::{Fetched, StorageSink},
+};
+use std::{
+    convert::Infallible,
+    env::{args, temp_dir},
+    net::TcpListener,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    spawn,
+    time::{sleep, Instant},
+};
+
+/// links from each page to the next pages
+const FAN_OUT: usize = 8;
+
+/// worker counts to compare
+const PROCESS_NUMS: [usize; 7] = [1, 2, 4, 8, 16, 32, 64];
+
+/// page `i` links to `FAN_OUT` new pages, its parent and a page far away\
+/// so most links found are already known
+fn page(i: usize, pages: usize) -> String {
+    let mut body = String::from("<html><body>");
+    let children = (i * FAN_OUT + 1..=i * FAN_OUT + FAN_OUT).filter(|c| *c < pages);
+    for link in children.chain([i / FAN_OUT, i * 7919 % pages]) {
+        body.push_str(&format!("<a href=\"/p/{}\">{}</a>", link, link));
+    }
+    body.push_str("</body></html>");
+    body
+}
+
+/// serve `pages` pages answering after `latency`
+fn start_site(pages: usize, latency: Duration) -> Url {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = Url::parse(&format!("http://{}/", listener.local_addr().unwrap())).unwrap();
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |request: Request<Body>| async move {
+            sleep(latency).await;
+            let i = request
+                .uri()
+                .path()
+                .strip_prefix("/p/")
+                .and_then(|i| i.parse().ok())
+                .unwrap_or(0);
+            let mut response = Response::new(Body::from(page(i, pages)));
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, "text/html".parse().unwrap());
+            Ok::<_, Infallible>(response)
+        }))
+    });
+    spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+    base
+}
+
+/// count the bytes instead of saving
+#[derive(Default)]
+struct CountingSink {
+    bytes: AtomicUsize,
+}
+
+#[async_trait]
+impl StorageSink for CountingSink {
+    async fn save(&self, page: &Fetched<'_>) -> Result<(), String> {
+        self.bytes.fetch_add(page.body.len as usize, Relaxed);
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    // `cargo bench` passes `--bench`
+    let mut numbers = args().skip(1).filter_map(|a| a.parse().ok());
+    let pages: usize = numbers.next().unwrap_or(2000);
+    let latency = Duration::from_millis(numbers.next().unwrap_or(5) as u64);
+    let base = start_site(pages, latency);
+    println!("{} pages, {:?} latency", pages, latency);
+    println!("process_num    pages/s    seconds");
+
+    let mut single = None;
+    for process_num in PROCESS_NUMS {
+        let sink = Arc::new(CountingSink::default());
+        let plugins = Plugins {
+            fetcher: Arc::new(ClientFetcher {
+                client: client_builder().build().unwrap(),
+            }),
+            extractor: Arc::new(AnchorsAndImages),
+            filter: Arc::new(AllowAll),
+            sink: Arc::clone(&sink) as _,
+            canonicalizer: Arc::new(Canonicalizer::default()),
+            near_duplicates: None,
+            retry: RetryPolicy::default(),
+            downloads: Arc::new(Downloads::new(
+                temp_dir().join("crawl_throughput"),
+                SizeLimits::default(),
+                None,
+            )),
+            routes: Arc::new(Routes::default()),
+            images: None,
+            robots_meta: None,
+        };
+        let state = SharedState::from_url(base.join("/p/0").unwrap());
+
+        let start = Instant::now();
+        let processed = crawl_parallel(process_num, &state, &plugins).await;
+        let seconds = start.elapsed().as_secs_f64();
+        assert_eq!(processed, pages, "every page crawled once");
+
+        let rate = processed as f64 / seconds;
+        let speedup = rate / *single.get_or_insert(rate);
+        println!(
+            "{:>11} {:>10.0} {:>10.3}  x{:.1}",
+            process_num, rate, seconds, speedup
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use regex::Regex;
+use reqwest::Url;
+use std::collections::HashMap;
+
+/// query parameters dropped by default: analytics and ad click tracking
+pub const TRACKING_PARAMS: &str =
+    r"^(utm_.*|fbclid|gclid|dclid|gbraid|wbraid|msclkid|yclid|mc_cid|mc_eid|_ga|_gl)$";
+
+/// rewrites URL so the same page found through different links
+/// is crawled once\
+/// the scheme and host are lowercased, default ports and dot segments
+/// removed already by `Url::parse`, always
+#[derive(Debug, Clone)]
+pub struct Canonicalizer {
+    /// drop `#fragment`
+    pub strip_fragment: bool,
+    /// `/path/` to `/path`, the root `/` is kept
+    pub strip_trailing_slash: bool,
+    /// lowercase the path, for servers ignoring its case
+    pub lowercase_path: bool,
+    /// sort query parameters by name then value
+    pub sort_query: bool,
+    /// query parameters whose decoded name matches are dropped
+    pub drop_params: Option<Regex>,
+    /// decode escaped unreserved characters, uppercase other escapes
+    pub normalize_percent_encoding: bool,
+    /// replaces this one for URL of a host, by lowercase host name
+    pub hosts: HashMap<String, Canonicalizer>,
+}
+
+impl Default for Canonicalizer {
+    fn default() -> Canonicalizer {
+        Canonicalizer {
+            strip_fragment: true,
+            strip_trailing_slash: true,
+            lowercase_path: false,
+            sort_query: true,
+            drop_params: Some(Regex::new(TRACKING_PARAMS).unwrap()),
+            normalize_percent_encoding: true,
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+impl Canonicalizer {
+    /// leave every URL as it is
+    pub fn identity() -> Canonicalizer {
+        Canonicalizer {
+            strip_fragment: false,
+            strip_trailing_slash: false,
+            lowercase_path: false,
+            sort_query: false,
+            drop_params: None,
+            normalize_percent_encoding: false,
+            hosts: HashMap::new(),
+        }
+    }
+
+    /// canonical form of `url`
+    pub fn canonicalize(&self, url: &Url) -> Url {
+        if let Some(host) = url.host_str().and_then(|host| self.hosts.get(host)) {
+            return host.canonicalize(url);
+        }
+        let mut url = url.clone();
+        if url.cannot_be_a_base() {
+            return url;
+        }
+
+        if self.strip_fragment {
+            url.set_fragment(None);
+        }
+
+        let mut path = url.path().to_owned();
+        if self.lowercase_path {
+            // before the escapes are uppercased
+            path.make_ascii_lowercase();
+        }
+        if self.normalize_percent_encoding {
+            path = normalize_percent(&path);
+        }
+        if self.strip_trailing_slash && path.len() > 1 && path.ends_with('/') {
+            path.pop();
+        }
+        url.set_path(&path);
+
+        if let Some(query) = url.query() {
+            let mut params: Vec<String> = query
+                .split('&')
+                .filter(|param| !param.is_empty())
+                .filter(|param| !self.is_dropped(param))
+                .map(|param| {
+                    if self.normalize_percent_encoding {
+                        normalize_percent(param)
+                    } else {
+                        param.to_owned()
+                    }
+                })
+                .collect();
+            if self.sort_query {
+                params.sort();
+            }
+            if params.is_empty() {
+                url.set_query(None);
+            } else {
+                url.set_query(Some(&params.join("&")));
+            }
+        }
+
+        url
+    }
+
+    /// whether the name of `param`, as `name=value`, matches `drop_params`
+    fn is_dropped(&self, param: &str) -> bool {
+        let drop_params = match &self.drop_params {
+            Some(re) => re,
+            None => return false,
+        };
+        let name = param.split('=').next().unwrap_or_default();
+        let name = percent_decode(&name.replace('+', " "));
+        drop_params.is_match(&name)
+    }
+}
+
+/// `%XX` of unreserved characters decoded, other `%xx` uppercased
+fn normalize_percent(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut normalized = String::with_capacity(s.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(byte) = escaped_byte(bytes, i) {
+            let c = byte as char;
+            if c.is_ascii_alphanumeric() || "-._~".contains(c) {
+                normalized.push(c);
+            } else {
+                normalized.push_str(&format!("%{:02X}", byte));
+            }
+            i += 3;
+        } else {
+            normalized.push(bytes[i] as char);
+            i += 1;
+        }
+    }
+    normalized
+}
+
+/// `s` with every `%XX` of two hex digits decoded, invalid UTF-8 replaced
+pub fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if let Some(byte) = escaped_byte(bytes, i) {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// the byte of the `%XX` escape at `i`, if any
+fn escaped_byte(bytes: &[u8], i: usize) -> Option<u8> {
+    match bytes.get(i..i + 3) {
+        Some([b'%', h, l]) if h.is_ascii_hexdigit() && l.is_ascii_hexdigit() => {
+            u8::from_str_radix(std::str::from_utf8(&[*h, *l]).ok()?, 16).ok()
+        }
+        _ => None,
+    }
+}
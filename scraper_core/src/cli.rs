@@ -0,0 +1,57 @@
+use clap::Args;
+use std::path::{Path, PathBuf};
+use tokio::task::JoinHandle;
+
+use crate::config::{Config, CONFIG_FILE};
+
+/// `--workdir` and `--config`, taken by every binary
+#[derive(Debug, Clone, Args)]
+pub struct Workdir {
+    /// folder holding the config, the rule files and what is saved
+    #[arg(short = 'C', long = "workdir", default_value = ".", global = true)]
+    pub dir: PathBuf,
+    /// settings file, relative to the workdir [default: scraper.toml if it exists]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+}
+
+impl Workdir {
+    /// `path` in the workdir, `path` itself if absolute
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.dir.join(path)
+    }
+
+    /// the config given, or `CONFIG_FILE` if it exists, otherwise the defaults\
+    /// its relative paths are taken from the workdir
+    pub fn load_config(&self) -> Result<Config, String> {
+        if !self.dir.is_dir() {
+            return Err(format!("workdir {}: not a folder", self.dir.display()));
+        }
+        let default = self.join(CONFIG_FILE);
+        match &self.config {
+            Some(path) => Config::load(self.join(path), &self.dir),
+            None if default.exists() => Config::load(default, &self.dir),
+            None => {
+                let mut config = Config::default();
+                config.resolve_paths(&self.dir);
+                Ok(config)
+            }
+        }
+    }
+}
+
+/// print `error` and exit with status 1
+pub fn exit_with(error: &str) -> ! {
+    eprintln!("{}", error);
+    std::process::exit(1)
+}
+
+/// wait for the task of `handle`
+/// # return
+/// its result, or its panic or cancellation as an error
+pub async fn join<T>(handle: JoinHandle<Result<T, String>>) -> Result<T, String> {
+    match handle.await {
+        Ok(result) => result,
+        Err(err) => Err(err.to_string()),
+    }
+}
@@ -0,0 +1,11 @@
+use reqwest::{Client, ClientBuilder};
+use std::time::Duration;
+
+use crate::robots::USER_AGENT;
+
+/// client settings shared by every scraper
+pub fn client_builder() -> ClientBuilder {
+    Client::builder()
+        .connect_timeout(Duration::from_secs(5)) // timeout: 5 sec
+        .user_agent(USER_AGENT)
+}
@@ -0,0 +1,569 @@
+use regex::Regex;
+use reqwest::{Client, ClientBuilder, Url};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{
+    canonical::Canonicalizer,
+    download::{Downloads, SizeLimits, PARTIAL_DIR},
+    filter::{HostRule, HostRules, LinkKinds, RuleFilter},
+    links::LinkKind,
+    meta_robots::RobotsMeta,
+    mime::is_type_pattern,
+    near_dup::NearDupConfig,
+    retry::RetryPolicy,
+    robots::{RobotsCache, USER_AGENT},
+    routes::{Action, Routes},
+    rules::{read_rules, Rule, RuleList},
+    seen::SeenBackend,
+    sink::HashedFileSink,
+};
+
+/// file read by `Workdir::load_config` from the workdir
+pub const CONFIG_FILE: &str = "scraper.toml";
+
+/// crawler settings shared by every scraper, read from TOML\
+/// every key is optional, see `Config::default`
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// URL the crawl starts from
+    pub seeds: Vec<String>,
+    /// rule, see `Rule::parse`, URL matching none are not crawled
+    /// unless there is no include rule
+    pub include: Vec<String>,
+    /// rule, URL matching any are not crawled
+    pub exclude: Vec<String>,
+    /// files of include rules, see `rules::parse_rules`
+    pub include_files: Vec<PathBuf>,
+    /// files of exclude rules
+    pub exclude_files: Vec<PathBuf>,
+    /// number of workers
+    pub concurrency: usize,
+    pub user_agent: String,
+    /// folder responses are saved under
+    pub output_dir: PathBuf,
+    pub connect_timeout_secs: f64,
+    /// whole request, no limit if absent
+    pub request_timeout_secs: Option<f64>,
+    /// attempts to write each file
+    pub save_attempts: usize,
+    /// pause between two attempts to write a file
+    pub save_retry_secs: f64,
+    /// fetches of a URL failing with a timeout, 429, 5xx or the like, 1 to never retry
+    pub retry_attempts: usize,
+    /// pause after the first failed fetch of a URL, doubled after each
+    pub retry_base_secs: f64,
+    /// longest pause before fetching a URL again, a longer `Retry-After` gives up
+    pub retry_max_secs: f64,
+    /// replaces every other crawl delay if set
+    pub crawl_delay_secs: Option<f64>,
+    /// largest body downloaded in bytes, larger ones are aborted
+    pub max_body_bytes: u64,
+    /// replaces `max_body_bytes` by `type/subtype` or `type/*`\
+    /// HTML is held whole in memory, its limit bounds the memory of each worker
+    pub max_body_bytes_by_type: HashMap<String, u64>,
+    /// bytes downloaded before the crawl stops, no limit if absent
+    pub byte_budget: Option<u64>,
+    /// action by `type/subtype`, `type/*` or `*/*` for responses other than HTML,
+    /// `save`, `skip`, `links`, `text` or `image`, `save` if none matches
+    pub routes: HashMap<String, String>,
+    /// honor `X-Robots-Tag`, `<meta name="robots">` and `rel="nofollow"`
+    pub robots_meta: bool,
+    /// store `noindex` pages marked as such instead of skipping them
+    pub save_noindex: bool,
+    /// kinds of links followed, see `LinkKind`,
+    /// pages and images if absent
+    pub link_kinds: Option<Vec<String>>,
+    /// `exact`, `bloom` or `bloom:FALSE_POSITIVE_RATE`,
+    /// a crawl on `bloom` cannot be resumed
+    pub seen: String,
+    /// `MAX_DISTANCE[:FAMILY_LIMIT]`, no near-duplicate detection if absent
+    pub near_dup: Option<String>,
+    /// regex on query parameter names dropped from URL,
+    /// tracking parameters if absent
+    pub drop_params: Option<String>,
+    /// drop `#fragment` from URL
+    pub strip_fragment: bool,
+    /// crawl `/path/` as `/path`
+    pub strip_trailing_slash: bool,
+    /// crawl URL paths lowercased, for servers ignoring their case\
+    /// the scheme and host are always lowercased and default ports dropped
+    pub lowercase_path: bool,
+    /// settings of single hosts by host name
+    pub hosts: HashMap<String, HostConfig>,
+}
+
+/// settings of one host
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+    /// replaces robots.txt `Crawl-delay`
+    pub crawl_delay_secs: Option<f64>,
+    /// rule, URL of the host matching any are not crawled
+    pub exclude: Vec<String>,
+    /// replace the settings of the same name for URL of the host
+    pub drop_params: Option<String>,
+    pub strip_fragment: Option<bool>,
+    pub strip_trailing_slash: Option<bool>,
+    pub lowercase_path: Option<bool>,
+}
+
+impl HostConfig {
+    /// whether any URL canonicalization setting is replaced
+    fn canonicalizes(&self) -> bool {
+        self.drop_params.is_some()
+            || self.strip_fragment.is_some()
+            || self.strip_trailing_slash.is_some()
+            || self.lowercase_path.is_some()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            seeds: Vec::new(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            include_files: Vec::new(),
+            exclude_files: Vec::new(),
+            concurrency: 32,
+            user_agent: USER_AGENT.to_owned(),
+            output_dir: PathBuf::from("."),
+            connect_timeout_secs: 5.0,
+            request_timeout_secs: None,
+            save_attempts: 3,
+            save_retry_secs: 5.0,
+            retry_attempts: 3,
+            retry_base_secs: 1.0,
+            retry_max_secs: 60.0,
+            crawl_delay_secs: None,
+            max_body_bytes: SizeLimits::default().default,
+            max_body_bytes_by_type: SizeLimits::default().types,
+            byte_budget: None,
+            routes: HashMap::new(),
+            robots_meta: true,
+            save_noindex: false,
+            link_kinds: None,
+            seen: SeenBackend::default().to_string(),
+            near_dup: None,
+            drop_params: None,
+            strip_fragment: true,
+            strip_trailing_slash: true,
+            lowercase_path: false,
+            hosts: HashMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// parse and validate TOML
+    /// # return
+    /// every problem found, one each line
+    pub fn parse(toml: &str) -> Result<Config, String> {
+        let config: Config = toml::from_str(toml).map_err(|e| e.to_string())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// read the file at `path`, take its relative paths from `dir`, then validate
+    /// # return
+    /// the problems found, prefixed with `path`
+    pub fn load<P: AsRef<Path>>(path: P, dir: &Path) -> Result<Config, String> {
+        let path = path.as_ref().display();
+        let prefixed = |e: String| {
+            e.lines()
+                .map(|line| format!("{}: {}", path, line))
+                .collect::<Vec<_>>()
+                .join("\n")
+        };
+        let toml = read_to_string(path.to_string()).map_err(|e| format!("{}: {}", path, e))?;
+        let mut config: Config = toml::from_str(&toml).map_err(|e| prefixed(e.to_string()))?;
+        config.resolve_paths(dir);
+        config.validate().map_err(prefixed)?;
+        Ok(config)
+    }
+
+    /// take `output_dir` and the rule files relative to `dir`
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        self.output_dir = dir.join(&self.output_dir);
+        for file in self.include_files.iter_mut().chain(&mut self.exclude_files) {
+            *file = dir.join(&*file);
+        }
+    }
+
+    /// check every value so the accessors below cannot fail
+    /// # return
+    /// every problem found, one each line
+    pub fn validate(&self) -> Result<(), String> {
+        let mut errors = Vec::new();
+        for (i, seed) in self.seeds.iter().enumerate() {
+            if let Err(e) = Url::parse(seed) {
+                errors.push(format!("seeds[{}] `{}`: {}", i, seed, e));
+            }
+        }
+        let mut rules = vec![
+            ("include".to_owned(), &self.include),
+            ("exclude".to_owned(), &self.exclude),
+        ];
+        for (host, host_config) in &self.hosts {
+            let key = format!("hosts.\"{}\".exclude", host);
+            rules.push((key, &host_config.exclude));
+        }
+        for (key, lines) in rules {
+            if let Err(e) = inline_rules(&key, lines) {
+                errors.extend(e.lines().map(str::to_owned));
+            }
+        }
+        for path in self.include_files.iter().chain(&self.exclude_files) {
+            if let Err(e) = read_rules(path) {
+                errors.extend(e.lines().map(str::to_owned));
+            }
+        }
+        if errors.is_empty() {
+            // every rule parses, but the lists may still not compile
+            let lists = self
+                .hosts
+                .iter()
+                .map(|(host, host_config)| host_exclude(host, &host_config.exclude).map(drop));
+            for result in lists.chain([self.rule_filter().map(drop)]) {
+                if let Err(e) = result {
+                    errors.push(e.replace('\n', " "));
+                }
+            }
+        }
+        let mut drop_params = vec![("drop_params".to_owned(), &self.drop_params)];
+        for (host, host_config) in &self.hosts {
+            let key = format!("hosts.\"{}\".drop_params", host);
+            drop_params.push((key, &host_config.drop_params));
+        }
+        for (key, regex) in drop_params {
+            if let Some(Err(e)) = regex.as_deref().map(Regex::new) {
+                // the regex error spans several lines
+                errors.push(format!("{}: {}", key, e.to_string().replace('\n', " ")));
+            }
+        }
+
+        if self.concurrency == 0 {
+            errors.push("concurrency: must be at least 1".to_owned());
+        }
+        if self.save_attempts == 0 {
+            errors.push("save_attempts: must be at least 1".to_owned());
+        }
+        if self.retry_attempts == 0 {
+            errors.push("retry_attempts: must be at least 1".to_owned());
+        }
+        if self.retry_max_secs < self.retry_base_secs {
+            errors.push("retry_max_secs: must not be below retry_base_secs".to_owned());
+        }
+        if self.max_body_bytes == 0 {
+            errors.push("max_body_bytes: must be at least 1".to_owned());
+        }
+        for (mime, bytes) in &self.max_body_bytes_by_type {
+            let key = format!("max_body_bytes_by_type.\"{}\"", mime);
+            if !is_type_pattern(mime) {
+                errors.push(format!("{}: not `type/subtype` nor `type/*`", key));
+            }
+            if *bytes == 0 {
+                errors.push(format!("{}: must be at least 1", key));
+            }
+        }
+        for (mime, action) in &self.routes {
+            let key = format!("routes.\"{}\"", mime);
+            if mime != "*/*" && !is_type_pattern(mime) {
+                errors.push(format!("{}: not `type/subtype`, `type/*` nor `*/*`", key));
+            }
+            if let Err(e) = action.parse::<Action>() {
+                errors.push(format!("{}: {}", key, e));
+            }
+        }
+        for (i, kind) in self.link_kinds.iter().flatten().enumerate() {
+            if let Err(e) = kind.parse::<LinkKind>() {
+                errors.push(format!("link_kinds[{}]: {}", i, e));
+            }
+        }
+        if self.byte_budget == Some(0) {
+            errors.push("byte_budget: must be at least 1".to_owned());
+        }
+        if self.user_agent.trim().is_empty() {
+            errors.push("user_agent: must not be empty".to_owned());
+        }
+        let mut durations = vec![
+            (
+                "connect_timeout_secs".to_owned(),
+                Some(self.connect_timeout_secs),
+                true,
+            ),
+            (
+                "request_timeout_secs".to_owned(),
+                self.request_timeout_secs,
+                true,
+            ),
+            (
+                "save_retry_secs".to_owned(),
+                Some(self.save_retry_secs),
+                false,
+            ),
+            ("crawl_delay_secs".to_owned(), self.crawl_delay_secs, false),
+            (
+                "retry_base_secs".to_owned(),
+                Some(self.retry_base_secs),
+                false,
+            ),
+            (
+                "retry_max_secs".to_owned(),
+                Some(self.retry_max_secs),
+                false,
+            ),
+        ];
+        for (host, host_config) in &self.hosts {
+            let key = format!("hosts.\"{}\".crawl_delay_secs", host);
+            durations.push((key, host_config.crawl_delay_secs, false));
+        }
+        for (key, secs, positive) in durations {
+            match secs {
+                Some(secs) if positive && !(secs > 0.0 && secs.is_finite()) => {
+                    errors.push(format!("{}: {} is not a positive number", key, secs))
+                }
+                Some(secs) if !(secs >= 0.0 && secs.is_finite()) => {
+                    errors.push(format!("{}: {} is not zero or positive", key, secs))
+                }
+                Some(secs) if secs >= Duration::MAX.as_secs_f64() => {
+                    errors.push(format!("{}: {} is too large", key, secs))
+                }
+                _ => {}
+            }
+        }
+
+        if let Err(e) = self.seen.parse::<SeenBackend>() {
+            errors.push(format!("seen: {}", e));
+        }
+        if let Some(Err(e)) = self.near_dup.as_ref().map(|s| s.parse::<NearDupConfig>()) {
+            errors.push(format!("near_dup: {}", e));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            errors.sort();
+            Err(errors.join("\n"))
+        }
+    }
+
+    pub fn seed_urls(&self) -> Vec<Url> {
+        self.seeds.iter().map(|s| Url::parse(s).unwrap()).collect()
+    }
+
+    /// the rules of `include`, `exclude` and their files as one filter
+    /// # return
+    /// the problems reading the files
+    pub fn rule_filter(&self) -> Result<RuleFilter, String> {
+        let list = |key: &str, lines: &[String], files: &[PathBuf]| {
+            let mut rules = inline_rules(key, lines)?;
+            for path in files {
+                rules.extend(read_rules(path)?);
+            }
+            RuleList::new(rules).map_err(|e| format!("{}: {}", key, e))
+        };
+        Ok(RuleFilter {
+            include: list("include", &self.include, &self.include_files)?,
+            exclude: list("exclude", &self.exclude, &self.exclude_files)?,
+        })
+    }
+
+    /// `inner` with the settings of `hosts`
+    pub fn host_rules<F>(&self, inner: F) -> HostRules<F> {
+        let hosts = self
+            .hosts
+            .iter()
+            .map(|(host, host_config)| {
+                let rule = HostRule {
+                    crawl_delay: host_config.crawl_delay_secs.map(Duration::from_secs_f64),
+                    exclude: host_exclude(host, &host_config.exclude).unwrap(),
+                };
+                (host.to_lowercase(), rule)
+            })
+            .collect();
+        HostRules { inner, hosts }
+    }
+
+    /// robots directives honored for `user_agent` if `robots_meta`
+    pub fn robots_meta(&self) -> Option<RobotsMeta> {
+        self.robots_meta.then(|| RobotsMeta {
+            user_agent: self.user_agent.clone(),
+            save_noindex: self.save_noindex,
+        })
+    }
+
+    /// `inner` following the links of `link_kinds`
+    pub fn link_kinds<F>(&self, inner: F) -> LinkKinds<F> {
+        let kinds = match &self.link_kinds {
+            Some(kinds) => kinds.iter().map(|kind| kind.parse().unwrap()).collect(),
+            None => LinkKind::DEFAULT.into(),
+        };
+        LinkKinds { inner, kinds }
+    }
+
+    /// client builder with the user agent and timeouts
+    pub fn client_builder(&self) -> ClientBuilder {
+        let builder = Client::builder()
+            .connect_timeout(Duration::from_secs_f64(self.connect_timeout_secs))
+            .user_agent(&self.user_agent);
+        match self.request_timeout_secs {
+            Some(secs) => builder.timeout(Duration::from_secs_f64(secs)),
+            None => builder,
+        }
+    }
+
+    /// client used by the crawlers\
+    /// follows redirections and ignores invalid certificates
+    pub fn crawler_client(&self) -> reqwest::Result<Client> {
+        self.client_builder()
+            .danger_accept_invalid_certs(true) // ignore certificate
+            .build()
+    }
+
+    /// `RobotsCache` fetching with `crawler_client` for `user_agent`,
+    /// retried as `retry_policy`
+    pub fn robots_cache(&self) -> reqwest::Result<RobotsCache> {
+        Ok(RobotsCache::new(
+            self.crawler_client()?,
+            &self.user_agent,
+            self.retry_policy(),
+        ))
+    }
+
+    /// `HashedFileSink` in `output_dir`
+    pub fn hashed_file_sink(&self) -> HashedFileSink {
+        HashedFileSink {
+            dir: self.output_dir.clone(),
+            attempts: self.save_attempts,
+            retry_delay: self.save_retry_delay(),
+        }
+    }
+
+    pub fn crawl_delay(&self) -> Option<Duration> {
+        self.crawl_delay_secs.map(Duration::from_secs_f64)
+    }
+
+    pub fn save_retry_delay(&self) -> Duration {
+        Duration::from_secs_f64(self.save_retry_secs)
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            attempts: self.retry_attempts,
+            base_delay: Duration::from_secs_f64(self.retry_base_secs),
+            max_delay: Duration::from_secs_f64(self.retry_max_secs),
+        }
+    }
+
+    /// size limits with lowercase types
+    pub fn size_limits(&self) -> SizeLimits {
+        SizeLimits {
+            default: self.max_body_bytes,
+            types: self
+                .max_body_bytes_by_type
+                .iter()
+                .map(|(mime, bytes)| (mime.to_ascii_lowercase(), *bytes))
+                .collect(),
+        }
+    }
+
+    /// downloads into `PARTIAL_DIR` in `output_dir`, with a new byte budget
+    pub fn downloads(&self) -> Downloads {
+        Downloads::new(
+            self.output_dir.join(PARTIAL_DIR),
+            self.size_limits(),
+            self.byte_budget,
+        )
+    }
+
+    /// routing table with lowercase types
+    pub fn routes(&self) -> Routes {
+        Routes {
+            actions: self
+                .routes
+                .iter()
+                .map(|(mime, action)| (mime.to_ascii_lowercase(), action.parse().unwrap()))
+                .collect(),
+        }
+    }
+
+    pub fn seen_backend(&self) -> SeenBackend {
+        self.seen.parse().unwrap()
+    }
+
+    pub fn near_dup_config(&self) -> Option<NearDupConfig> {
+        self.near_dup.as_ref().map(|s| s.parse().unwrap())
+    }
+
+    /// the default canonicalizer with the settings given,
+    /// replaced for the hosts setting any
+    pub fn canonicalizer(&self) -> Canonicalizer {
+        let mut canonicalizer = Canonicalizer {
+            strip_fragment: self.strip_fragment,
+            strip_trailing_slash: self.strip_trailing_slash,
+            lowercase_path: self.lowercase_path,
+            ..Canonicalizer::default()
+        };
+        if let Some(drop_params) = &self.drop_params {
+            canonicalizer.drop_params = Some(Regex::new(drop_params).unwrap());
+        }
+        let hosts = self
+            .hosts
+            .iter()
+            .filter(|(_, host_config)| host_config.canonicalizes())
+            .map(|(host, host_config)| {
+                let mut host_canonicalizer = canonicalizer.clone();
+                if let Some(drop_params) = &host_config.drop_params {
+                    host_canonicalizer.drop_params = Some(Regex::new(drop_params).unwrap());
+                }
+                let or = |setting: Option<bool>, default| setting.unwrap_or(default);
+                host_canonicalizer.strip_fragment =
+                    or(host_config.strip_fragment, self.strip_fragment);
+                host_canonicalizer.strip_trailing_slash =
+                    or(host_config.strip_trailing_slash, self.strip_trailing_slash);
+                host_canonicalizer.lowercase_path =
+                    or(host_config.lowercase_path, self.lowercase_path);
+                (host.to_lowercase(), host_canonicalizer)
+            })
+            .collect();
+        canonicalizer.hosts = hosts;
+        canonicalizer
+    }
+}
+
+/// the `exclude` list of `hosts.host`
+/// # return
+/// the problems parsing or compiling the rules
+fn host_exclude(host: &str, lines: &[String]) -> Result<RuleList, String> {
+    let key = format!("hosts.\"{}\".exclude", host);
+    let rules = inline_rules(&key, lines)?;
+    RuleList::new(rules).map_err(|e| format!("{}: {}", key, e))
+}
+
+/// parse the rules of config `key`, each with origin `key[i]`
+/// # return
+/// every problem found, one each line
+fn inline_rules(key: &str, lines: &[String]) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let origin = format!("{}[{}]", key, i);
+        match Rule::parse(line, origin.clone()) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => errors.push(format!("{}: {}", origin, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(rules)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
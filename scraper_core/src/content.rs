@@ -0,0 +1,105 @@
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, fmt, sync::Mutex};
+
+/// sha256 of `body`, the name its content is stored under
+pub fn content_hash(body: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(body))
+}
+
+/// `content_hash` of a body received in chunks
+#[derive(Debug, Clone, Default)]
+pub struct ContentHasher(Sha256);
+
+impl ContentHasher {
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.0.update(chunk);
+    }
+
+    pub fn finish(self) -> String {
+        format!("{:x}", self.0.finalize())
+    }
+}
+
+/// URL serving identical content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cluster {
+    pub hash: String,
+    /// sorted
+    pub urls: Vec<Url>,
+}
+
+/// `hash (n URL)` then one URL each line, indented
+impl fmt::Display for Cluster {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({} URL)", self.hash, self.urls.len())?;
+        for url in &self.urls {
+            write!(f, "\n  {}", url)?;
+        }
+        Ok(())
+    }
+}
+
+/// clusters sorted by size, largest first, then by hash
+pub fn sort_clusters(clusters: &mut [Cluster]) {
+    clusters.sort_by(|a, b| b.urls.len().cmp(&a.urls.len()).then(a.hash.cmp(&b.hash)));
+}
+
+/// every cluster separated by an empty line
+pub fn report(clusters: &[Cluster]) -> String {
+    clusters
+        .iter()
+        .map(|cluster| format!("{}\n", cluster))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// the URL each content hash was fetched from during a crawl
+#[derive(Debug, Default)]
+pub struct ContentIndex {
+    urls: Mutex<HashMap<String, Vec<Url>>>,
+}
+
+impl ContentIndex {
+    /// record that `url` served the content hashed to `hash`
+    /// # return
+    /// `true` if another URL served it first
+    pub fn insert(&self, hash: &str, url: &Url) -> bool {
+        let mut urls = self.urls.lock().unwrap();
+        let urls = urls.entry(hash.to_owned()).or_default();
+        if !urls.contains(url) {
+            urls.push(url.clone());
+        }
+        urls[0] != *url
+    }
+
+    /// number of distinct contents
+    pub fn len(&self) -> usize {
+        self.urls.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// contents served by more than one URL, see `sort_clusters`
+    pub fn clusters(&self) -> Vec<Cluster> {
+        let mut clusters: Vec<Cluster> = self
+            .urls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, urls)| urls.len() > 1)
+            .map(|(hash, urls)| {
+                let mut urls = urls.clone();
+                urls.sort();
+                Cluster {
+                    hash: hash.clone(),
+                    urls,
+                }
+            })
+            .collect();
+        sort_clusters(&mut clusters);
+        clusters
+    }
+}
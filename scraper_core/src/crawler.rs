@@ -0,0 +1,706 @@
+use reqwest::{header::HeaderMap, StatusCode, Url};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    fs::{read, remove_file},
+    select, spawn,
+    sync::watch,
+    task::spawn_blocking,
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    canonical::Canonicalizer,
+    content::ContentIndex,
+    download::{Aborted, Body, DownloadError, Downloads},
+    extract::{body_text, visible_text},
+    fetcher::Fetcher,
+    filter::Filter,
+    frontier::{host_key, Pop, ShardedFrontier},
+    links::{canonical_link, css_links, text_links, Link, LinkExtractor, LinkKind},
+    meta_robots::{Directives, RobotsMeta},
+    mime::{choose_extension, content_type},
+    near_dup::NearDuplicates,
+    retry::{classify_error, classify_status, retry_after, FailureKind, Retries, RetryPolicy},
+    routes::{Action, Routes},
+    seen::{SeenBackend, SeenSet},
+    sink::{Fetched, StorageSink},
+};
+
+/// crawl state shared by every worker
+#[derive(Clone)]
+pub struct SharedState {
+    pub link_waitlist: Arc<ShardedFrontier>,
+    pub known_url: Arc<SeenSet>,
+    /// URL each content fetched came from
+    pub content: Arc<ContentIndex>,
+    /// failures of the URL being retried
+    pub retries: Arc<Retries>,
+    /// sent after every change of `link_waitlist` that may let a worker go on
+    frontier_changed: Arc<watch::Sender<()>>,
+}
+
+impl SharedState {
+    pub fn new(known_url: SeenSet, link_waitlist: ShardedFrontier) -> SharedState {
+        SharedState {
+            link_waitlist: Arc::new(link_waitlist),
+            known_url: Arc::new(known_url),
+            content: Arc::new(ContentIndex::default()),
+            retries: Arc::new(Retries::default()),
+            frontier_changed: Arc::new(watch::channel(()).0),
+        }
+    }
+
+    /// wake the workers waiting for `link_waitlist` to change
+    fn notify_frontier_changed(&self) {
+        self.frontier_changed.send_replace(());
+    }
+
+    /// start from `url` alone
+    pub fn from_url(url: Url) -> SharedState {
+        SharedState::from_seeds(vec![url], SeenBackend::Exact, None)
+    }
+
+    /// start from `seeds`, remembered with `seen`\
+    /// `crawl_delay` overrides robots.txt `Crawl-delay` for every host
+    pub fn from_seeds(
+        seeds: Vec<Url>,
+        seen: SeenBackend,
+        crawl_delay: Option<Duration>,
+    ) -> SharedState {
+        let link_waitlist = ShardedFrontier::new(crawl_delay);
+        let known_url = seeds.iter().map(|url| (url.clone(), false)).collect();
+        for url in seeds {
+            link_waitlist.push_back(url);
+        }
+        SharedState::new(SeenSet::new(seen, known_url), link_waitlist)
+    }
+
+    /// known URL and every URL not finished, in-flight ones first\
+    /// URL seen but not pushed yet are added to the end,
+    /// every URL of the waitlist is unchecked\
+    /// workers may keep going while it is taken
+    pub fn snapshot(&self) -> (HashMap<Url, bool>, Vec<Url>) {
+        // seen set first: URL pushed after it are in the waitlist
+        let mut known_url = self.known_url.snapshot();
+        let mut waitlist = self.link_waitlist.snapshot();
+
+        let queued: HashSet<&Url> = waitlist.iter().collect();
+        let mut unqueued: Vec<Url> = known_url
+            .iter()
+            .filter(|(url, checked)| !**checked && !queued.contains(url))
+            .map(|(url, _)| url.clone())
+            .collect();
+        unqueued.sort();
+        waitlist.extend(unqueued);
+        for url in &waitlist {
+            known_url.insert(url.clone(), false);
+        }
+
+        (known_url, waitlist)
+    }
+}
+
+/// the pluggable parts of a crawl
+#[derive(Clone)]
+pub struct Plugins {
+    pub fetcher: Arc<dyn Fetcher>,
+    pub extractor: Arc<dyn LinkExtractor>,
+    pub filter: Arc<dyn Filter>,
+    pub sink: Arc<dyn StorageSink>,
+    /// applied to every URL before it is filtered or looked up
+    pub canonicalizer: Arc<Canonicalizer>,
+    /// links of families yielding near-duplicate pages are dropped if set
+    pub near_duplicates: Option<Arc<NearDuplicates>>,
+    /// when URL failing for a while are fetched again
+    pub retry: RetryPolicy,
+    /// where bodies are downloaded to, their size limits and the byte budget
+    pub downloads: Arc<Downloads>,
+    /// what is done with responses other than HTML by content type
+    pub routes: Arc<Routes>,
+    /// stores the responses routed to `Action::Image` instead of `sink` if set
+    pub images: Option<Arc<dyn StorageSink>>,
+    /// robots directives of pages and `rel="nofollow"` are honored if set
+    pub robots_meta: Option<Arc<RobotsMeta>>,
+}
+
+impl Plugins {
+    /// directives of `X-Robots-Tag` in `headers`, none if not honored
+    fn header_directives(&self, headers: &HeaderMap) -> Directives {
+        self.robots_meta
+            .as_ref()
+            .map(|robots_meta| robots_meta.from_headers(headers))
+            .unwrap_or_default()
+    }
+
+    /// whether `url` belongs to a link family not followed any more
+    fn is_exhausted(&self, url: &Url) -> bool {
+        matches!(
+            &self.near_duplicates,
+            Some(near_duplicates) if near_duplicates.is_exhausted(url)
+        )
+    }
+}
+
+/// crawl with `process_num` workers until the waitlist is empty
+/// and no URL is in flight\
+/// the crawl delay of the hosts waiting is looked up first
+/// # return
+/// number of URL processed successfully
+pub async fn crawl_parallel(process_num: usize, state: &SharedState, plugins: &Plugins) -> usize {
+    // the hosts of the seeds or resumed URL, before any is fetched
+    let mut hosts = HashSet::new();
+    for url in state.link_waitlist.snapshot() {
+        if hosts.insert(host_key(&url)) {
+            let crawl_delay = plugins.filter.crawl_delay(&url).await;
+            state.link_waitlist.set_crawl_delay(&url, crawl_delay);
+        }
+    }
+
+    // spawn `process_num` async processes
+    let mut handles = Vec::new();
+    for process_id in 0..process_num {
+        let state = state.clone();
+        let plugins = plugins.clone();
+        handles.push(spawn(async move {
+            // create a crawler and crawl
+            let mut crawler = CrawlerParallel::new(state, plugins, process_id);
+            crawler.crawl().await;
+
+            crawler.processed_count // return the processed count
+        }));
+    }
+
+    // count total URL processed successfully
+    let mut total_processed_count = 0usize;
+    for handle in handles {
+        match handle.await {
+            Ok(processed_count) => {
+                total_processed_count += processed_count;
+            }
+            Err(err) => {
+                println!("Master joining handle: {}", err)
+            }
+        }
+    }
+    total_processed_count
+}
+
+/// what a worker does after looking into the waitlist
+enum Next {
+    /// process `url`
+    Process,
+    /// look into the waitlist again
+    Retry,
+    /// nothing left to crawl
+    Exit,
+}
+
+pub struct CrawlerParallel {
+    state: SharedState,
+    plugins: Plugins,
+    process_id: usize,
+    pub processed_count: usize,
+    /// changes of the waitlist not seen yet
+    frontier_changed: watch::Receiver<()>,
+    url: Url,
+    /// canonical URL the response is stored under
+    final_url: Url,
+    /// URL the response came from, not canonicalized,
+    /// relative links are resolved against it
+    base_url: Url,
+    /// `url` was put back into the waitlist to be fetched again
+    retrying: bool,
+}
+
+impl CrawlerParallel {
+    /// construct a new `CrawlerParallel`
+    pub fn new(state: SharedState, plugins: Plugins, process_id: usize) -> CrawlerParallel {
+        let default_url = Url::parse("https://www.google.com/").unwrap(); // used as place holder
+
+        let frontier_changed = state.frontier_changed.subscribe();
+
+        CrawlerParallel {
+            state,
+            plugins,
+            process_id,
+            processed_count: 0,
+            frontier_changed,
+            url: default_url.clone(),
+            final_url: default_url.clone(),
+            base_url: default_url,
+            retrying: false,
+        }
+    }
+
+    /// crawl URL from the waitlist\
+    /// give back URL into it\
+    /// store responses in the sink
+    pub async fn crawl(&mut self) {
+        // repeatedly crawl
+        loop {
+            // get a URL from waitlist, wait for changes if none is ready
+            match self.get_url().await {
+                Next::Process => {}
+                Next::Retry => continue,
+                Next::Exit => break,
+            }
+
+            // process the URL
+            self.retrying = false;
+            self.process_url().await;
+            if !self.retrying {
+                self.state.retries.forget(&self.url);
+            }
+            self.state.link_waitlist.finish(&self.url);
+            self.state.notify_frontier_changed();
+        }
+    }
+
+    /// get a URL from waitlist whose host is not cooling down\
+    /// wait for the waitlist to change if it is empty\
+    /// sleep until a host is ready if all hosts are cooling down
+    /// # return
+    /// `Next::Process` with the URL in `self.url`\
+    /// `Next::Retry` after waiting\
+    /// `Next::Exit` if the waitlist is empty and no URL is in flight
+    async fn get_url(&mut self) -> Next {
+        // any change after this point wakes this worker
+        self.frontier_changed.borrow_and_update();
+
+        if self.plugins.downloads.budget.is_exhausted() {
+            // the URL left stay in the waitlist
+            self.state.notify_frontier_changed(); // wake the others to exit
+            println!("Process {} exits, byte budget exhausted", self.process_id);
+            return Next::Exit;
+        }
+
+        // pop the first URL from waitlist until it was not checked
+        // nor from an exhausted link family, and mark it as checked
+        let known_url = &self.state.known_url;
+        let link_waitlist = &self.state.link_waitlist;
+        let plugins = &self.plugins;
+        let pop = link_waitlist.pop(Instant::now(), self.process_id, |url0| {
+            let checked = known_url.check(url0) == Some(true);
+            checked || plugins.is_exhausted(url0)
+        });
+        if pop == Pop::Empty && link_waitlist.is_drained() {
+            // no URL in flight either, nothing will be added
+            self.state.notify_frontier_changed(); // wake the others to exit
+            println!("Process {} exits", self.process_id);
+            return Next::Exit;
+        }
+
+        match pop {
+            Pop::Ready(url0) => {
+                self.url = url0;
+                Next::Process
+            }
+            Pop::Wait { host, until } => {
+                // every host with waiting URL is cooling down
+                // until the first is ready or a URL is added
+                let start = Instant::now();
+                select! {
+                    _ = sleep_until(until) => {}
+                    _ = self.frontier_changed.changed() => {}
+                }
+                self.state.link_waitlist.add_wait(&host, start.elapsed());
+                Next::Retry
+            }
+            Pop::Empty => {
+                // link_waitlist is empty, wait for the URL in flight
+                // to add links or finish
+                let _ = self.frontier_changed.changed().await;
+                Next::Retry
+            }
+        }
+    }
+
+    /// process the URL given
+    /// - HTTP request
+    /// - check final URL after potential redirection
+    /// - put the URL back into the waitlist if it may work later
+    /// - tell the sink if not modified or failed
+    /// - skip it if the routing table says so
+    /// - download the body within the size limits and byte budget
+    /// - count near-duplicate HTML against its link family
+    /// - follow the links of HTML, or of what is routed to `Action::Links`,
+    ///   unless another URL served the same body or robots directives say `nofollow`
+    /// - store HTML under its `<link rel="canonical">`
+    /// - store the response, with its text if routed to `Action::Text`,
+    ///   unless robots directives say `noindex`
+    /// # return
+    /// `false` normally\
+    /// `true` if something didn't go through, including:
+    /// - the URL is already checked
+    /// - the response status is wrong
+    /// - something went wrong when processing the file
+    async fn process_url(&mut self) -> bool {
+        self.final_url = self.url.clone();
+        // make the request
+        let response = match self.plugins.fetcher.fetch(&self.url).await {
+            Ok(r) => r,
+            Err(err) => {
+                println!(
+                    "Process {} response: {} | {}",
+                    self.process_id, err, self.url
+                );
+                self.retry_later(classify_error(&err), None);
+                return true;
+            }
+        };
+        // URL after potential redirection
+        self.base_url = response.url().clone();
+        self.final_url = self.plugins.canonicalizer.canonicalize(&self.base_url);
+
+        // check the final URL after potential redirection
+        if self.final_url != self.url && self.check_final_url().await {
+            return true;
+        }
+
+        // unchanged since the last fetch
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            if let Err(e) = self.plugins.sink.not_modified(&self.final_url).await {
+                println!(
+                    "Process {} not modified: {} | {}",
+                    self.process_id, e, self.final_url
+                );
+                return true;
+            }
+            self.processed_count += 1;
+            return false;
+        }
+
+        // check response status
+        if status.is_client_error() || status.is_server_error() {
+            let retry_after = retry_after(response.headers());
+            if !self.retry_later(classify_status(status), retry_after) {
+                self.plugins.sink.failed(&self.final_url, status).await;
+            }
+            return true;
+        }
+
+        // HTML is read as text and its links followed,
+        // anything else goes where the routing table says
+        let mime = content_type(response.headers());
+        let is_html = mime.as_deref() == Some("text/html");
+        let action = if is_html {
+            Action::Links
+        } else {
+            self.plugins.routes.action(mime.as_deref())
+        };
+        if action == Action::Skip {
+            println!(
+                "Process {}: skipped {} | {}",
+                self.process_id,
+                mime.as_deref().unwrap_or("untyped response"),
+                self.final_url
+            );
+            self.plugins
+                .sink
+                .aborted(&self.final_url, status, Aborted::Skipped)
+                .await;
+            return true;
+        }
+
+        // nothing to store nor follow, the body is not needed
+        let directives = self.plugins.header_directives(response.headers());
+        if directives.noindex && directives.nofollow && !self.saves_noindex() {
+            self.skip_noindex(status).await;
+            return true;
+        }
+
+        let headers = response.headers().clone();
+        let body = match self.plugins.downloads.download(response, is_html).await {
+            Ok(body) => body,
+            Err(err) => {
+                println!(
+                    "Process {} body: {} | {}",
+                    self.process_id, err, self.final_url
+                );
+                self.download_failed(err, status).await;
+                return true;
+            }
+        };
+        let done = self
+            .process_body(status, &headers, mime.as_deref(), action, directives, &body)
+            .await;
+        // kept only if the sink moved it away
+        let _ = remove_file(&body.path).await;
+        done
+    }
+
+    /// follow the links of the downloaded `body` of type `mime`,
+    /// and store it according to `action` and the robots `directives`
+    /// of its headers and HTML
+    /// # return
+    /// same as `process_url`
+    async fn process_body(
+        &mut self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        mime: Option<&str>,
+        action: Action,
+        mut directives: Directives,
+        body: &Body,
+    ) -> bool {
+        if let (Some(html), Some(robots_meta)) = (&body.html, &self.plugins.robots_meta) {
+            directives.merge(robots_meta.from_html(html));
+        }
+
+        // check each link and add to known_url and link_waitlist
+        // the links of a duplicate were found from the first URL serving it
+        let duplicate = self
+            .state
+            .content
+            .insert(&body.content_hash, &self.final_url);
+        let follow = !duplicate && !directives.nofollow;
+        if let (Some(html), true) = (&body.html, follow) {
+            if let Some(near_duplicates) = &self.plugins.near_duplicates {
+                near_duplicates.observe(&self.final_url, &visible_text(html));
+            }
+            let links = self.plugins.extractor.extract(&self.base_url, html);
+            self.process_links(links).await;
+        }
+
+        // the body itself is needed for the links or text of other types
+        let mut text = None;
+        if body.html.is_none() && matches!(action, Action::Links | Action::Text) {
+            let bytes = match read(&body.path).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    println!(
+                        "Process {} read body: {} | {}",
+                        self.process_id, e, self.final_url
+                    );
+                    return true;
+                }
+            };
+            if action == Action::Links && follow {
+                let content = String::from_utf8_lossy(&bytes);
+                let links = match mime {
+                    Some("text/css") => css_links(&self.base_url, &content),
+                    _ => text_links(&content),
+                };
+                let links = links
+                    .into_iter()
+                    .map(|url| Link::new(url, LinkKind::Text))
+                    .collect();
+                self.process_links(links).await;
+            }
+            if action == Action::Text {
+                let mime = mime.unwrap_or_default().to_owned();
+                // parsing a PDF takes a while
+                text = spawn_blocking(move || body_text(&mime, &bytes))
+                    .await
+                    .ok()
+                    .flatten();
+            }
+        }
+
+        // the page names another URL as the one to keep
+        if let Some(html) = &body.html {
+            if self.check_canonical_link(html).await {
+                return true;
+            }
+        }
+        if directives.noindex && !self.saves_noindex() {
+            self.skip_noindex(status).await;
+            return true;
+        }
+
+        let extension = if body.html.is_some() {
+            ".html".to_owned()
+        } else {
+            let head = body.head().await.unwrap_or_default();
+            choose_extension(headers, &self.final_url, &head)
+        };
+        let page = Fetched {
+            url: &self.url,
+            final_url: &self.final_url,
+            status,
+            headers,
+            extension: &extension,
+            body,
+            text: text.as_deref(),
+            noindex: directives.noindex,
+        };
+        let sink = match (&self.plugins.images, action) {
+            (Some(images), Action::Image) => images,
+            _ => &self.plugins.sink,
+        };
+        if let Err(e) = sink.save(&page).await {
+            println!(
+                "Process {} save: {} | {}",
+                self.process_id, e, self.final_url
+            );
+            return true;
+        }
+
+        self.processed_count += 1;
+
+        false
+    }
+
+    /// whether pages saying `noindex` are stored anyway
+    fn saves_noindex(&self) -> bool {
+        matches!(&self.plugins.robots_meta, Some(robots_meta) if robots_meta.save_noindex)
+    }
+
+    /// tell the sink the response of `final_url` is not stored for `noindex`
+    async fn skip_noindex(&self, status: StatusCode) {
+        println!(
+            "Process {}: skipped noindex | {}",
+            self.process_id, self.final_url
+        );
+        self.plugins
+            .sink
+            .aborted(&self.final_url, status, Aborted::NoIndex)
+            .await;
+    }
+
+    /// put `url` back into the waitlist if its download may work later,
+    /// otherwise tell the sink it answered `status` but was aborted
+    async fn download_failed(&mut self, err: DownloadError, status: StatusCode) {
+        let aborted = match err {
+            DownloadError::Aborted(Aborted::OverBudget) => {
+                // fetched again when the crawl resumes
+                self.requeue(Duration::ZERO);
+                return;
+            }
+            DownloadError::Aborted(aborted) => aborted,
+            DownloadError::Broken { error, received } => {
+                if self.retry_later(classify_error(&error), None) {
+                    return;
+                }
+                Aborted::Truncated { received }
+            }
+            DownloadError::Io(err) => Aborted::Unwritable { kind: err.kind() },
+        };
+        self.plugins
+            .sink
+            .aborted(&self.final_url, status, aborted)
+            .await;
+    }
+
+    /// put `url` back into the waitlist after a `failure`
+    /// if it is retryable and `url` has attempts left
+    /// # return
+    /// `true` if `url` will be fetched again
+    fn retry_later(&mut self, failure: FailureKind, retry_after: Option<Duration>) -> bool {
+        if failure == FailureKind::Permanent {
+            return false;
+        }
+        let failures = self.state.retries.fail(&self.url);
+        let delay = match self.plugins.retry.next_delay(failures, retry_after) {
+            Some(delay) => delay,
+            None => {
+                println!(
+                    "Process {}: giving up after {} failures | {}",
+                    self.process_id, failures, self.url
+                );
+                return false;
+            }
+        };
+        println!(
+            "Process {}: retrying in {:.1}s | {}",
+            self.process_id,
+            delay.as_secs_f64(),
+            self.url
+        );
+
+        self.requeue(delay);
+        true
+    }
+
+    /// put `url` back into the waitlist to be fetched after `delay`\
+    /// nothing is fetched from its host until then
+    fn requeue(&mut self, delay: Duration) {
+        // checked when popped or redirected to, fetched again from `url`
+        self.state.known_url.uncheck(&self.url);
+        if self.final_url != self.url {
+            self.state.known_url.uncheck(&self.final_url);
+        }
+        self.state.link_waitlist.push_retry(self.url.clone(), delay);
+        self.retrying = true;
+    }
+
+    /// check if `final_url` is already checked\
+    /// record new URL as checked
+    /// # return
+    /// `false` for new and allowed URL\
+    /// `true` for checked or filtered URL
+    async fn check_final_url(&self) -> bool {
+        // check the filter before taking the lock
+        let allowed = self.plugins.filter.allows(&self.final_url).await;
+
+        // mark this URL as checked
+        match self.state.known_url.check(&self.final_url) {
+            //* this URL is already checked, skip
+            Some(true) => true,
+            Some(false) => false,
+            // filter this newly found URL
+            None => !allowed,
+        }
+    }
+
+    /// use the `<link rel="canonical">` of `html` as `final_url`
+    /// if it is allowed and not checked yet, and mark it as checked
+    /// # return
+    /// `false` normally\
+    /// `true` if the canonical URL is already checked
+    async fn check_canonical_link(&mut self, html: &str) -> bool {
+        let canonical = match canonical_link(&self.base_url, html) {
+            Some(url) => self.plugins.canonicalizer.canonicalize(&url),
+            None => return false,
+        };
+        if canonical == self.final_url || !self.plugins.filter.allows(&canonical).await {
+            return false;
+        }
+
+        if self.state.known_url.check(&canonical) == Some(true) {
+            return true;
+        }
+        self.final_url = canonical;
+        false
+    }
+
+    /// process and consume the links from HTML\
+    /// links of a kind not followed are ignored,
+    /// so are `rel="nofollow"` ones if robots directives are honored
+    async fn process_links(&self, links: Vec<Link>) {
+        let known_url = &self.state.known_url;
+        let link_waitlist = &self.state.link_waitlist;
+
+        let mut pushed = false;
+        for link in links {
+            let nofollow = link.nofollow && self.plugins.robots_meta.is_some();
+            if nofollow || !self.plugins.filter.follows(link.kind) {
+                continue;
+            }
+            let link = self.plugins.canonicalizer.canonicalize(&link.url);
+            if known_url.get(&link).is_some() {
+                continue;
+            }
+            if !self.plugins.is_exhausted(&link) && self.plugins.filter.allows(&link).await {
+                let crawl_delay = self.plugins.filter.crawl_delay(&link).await;
+                // record and add to waitlist if still unknown
+                if known_url.insert_new(&link, false) {
+                    link_waitlist.set_crawl_delay(&link, crawl_delay);
+                    link_waitlist.push_back(link);
+                    pushed = true;
+                }
+            } else {
+                // filtered or from an exhausted family, mark as checked
+                known_url.insert_new(&link, true);
+            }
+        }
+        if pushed {
+            self.state.notify_frontier_changed();
+        }
+    }
+}
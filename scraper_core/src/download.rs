@@ -0,0 +1,317 @@
+use encoding_rs::{Encoding, UTF_8};
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    Response,
+};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+};
+use tokio::{
+    fs::{create_dir_all, remove_dir_all, remove_file, File},
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+
+use crate::{
+    content::{content_hash, ContentHasher},
+    mime::{content_type, lookup},
+};
+
+/// folder of the bodies being downloaded, inside the folder they are saved in
+/// so they can be renamed into place
+pub const PARTIAL_DIR: &str = ".partial";
+
+const MIB: u64 = 1024 * 1024;
+
+/// bytes read to guess the type of a body
+const SNIFF_LEN: usize = 512;
+
+/// largest body downloaded of each content type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeLimits {
+    /// bytes of the types not in `types`
+    pub default: u64,
+    /// bytes by `type/subtype` or `type/*`
+    pub types: HashMap<String, u64>,
+}
+
+impl Default for SizeLimits {
+    /// 10 MiB of HTML, 100 MiB of anything else
+    fn default() -> SizeLimits {
+        SizeLimits {
+            default: 100 * MIB,
+            types: HashMap::from([("text/html".to_owned(), 10 * MIB)]),
+        }
+    }
+}
+
+impl SizeLimits {
+    /// bytes allowed for `mime`, `type/subtype` before `type/*`
+    pub fn limit(&self, mime: Option<&str>) -> u64 {
+        mime.and_then(|mime| lookup(&self.types, mime))
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+/// bytes the whole crawl may download
+#[derive(Debug, Default)]
+pub struct ByteBudget {
+    /// no limit if `None`
+    limit: Option<u64>,
+    used: AtomicU64,
+    exhausted: AtomicBool,
+}
+
+impl ByteBudget {
+    pub fn new(limit: Option<u64>) -> ByteBudget {
+        ByteBudget {
+            limit,
+            ..ByteBudget::default()
+        }
+    }
+
+    /// count `bytes` more downloaded
+    /// # return
+    /// `false` if that is over the limit, which exhausts the budget
+    pub fn take(&self, bytes: u64) -> bool {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => {
+                self.used.fetch_add(bytes, Ordering::Relaxed);
+                return true;
+            }
+        };
+        let taken = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used + bytes).filter(|used| *used <= limit)
+            });
+        if taken.is_err() {
+            self.exhausted.store(true, Ordering::Relaxed);
+        }
+        taken.is_ok()
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// a download went over the limit, nothing more should be fetched
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted.load(Ordering::Relaxed)
+    }
+}
+
+/// why a body was not stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aborted {
+    /// over the size limit of its type, by `Content-Length` or once received
+    TooLarge { limit: u64 },
+    /// the byte budget of the crawl ran out
+    OverBudget,
+    /// the body broke off after `received` bytes and will not be fetched again
+    Truncated { received: u64 },
+    /// not downloaded as routed to `routes::Action::Skip`
+    Skipped,
+    /// not stored as its robots directives say `noindex`
+    NoIndex,
+    /// writing the temporary file failed
+    Unwritable { kind: io::ErrorKind },
+}
+
+impl fmt::Display for Aborted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Aborted::TooLarge { limit } => write!(f, "larger than {} bytes", limit),
+            Aborted::OverBudget => write!(f, "over the byte budget"),
+            Aborted::Truncated { received } => write!(f, "truncated after {} bytes", received),
+            Aborted::Skipped => write!(f, "skipped by the routing table"),
+            Aborted::NoIndex => write!(f, "noindex by robots directives"),
+            Aborted::Unwritable { kind } => write!(f, "not written, {}", kind),
+        }
+    }
+}
+
+/// a failed download, its partial file removed
+#[derive(Debug)]
+pub enum DownloadError {
+    Aborted(Aborted),
+    /// the body broke off after `received` bytes
+    Broken {
+        error: reqwest::Error,
+        received: u64,
+    },
+    /// writing the partial file failed
+    Io(io::Error),
+}
+
+impl fmt::Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DownloadError::Aborted(aborted) => write!(f, "aborted, {}", aborted),
+            DownloadError::Broken { error, received } => {
+                write!(f, "{} after {} bytes", error, received)
+            }
+            DownloadError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl From<io::Error> for DownloadError {
+    fn from(error: io::Error) -> DownloadError {
+        DownloadError::Io(error)
+    }
+}
+
+/// a body downloaded to a temporary file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Body {
+    /// moved away by the sink to keep it, removed otherwise
+    pub path: PathBuf,
+    /// bytes in the file
+    pub len: u64,
+    /// `content::content_hash` of the file
+    pub content_hash: String,
+    /// the file as text if HTML
+    pub html: Option<String>,
+}
+
+impl Body {
+    /// the first bytes of the file, enough for `mime::sniff`
+    pub async fn head(&self) -> io::Result<Vec<u8>> {
+        let mut head = Vec::with_capacity(SNIFF_LEN);
+        File::open(&self.path)
+            .await?
+            .take(SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .await?;
+        Ok(head)
+    }
+}
+
+/// where bodies are downloaded to, and how much of them
+#[derive(Debug)]
+pub struct Downloads {
+    /// folder of the temporary files
+    pub dir: PathBuf,
+    pub limits: SizeLimits,
+    pub budget: ByteBudget,
+    /// temporary files named so far
+    count: AtomicUsize,
+}
+
+impl Downloads {
+    pub fn new(dir: PathBuf, limits: SizeLimits, budget: Option<u64>) -> Downloads {
+        Downloads {
+            dir,
+            limits,
+            budget: ByteBudget::new(budget),
+            count: AtomicUsize::new(0),
+        }
+    }
+
+    /// remove the temporary files left, by an interrupted crawl too
+    pub async fn clear(&self) -> io::Result<()> {
+        match remove_dir_all(&self.dir).await {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+            cleared => cleared,
+        }
+    }
+
+    /// stream the body of `response` to a new temporary file\
+    /// HTML is held whole in memory to be decoded according to the charset
+    /// and parsed, then stored as UTF-8,
+    /// so its size limit bounds the memory each worker takes\
+    /// aborted as soon as it is over the size limit of its type
+    /// or the byte budget
+    pub async fn download(&self, response: Response, is_html: bool) -> Result<Body, DownloadError> {
+        let limit = self
+            .limits
+            .limit(content_type(response.headers()).as_deref());
+        if matches!(response.content_length(), Some(length) if length > limit) {
+            return Err(DownloadError::Aborted(Aborted::TooLarge { limit }));
+        }
+
+        create_dir_all(&self.dir).await?;
+        let count = self.count.fetch_add(1, Ordering::Relaxed);
+        let path = self.dir.join(format!("{}-{}.part", process::id(), count));
+        let result = self.stream(response, &path, limit, is_html).await;
+        if result.is_err() {
+            let _ = remove_file(&path).await;
+        }
+        result
+    }
+
+    async fn stream(
+        &self,
+        mut response: Response,
+        path: &Path,
+        limit: u64,
+        is_html: bool,
+    ) -> Result<Body, DownloadError> {
+        let headers = response.headers().clone();
+        let mut file = File::create(path).await?;
+        let mut hasher = ContentHasher::default();
+        let mut html = Vec::new();
+        let mut received = 0;
+        loop {
+            let chunk = match response.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(error) => return Err(DownloadError::Broken { error, received }),
+            };
+            received += chunk.len() as u64;
+            if received > limit {
+                return Err(DownloadError::Aborted(Aborted::TooLarge { limit }));
+            }
+            if !self.budget.take(chunk.len() as u64) {
+                return Err(DownloadError::Aborted(Aborted::OverBudget));
+            }
+            if is_html {
+                html.extend_from_slice(&chunk);
+            } else {
+                hasher.update(&chunk);
+                file.write_all(&chunk).await?;
+            }
+        }
+
+        let (len, content_hash, html) = if is_html {
+            let html = decode(&headers, &html);
+            file.write_all(html.as_bytes()).await?;
+            (html.len() as u64, content_hash(html.as_bytes()), Some(html))
+        } else {
+            (received, hasher.finish(), None)
+        };
+        // tokio finishes the write in the background unless flushed
+        file.flush().await?;
+        Ok(Body {
+            path: path.to_owned(),
+            len,
+            content_hash,
+            html,
+        })
+    }
+}
+
+/// `bytes` decoded with the charset of `Content-Type`, UTF-8 by default\
+/// a byte order mark takes precedence
+fn decode(headers: &HeaderMap, bytes: &[u8]) -> String {
+    let charset = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| {
+            value.split(';').skip(1).find_map(|param| {
+                let (name, value) = param.split_once('=')?;
+                let is_charset = name.trim().eq_ignore_ascii_case("charset");
+                is_charset.then(|| value.trim().trim_matches('"'))
+            })
+        });
+    let encoding = charset
+        .and_then(|label| Encoding::for_label(label.as_bytes()))
+        .unwrap_or(UTF_8);
+    encoding.decode(bytes).0.into_owned()
+}
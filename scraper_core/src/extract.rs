@@ -0,0 +1,100 @@
+use select::{
+    document::Document,
+    node::Node,
+    predicate::{Name, Or, Text},
+};
+use std::panic::catch_unwind;
+
+/// elements whose text is not shown
+const HIDDEN: [&str; 4] = ["script", "style", "noscript", "head"];
+
+/// the text a reader would see in `html`, one text block each line\
+/// text in `script`, `style`, `noscript` and `head` is dropped
+pub fn visible_text(html: &str) -> String {
+    text_of(&Document::from(html))
+}
+
+/// content of `<title>`, empty if none
+pub fn title(document: &Document) -> String {
+    document
+        .find(Name("title"))
+        .next()
+        .map(|n| normalize(&n.text()))
+        .unwrap_or_default()
+}
+
+/// text of every `<h1>` to `<h6>`, one each line
+pub fn headings(document: &Document) -> String {
+    let mut text = String::new();
+    let heading = Or(
+        Or(Or(Name("h1"), Name("h2")), Or(Name("h3"), Name("h4"))),
+        Or(Name("h5"), Name("h6")),
+    );
+    for node in document.find(heading) {
+        let line = normalize(&node.text());
+        if !line.is_empty() {
+            text.push_str(&line);
+            text.push('\n');
+        }
+    }
+    text
+}
+
+/// visible text of a parsed document, see `visible_text`
+pub fn text_of(document: &Document) -> String {
+    let mut text = String::new();
+    for node in document.find(Text) {
+        if has_hidden_ancestor(&node) {
+            continue;
+        }
+        let line = normalize(node.as_text().unwrap_or_default());
+        if line.is_empty() {
+            continue;
+        }
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+/// collapse every whitespace run into one space
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+fn has_hidden_ancestor(node: &Node) -> bool {
+    let mut parent = node.parent();
+    while let Some(p) = parent {
+        if matches!(p.name(), Some(name) if HIDDEN.contains(&name)) {
+            return true;
+        }
+        parent = p.parent();
+    }
+    false
+}
+
+/// the text of a body of type `mime`
+/// - HTML: `visible_text`
+/// - PDF: the text of every page
+/// - `text/*`, JSON and XML: the body itself
+///
+/// # return
+/// `None` for other types or a PDF that fails to parse
+pub fn body_text(mime: &str, body: &[u8]) -> Option<String> {
+    match mime {
+        "text/html" | "application/xhtml+xml" => Some(visible_text(&String::from_utf8_lossy(body))),
+        // the parser panics on some broken files
+        "application/pdf" => catch_unwind(|| pdf_extract::extract_text_from_mem(body))
+            .ok()?
+            .ok(),
+        _ if mime.starts_with("text/")
+            || mime.ends_with("/json")
+            || mime.ends_with("+json")
+            || mime.ends_with("/xml")
+            || mime.ends_with("+xml") =>
+        {
+            Some(String::from_utf8_lossy(body).into_owned())
+        }
+        _ => None,
+    }
+}
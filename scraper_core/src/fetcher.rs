@@ -0,0 +1,21 @@
+use async_trait::async_trait;
+use reqwest::{Client, Response, Url};
+
+/// makes the HTTP request for a URL
+#[async_trait]
+pub trait Fetcher: Send + Sync {
+    async fn fetch(&self, url: &Url) -> reqwest::Result<Response>;
+}
+
+/// plain GET with `client`
+#[derive(Debug, Clone)]
+pub struct ClientFetcher {
+    pub client: Client,
+}
+
+#[async_trait]
+impl Fetcher for ClientFetcher {
+    async fn fetch(&self, url: &Url) -> reqwest::Result<Response> {
+        self.client.get(url.clone()).send().await
+    }
+}
@@ -0,0 +1,202 @@
+use async_trait::async_trait;
+use reqwest::Url;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    links::LinkKind,
+    robots::RobotsCache,
+    rules::{Rule, RuleList},
+};
+
+/// decides which URL are crawled
+#[async_trait]
+pub trait Filter: Send + Sync {
+    /// whether `url` should be crawled
+    async fn allows(&self, url: &Url) -> bool;
+
+    /// minimum interval between two fetches to the host of `url`
+    async fn crawl_delay(&self, _url: &Url) -> Option<Duration> {
+        None
+    }
+
+    /// whether links of `kind` are followed at all\
+    /// a link not followed leaves its URL unknown, to be found another way
+    fn follows(&self, _kind: LinkKind) -> bool {
+        true
+    }
+}
+
+/// crawl everything
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+
+#[async_trait]
+impl Filter for AllowAll {
+    async fn allows(&self, _url: &Url) -> bool {
+        true
+    }
+}
+
+/// crawl URL matching no `exclude` rule and any `include` rule,
+/// or any URL if there is none
+#[derive(Debug, Clone, Default)]
+pub struct RuleFilter {
+    pub include: RuleList,
+    pub exclude: RuleList,
+}
+
+impl RuleFilter {
+    /// `true` if excluded or not included
+    pub fn is_filtered(&self, url: &Url) -> bool {
+        self.exclude.is_match(url) || !(self.include.is_empty() || self.include.is_match(url))
+    }
+
+    /// the rules deciding whether `url` is crawled
+    pub fn explain(&self, url: &Url) -> Explanation<'_> {
+        Explanation {
+            excluded_by: self.exclude.matches(url),
+            included_by: self.include.matches(url),
+            needs_include: !self.include.is_empty(),
+        }
+    }
+}
+
+#[async_trait]
+impl Filter for RuleFilter {
+    async fn allows(&self, url: &Url) -> bool {
+        !self.is_filtered(url)
+    }
+}
+
+/// rules matching a URL, see `RuleFilter::explain`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Explanation<'a> {
+    pub excluded_by: Vec<&'a Rule>,
+    pub included_by: Vec<&'a Rule>,
+    /// whether only included URL are crawled
+    pub needs_include: bool,
+}
+
+impl Explanation<'_> {
+    pub fn is_allowed(&self) -> bool {
+        self.excluded_by.is_empty() && (!self.needs_include || !self.included_by.is_empty())
+    }
+}
+
+/// `allowed` or `rejected`, then one matching rule each line
+impl fmt::Display for Explanation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(if self.is_allowed() {
+            "allowed"
+        } else {
+            "rejected"
+        })?;
+        for rule in &self.excluded_by {
+            write!(f, "\n  excluded by {}", rule)?;
+        }
+        for rule in &self.included_by {
+            write!(f, "\n  included by {}", rule)?;
+        }
+        if self.needs_include && self.included_by.is_empty() {
+            f.write_str("\n  matches no include rule")?;
+        }
+        Ok(())
+    }
+}
+
+/// `inner` plus robots.txt\
+/// `Crawl-delay` is taken from robots.txt
+pub struct Polite<F> {
+    pub inner: F,
+    pub robots: Arc<RobotsCache>,
+}
+
+#[async_trait]
+impl<F: Filter> Filter for Polite<F> {
+    async fn allows(&self, url: &Url) -> bool {
+        // check `inner` first so robots.txt of filtered hosts is never fetched
+        self.inner.allows(url).await && self.robots.is_allowed(url).await
+    }
+
+    async fn crawl_delay(&self, url: &Url) -> Option<Duration> {
+        self.robots.crawl_delay(url).await
+    }
+
+    fn follows(&self, kind: LinkKind) -> bool {
+        self.inner.follows(kind)
+    }
+}
+
+/// settings of one host for `HostRules`
+#[derive(Debug, Clone, Default)]
+pub struct HostRule {
+    /// replaces the crawl delay of `inner`
+    pub crawl_delay: Option<Duration>,
+    /// URL matching are not crawled
+    pub exclude: RuleList,
+}
+
+/// `inner` with settings for single hosts
+pub struct HostRules<F> {
+    pub inner: F,
+    /// by lowercase host name
+    pub hosts: HashMap<String, HostRule>,
+}
+
+impl<F> HostRules<F> {
+    fn rule(&self, url: &Url) -> Option<&HostRule> {
+        self.hosts.get(url.host_str()?)
+    }
+
+    /// the `exclude` rules of the host of `url` matching it
+    pub fn excluded_by(&self, url: &Url) -> Vec<&Rule> {
+        self.rule(url)
+            .map(|rule| rule.exclude.matches(url))
+            .unwrap_or_default()
+    }
+}
+
+#[async_trait]
+impl<F: Filter> Filter for HostRules<F> {
+    async fn allows(&self, url: &Url) -> bool {
+        let excluded = matches!(self.rule(url), Some(rule) if rule.exclude.is_match(url));
+        !excluded && self.inner.allows(url).await
+    }
+
+    async fn crawl_delay(&self, url: &Url) -> Option<Duration> {
+        match self.rule(url).and_then(|rule| rule.crawl_delay) {
+            Some(crawl_delay) => Some(crawl_delay),
+            None => self.inner.crawl_delay(url).await,
+        }
+    }
+
+    fn follows(&self, kind: LinkKind) -> bool {
+        self.inner.follows(kind)
+    }
+}
+
+/// `inner` following only links of `kinds`
+pub struct LinkKinds<F> {
+    pub inner: F,
+    pub kinds: HashSet<LinkKind>,
+}
+
+#[async_trait]
+impl<F: Filter> Filter for LinkKinds<F> {
+    async fn allows(&self, url: &Url) -> bool {
+        self.inner.allows(url).await
+    }
+
+    async fn crawl_delay(&self, url: &Url) -> Option<Duration> {
+        self.inner.crawl_delay(url).await
+    }
+
+    fn follows(&self, kind: LinkKind) -> bool {
+        self.kinds.contains(&kind) && self.inner.follows(kind)
+    }
+}
@@ -0,0 +1,383 @@
+use reqwest::Url;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+    hash::{Hash, Hasher},
+    sync::{
+        atomic::{AtomicUsize, Ordering::SeqCst},
+        Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// number of independently locked parts of a `ShardedFrontier`
+const SHARDS: usize = 16;
+
+/// waitlist of one host
+#[derive(Debug)]
+struct HostQueue {
+    urls: VecDeque<Url>,
+    /// earliest time the next URL of this host may be fetched
+    next_fetch: Instant,
+    /// `Crawl-delay` from robots.txt
+    crawl_delay: Option<Duration>,
+    /// total time workers spent waiting for this host to cool down
+    waited: Duration,
+}
+
+impl HostQueue {
+    fn new(now: Instant) -> HostQueue {
+        HostQueue {
+            urls: VecDeque::new(),
+            next_fetch: now,
+            crawl_delay: None,
+            waited: Duration::ZERO,
+        }
+    }
+}
+
+/// result of `Frontier::pop`
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pop {
+    /// a URL whose host is ready to be fetched
+    Ready(Url),
+    /// every host with waiting URL is cooling down\
+    /// `host` is the first to be ready at `until`
+    Wait { host: String, until: Instant },
+    /// no URL waiting
+    Empty,
+}
+
+/// host-aware waitlist\
+/// enforces a minimum interval between fetches to the same host
+#[derive(Debug)]
+pub struct Frontier {
+    hosts: HashMap<String, HostQueue>,
+    /// URL popped but not finished yet, by times popped\
+    /// a URL requeued may be popped again before the first pop finishes
+    in_flight: HashMap<Url, usize>,
+    /// interval used for every host instead of `Crawl-delay`
+    delay_override: Option<Duration>,
+    len: usize,
+}
+
+/// key of the host of `url` in the frontier
+pub fn host_key(url: &Url) -> String {
+    url.origin().ascii_serialization()
+}
+
+impl Frontier {
+    /// construct an empty `Frontier`\
+    /// `delay_override` replaces robots.txt `Crawl-delay` if set
+    pub fn new(delay_override: Option<Duration>) -> Frontier {
+        Frontier {
+            hosts: HashMap::new(),
+            in_flight: HashMap::new(),
+            delay_override,
+            len: 0,
+        }
+    }
+
+    /// number of URL waiting
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// no URL waiting and none in flight\
+    /// nothing can be added to the frontier any more
+    pub fn is_drained(&self) -> bool {
+        self.len == 0 && self.in_flight.is_empty()
+    }
+
+    /// add `url` to the end of its host's waitlist
+    pub fn push_back(&mut self, url: Url) {
+        let now = Instant::now();
+        self.hosts
+            .entry(host_key(&url))
+            .or_insert_with(|| HostQueue::new(now))
+            .urls
+            .push_back(url);
+        self.len += 1;
+    }
+
+    /// add `url` back to the end of its host's waitlist
+    /// and fetch nothing from the host for `delay`
+    pub fn push_retry(&mut self, url: Url, delay: Duration) {
+        let now = Instant::now();
+        let queue = self
+            .hosts
+            .entry(host_key(&url))
+            .or_insert_with(|| HostQueue::new(now));
+        queue.next_fetch = queue.next_fetch.max(now + delay);
+        queue.urls.push_back(url);
+        self.len += 1;
+    }
+
+    /// record the robots.txt `Crawl-delay` for the host of `url`
+    pub fn set_crawl_delay(&mut self, url: &Url, crawl_delay: Option<Duration>) {
+        let now = Instant::now();
+        self.hosts
+            .entry(host_key(url))
+            .or_insert_with(|| HostQueue::new(now))
+            .crawl_delay = crawl_delay;
+    }
+
+    /// record time a worker spent waiting for `host` to cool down
+    pub fn add_wait(&mut self, host: &str, waited: Duration) {
+        if let Some(queue) = self.hosts.get_mut(host) {
+            queue.waited += waited;
+        }
+    }
+
+    /// pop the first URL of the host ready the longest\
+    /// URL for which `skip` returns `true` are dropped without
+    /// counting as a fetch
+    pub fn pop(&mut self, now: Instant, mut skip: impl FnMut(&Url) -> bool) -> Pop {
+        loop {
+            // the ready host with the earliest `next_fetch`, or the host ready soonest
+            let mut ready: Option<(&String, Instant)> = None;
+            let mut cooling: Option<(&String, Instant)> = None;
+            for (host, queue) in &self.hosts {
+                if queue.urls.is_empty() {
+                    continue;
+                }
+                let slot = if queue.next_fetch <= now {
+                    &mut ready
+                } else {
+                    &mut cooling
+                };
+                if !matches!(*slot, Some((_, t)) if queue.next_fetch >= t) {
+                    *slot = Some((host, queue.next_fetch));
+                }
+            }
+
+            let host = match (ready, cooling) {
+                (Some((host, _)), _) => host.to_owned(),
+                (None, Some((host, until))) => {
+                    return Pop::Wait {
+                        host: host.to_owned(),
+                        until,
+                    }
+                }
+                (None, None) => return Pop::Empty,
+            };
+
+            let delay_override = self.delay_override;
+            let queue = self.hosts.get_mut(&host).unwrap();
+            while let Some(url) = queue.urls.pop_front() {
+                self.len -= 1;
+                if skip(&url) {
+                    continue;
+                }
+                let delay = delay_override
+                    .or(queue.crawl_delay)
+                    .unwrap_or(Duration::ZERO);
+                queue.next_fetch = now + delay;
+                *self.in_flight.entry(url.clone()).or_default() += 1;
+                return Pop::Ready(url);
+            }
+            // every URL of this host skipped, look at the other hosts
+        }
+    }
+
+    /// mark `url` from `pop` as finished, successfully or not\
+    /// still in flight if popped again since
+    pub fn finish(&mut self, url: &Url) {
+        if let Some(count) = self.in_flight.get_mut(url) {
+            *count -= 1;
+            if *count == 0 {
+                self.in_flight.remove(url);
+            }
+        }
+    }
+
+    /// URL popped but not finished yet
+    pub fn in_flight(&self) -> impl Iterator<Item = &Url> {
+        self.in_flight.keys()
+    }
+
+    /// every URL not finished, in-flight ones first
+    pub fn snapshot(&self) -> Vec<Url> {
+        let mut in_flight: Vec<Url> = self.in_flight.keys().cloned().collect();
+        in_flight.sort();
+        let mut hosts: Vec<&String> = self.hosts.keys().collect();
+        hosts.sort();
+
+        let mut snapshot = in_flight;
+        for host in hosts {
+            snapshot.extend(self.hosts[host].urls.iter().cloned());
+        }
+        snapshot
+    }
+
+    /// total time waited for each host that had to be waited for
+    pub fn wait_times(&self) -> Vec<(String, Duration)> {
+        let mut wait_times: Vec<(String, Duration)> = self
+            .hosts
+            .iter()
+            .filter(|(_, queue)| !queue.waited.is_zero())
+            .map(|(host, queue)| (host.to_owned(), queue.waited))
+            .collect();
+        wait_times.sort();
+        wait_times
+    }
+}
+
+impl Default for Frontier {
+    fn default() -> Frontier {
+        Frontier::new(None)
+    }
+}
+
+/// `Frontier` shared by workers\
+/// hosts are split into shards, each behind its own lock
+#[derive(Debug)]
+pub struct ShardedFrontier {
+    shards: Vec<Mutex<Frontier>>,
+    /// URL waiting or in flight
+    unfinished: AtomicUsize,
+}
+
+impl ShardedFrontier {
+    /// construct an empty `ShardedFrontier`\
+    /// `delay_override` replaces robots.txt `Crawl-delay` if set
+    pub fn new(delay_override: Option<Duration>) -> ShardedFrontier {
+        ShardedFrontier {
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(Frontier::new(delay_override)))
+                .collect(),
+            unfinished: AtomicUsize::new(0),
+        }
+    }
+
+    fn shard(&self, host: &str) -> MutexGuard<'_, Frontier> {
+        let mut hasher = DefaultHasher::new();
+        host.hash(&mut hasher);
+        self.shards[hasher.finish() as usize % SHARDS]
+            .lock()
+            .unwrap()
+    }
+
+    /// number of URL waiting\
+    /// locks the shards one after the other
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// no URL waiting and none in flight\
+    /// nothing can be added to the frontier any more
+    pub fn is_drained(&self) -> bool {
+        self.unfinished.load(SeqCst) == 0
+    }
+
+    /// add `url` to the end of its host's waitlist
+    pub fn push_back(&self, url: Url) {
+        // counted before it can be popped
+        self.unfinished.fetch_add(1, SeqCst);
+        self.shard(&host_key(&url)).push_back(url);
+    }
+
+    /// add `url` back to the end of its host's waitlist
+    /// and fetch nothing from the host for `delay`
+    pub fn push_retry(&self, url: Url, delay: Duration) {
+        self.unfinished.fetch_add(1, SeqCst);
+        self.shard(&host_key(&url)).push_retry(url, delay);
+    }
+
+    /// record the robots.txt `Crawl-delay` for the host of `url`
+    pub fn set_crawl_delay(&self, url: &Url, crawl_delay: Option<Duration>) {
+        self.shard(&host_key(url)).set_crawl_delay(url, crawl_delay);
+    }
+
+    /// record time a worker spent waiting for `host` to cool down
+    pub fn add_wait(&self, host: &str, waited: Duration) {
+        self.shard(host).add_wait(host, waited);
+    }
+
+    /// pop a ready URL from the shards, starting with shard `first`\
+    /// so workers starting at different shards rarely wait for each other\
+    /// URL for which `skip` returns `true` are dropped without
+    /// counting as a fetch
+    /// # return
+    /// the first ready URL, otherwise the host ready soonest
+    pub fn pop(&self, now: Instant, first: usize, mut skip: impl FnMut(&Url) -> bool) -> Pop {
+        let mut soonest = Pop::Empty;
+        for i in 0..SHARDS {
+            let pop = self.shards[(first + i) % SHARDS]
+                .lock()
+                .unwrap()
+                .pop(now, |url| {
+                    let skipped = skip(url);
+                    if skipped {
+                        self.unfinished.fetch_sub(1, SeqCst);
+                    }
+                    skipped
+                });
+            match (pop, &soonest) {
+                (Pop::Ready(url), _) => return Pop::Ready(url),
+                (Pop::Wait { host, until }, Pop::Empty) => soonest = Pop::Wait { host, until },
+                (Pop::Wait { host, until }, Pop::Wait { until: t, .. }) if until < *t => {
+                    soonest = Pop::Wait { host, until }
+                }
+                _ => {}
+            }
+        }
+        soonest
+    }
+
+    /// mark `url` from `pop` as finished, successfully or not
+    pub fn finish(&self, url: &Url) {
+        self.shard(&host_key(url)).finish(url);
+        self.unfinished.fetch_sub(1, SeqCst);
+    }
+
+    /// lock every shard in order
+    fn lock_all(&self) -> Vec<MutexGuard<'_, Frontier>> {
+        self.shards.iter().map(|s| s.lock().unwrap()).collect()
+    }
+
+    /// every URL not finished, in-flight ones first\
+    /// consistent across shards
+    pub fn snapshot(&self) -> Vec<Url> {
+        let shards = self.lock_all();
+        let mut in_flight: Vec<Url> = shards
+            .iter()
+            .flat_map(|shard| shard.in_flight.keys().cloned())
+            .collect();
+        in_flight.sort();
+        let mut hosts: Vec<(&String, &HostQueue)> =
+            shards.iter().flat_map(|shard| shard.hosts.iter()).collect();
+        hosts.sort_by_key(|(host, _)| *host);
+
+        let mut snapshot = in_flight;
+        for (_, queue) in hosts {
+            snapshot.extend(queue.urls.iter().cloned());
+        }
+        snapshot
+    } // shards unlock
+
+    /// total time waited for each host that had to be waited for
+    pub fn wait_times(&self) -> Vec<(String, Duration)> {
+        let mut wait_times: Vec<(String, Duration)> = self
+            .lock_all()
+            .iter()
+            .flat_map(|shard| shard.wait_times())
+            .collect();
+        wait_times.sort();
+        wait_times
+    }
+}
+
+impl Default for ShardedFrontier {
+    fn default() -> ShardedFrontier {
+        ShardedFrontier::new(None)
+    }
+}
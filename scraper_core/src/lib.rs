@@ -0,0 +1,24 @@
+pub mod canonical;
+pub mod cli;
+pub mod client;
+pub mod config;
+pub mod content;
+pub mod crawler;
+pub mod download;
+pub mod extract;
+pub mod fetcher;
+pub mod filter;
+pub mod frontier;
+pub mod links;
+pub mod meta_robots;
+pub mod mime;
+pub mod near_dup;
+pub mod retry;
+pub mod robots;
+pub mod routes;
+pub mod rules;
+pub mod seen;
+pub mod sink;
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,384 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::Url;
+use select::{
+    document::Document,
+    predicate::{Any, Attr, Name, Predicate},
+};
+use std::{fmt, str::FromStr};
+
+/// the element a link was found in, so filters can treat them differently
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LinkKind {
+    /// `<a href>`
+    Anchor,
+    /// `<area href>` of an image map
+    Area,
+    /// `<img src>`, `<img srcset>` and `<source>` of a `<picture>`
+    Image,
+    /// `<source>` of a `<video>` or `<audio>`
+    Media,
+    /// `<link rel="stylesheet">`
+    Stylesheet,
+    /// `<link rel="canonical">`
+    Canonical,
+    /// `<link rel="alternate">`, other languages and feeds
+    Alternate,
+    /// any other `<link href>`, icons, preloads…
+    Link,
+    /// `<script src>`
+    Script,
+    /// `<iframe src>` and `<frame src>`
+    Frame,
+    /// `action` of a `<form method="get">`
+    Form,
+    /// `<meta http-equiv="refresh">`
+    Refresh,
+    /// written in a body other than HTML, see `css_links` and `text_links`
+    Text,
+}
+
+impl LinkKind {
+    const ALL: [LinkKind; 13] = [
+        LinkKind::Anchor,
+        LinkKind::Area,
+        LinkKind::Image,
+        LinkKind::Media,
+        LinkKind::Stylesheet,
+        LinkKind::Canonical,
+        LinkKind::Alternate,
+        LinkKind::Link,
+        LinkKind::Script,
+        LinkKind::Frame,
+        LinkKind::Form,
+        LinkKind::Refresh,
+        LinkKind::Text,
+    ];
+
+    /// kinds followed unless configured otherwise, the pages and images\
+    /// canonical links are left to `crawler`, which saves pages under them
+    pub const DEFAULT: [LinkKind; 7] = [
+        LinkKind::Anchor,
+        LinkKind::Area,
+        LinkKind::Image,
+        LinkKind::Alternate,
+        LinkKind::Frame,
+        LinkKind::Refresh,
+        LinkKind::Text,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkKind::Anchor => "anchor",
+            LinkKind::Area => "area",
+            LinkKind::Image => "image",
+            LinkKind::Media => "media",
+            LinkKind::Stylesheet => "stylesheet",
+            LinkKind::Canonical => "canonical",
+            LinkKind::Alternate => "alternate",
+            LinkKind::Link => "link",
+            LinkKind::Script => "script",
+            LinkKind::Frame => "frame",
+            LinkKind::Form => "form",
+            LinkKind::Refresh => "refresh",
+            LinkKind::Text => "text",
+        }
+    }
+}
+
+impl fmt::Display for LinkKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for LinkKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LinkKind, String> {
+        LinkKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = LinkKind::ALL.iter().map(|k| k.name()).collect();
+                format!("unknown link kind `{}`, expected {}", s, names.join(", "))
+            })
+    }
+}
+
+/// a resolved link and where it was found
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Link {
+    pub url: Url,
+    pub kind: LinkKind,
+    /// marked `rel="nofollow"`
+    pub nofollow: bool,
+}
+
+impl Link {
+    pub fn new(url: Url, kind: LinkKind) -> Link {
+        Link {
+            url,
+            kind,
+            nofollow: false,
+        }
+    }
+}
+
+/// finds the links to follow in an HTML page
+pub trait LinkExtractor: Send + Sync {
+    /// links in `html` resolved against `base`, or its `<base href>`\
+    /// links that fail to resolve are skipped
+    fn extract(&self, base: &Url, html: &str) -> Vec<Link>;
+}
+
+/// every `<a href>` and `<img src>`, sorted and deduplicated
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AnchorsAndImages;
+
+impl LinkExtractor for AnchorsAndImages {
+    fn extract(&self, base: &Url, html: &str) -> Vec<Link> {
+        let document = Document::from(html);
+        let base = document_base(base, &document);
+        let mut links = resolve(&base, &document, Name("a"), "href", LinkKind::Anchor);
+        links.extend(resolve(
+            &base,
+            &document,
+            Name("img"),
+            "src",
+            LinkKind::Image,
+        ));
+        links.sort();
+        links.dedup();
+        links
+    }
+}
+
+/// every `<img src>`, in document order
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Images;
+
+impl LinkExtractor for Images {
+    fn extract(&self, base: &Url, html: &str) -> Vec<Link> {
+        let document = Document::from(html);
+        let base = document_base(base, &document);
+        resolve(&base, &document, Name("img"), "src", LinkKind::Image)
+    }
+}
+
+/// every link of every `LinkKind` found in HTML, sorted and deduplicated\
+/// a URL linked in several ways is listed once for each
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllLinks;
+
+impl LinkExtractor for AllLinks {
+    fn extract(&self, base: &Url, html: &str) -> Vec<Link> {
+        let document = Document::from(html);
+        let base = document_base(base, &document);
+        let mut links = Vec::new();
+        let mut add = |kind, link: Option<&str>, nofollow| {
+            if let Some(url) = link.and_then(|link| base.join(link.trim()).ok()) {
+                links.push(Link {
+                    url,
+                    kind,
+                    nofollow,
+                });
+            }
+        };
+        for node in document.find(Any) {
+            let nofollow = has_rel(node.attr("rel"), "nofollow");
+            let mut push = |kind, link| add(kind, link, nofollow);
+            match node.name().unwrap_or_default() {
+                "a" => push(LinkKind::Anchor, node.attr("href")),
+                "area" => push(LinkKind::Area, node.attr("href")),
+                "img" => {
+                    push(LinkKind::Image, node.attr("src"));
+                    for url in srcset_urls(node.attr("srcset").unwrap_or_default()) {
+                        push(LinkKind::Image, Some(url));
+                    }
+                }
+                "source" => {
+                    let in_picture = node.parent().and_then(|p| p.name()) == Some("picture");
+                    let kind = if in_picture {
+                        LinkKind::Image
+                    } else {
+                        LinkKind::Media
+                    };
+                    push(kind, node.attr("src"));
+                    for url in srcset_urls(node.attr("srcset").unwrap_or_default()) {
+                        push(kind, Some(url));
+                    }
+                }
+                "link" => push(link_kind(node.attr("rel")), node.attr("href")),
+                "script" => push(LinkKind::Script, node.attr("src")),
+                "iframe" | "frame" => push(LinkKind::Frame, node.attr("src")),
+                "form" => {
+                    let method = node.attr("method").unwrap_or("get");
+                    if method.trim().eq_ignore_ascii_case("get") {
+                        // no action submits to the page itself
+                        push(
+                            LinkKind::Form,
+                            node.attr("action").filter(|a| !a.is_empty()),
+                        );
+                    }
+                }
+                "meta" => {
+                    let http_equiv = node.attr("http-equiv").unwrap_or_default();
+                    if http_equiv.eq_ignore_ascii_case("refresh") {
+                        push(
+                            LinkKind::Refresh,
+                            node.attr("content").and_then(refresh_url),
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+        links.sort();
+        links.dedup();
+        links
+    }
+}
+
+/// `base` resolved with the `href` of the first `<base>` of `document` if any
+fn document_base(base: &Url, document: &Document) -> Url {
+    document
+        .find(Name("base"))
+        .find_map(|n| n.attr("href"))
+        .and_then(|href| base.join(href.trim()).ok())
+        .unwrap_or_else(|| base.clone())
+}
+
+/// whether the `rel` attribute `rel` lists `value`
+fn has_rel(rel: Option<&str>, value: &str) -> bool {
+    rel.unwrap_or_default()
+        .split_ascii_whitespace()
+        .any(|r| r.eq_ignore_ascii_case(value))
+}
+
+/// kind of a `<link>` with the `rel` attribute `rel`
+fn link_kind(rel: Option<&str>) -> LinkKind {
+    let has = |value| has_rel(rel, value);
+    // `alternate stylesheet` is a stylesheet
+    if has("stylesheet") {
+        LinkKind::Stylesheet
+    } else if has("canonical") {
+        LinkKind::Canonical
+    } else if has("alternate") {
+        LinkKind::Alternate
+    } else {
+        LinkKind::Link
+    }
+}
+
+/// the URL of every candidate of a `srcset`, `a.jpg 1x, b.jpg 2x`\
+/// a URL may contain commas, but not end with one
+fn srcset_urls(srcset: &str) -> Vec<&str> {
+    let mut urls = Vec::new();
+    let mut rest = srcset;
+    loop {
+        rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+        if rest.is_empty() {
+            return urls;
+        }
+        let end = rest
+            .find(|c: char| c.is_ascii_whitespace())
+            .unwrap_or(rest.len());
+        let (url, after) = rest.split_at(end);
+        urls.push(url.trim_end_matches(','));
+        // the descriptors follow up to the next comma, unless it ended the URL
+        rest = if url.ends_with(',') {
+            after
+        } else {
+            after.split_once(',').map_or("", |(_, rest)| rest)
+        };
+    }
+}
+
+/// URL of the `content` of a `<meta http-equiv="refresh">`, `5; url=next.html`
+fn refresh_url(content: &str) -> Option<&str> {
+    let (_, rest) = content.split_once([';', ','])?;
+    let rest = rest.trim_start();
+    let rest = match rest.get(..3) {
+        Some(key) if key.eq_ignore_ascii_case("url") => {
+            rest[3..].trim_start().strip_prefix('=')?.trim_start()
+        }
+        _ => rest,
+    };
+    let url = rest.trim_end().trim_matches(['"', '\'']);
+    (!url.is_empty()).then_some(url)
+}
+
+/// `href` of the first `<link rel="canonical">` resolved against `base`,
+/// or its `<base href>`
+pub fn canonical_link(base: &Url, html: &str) -> Option<Url> {
+    let document = Document::from(html);
+    let base = document_base(base, &document);
+    document
+        .find(Name("link").and(Attr("rel", ())))
+        .filter(|n| has_rel(n.attr("rel"), "canonical"))
+        .filter_map(|n| n.attr("href"))
+        .find_map(|link| base.join(link).ok())
+}
+
+/// `url(…)` and `@import "…"` of a stylesheet
+static CSS_URL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r#"url\(\s*(?:"([^"]*)"|'([^']*)'|([^)'"\s]*))\s*\)|@import\s+(?:"([^"]*)"|'([^']*)')"#,
+    )
+    .unwrap()
+});
+
+/// absolute http(s) URL in any text
+static TEXT_URL: Lazy<Regex> = Lazy::new(|| Regex::new(r#"https?://[^\s"'<>()\\]+"#).unwrap());
+
+/// every `url()` and `@import` of `css` resolved against `base`,
+/// sorted and deduplicated\
+/// `data:` URL are skipped
+pub fn css_links(base: &Url, css: &str) -> Vec<Url> {
+    let mut links: Vec<Url> = CSS_URL
+        .captures_iter(css)
+        .filter_map(|c| c.iter().skip(1).flatten().next())
+        .map(|m| m.as_str().trim())
+        .filter(|link| !link.is_empty() && !link.starts_with("data:"))
+        .filter_map(|link| base.join(link).ok())
+        .collect();
+    links.sort();
+    links.dedup();
+    links
+}
+
+/// every absolute http(s) URL written in `text`, sorted and deduplicated\
+/// a trailing `.` or `,` ends the sentence, not the URL
+pub fn text_links(text: &str) -> Vec<Url> {
+    let mut links: Vec<Url> = TEXT_URL
+        .find_iter(text)
+        .map(|m| m.as_str().trim_end_matches(['.', ',', ';']))
+        .filter_map(|link| Url::parse(link).ok())
+        .collect();
+    links.sort();
+    links.dedup();
+    links
+}
+
+/// attribute `attr` of every node matching `predicate`, resolved against `base`
+fn resolve(
+    base: &Url,
+    document: &Document,
+    predicate: impl Predicate,
+    attr: &str,
+    kind: LinkKind,
+) -> Vec<Link> {
+    document
+        .find(predicate)
+        .filter_map(|n| {
+            let url = base.join(n.attr(attr)?).ok()?;
+            let nofollow = has_rel(n.attr("rel"), "nofollow");
+            Some(Link {
+                url,
+                kind,
+                nofollow,
+            })
+        })
+        .collect()
+}
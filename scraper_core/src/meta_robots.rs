@@ -0,0 +1,92 @@
+use reqwest::header::HeaderMap;
+use select::{document::Document, predicate::Name};
+
+/// header carrying the robots directives of a response
+pub const X_ROBOTS_TAG: &str = "x-robots-tag";
+
+/// robots directives of a page, see `RobotsMeta`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Directives {
+    /// the page should not be stored
+    pub noindex: bool,
+    /// the links of the page should not be followed
+    pub nofollow: bool,
+}
+
+impl Directives {
+    /// add the comma separated directives of `content`, others ignored\
+    /// `none` is `noindex, nofollow`
+    pub fn add(&mut self, content: &str) {
+        for directive in content.split(',') {
+            match directive.trim().to_ascii_lowercase().as_str() {
+                "noindex" => self.noindex = true,
+                "nofollow" => self.nofollow = true,
+                "none" => {
+                    self.noindex = true;
+                    self.nofollow = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// directives of either
+    pub fn merge(&mut self, other: Directives) {
+        self.noindex |= other.noindex;
+        self.nofollow |= other.nofollow;
+    }
+}
+
+/// honors `X-Robots-Tag`, `<meta name="robots">` and `rel="nofollow"`
+/// for `user_agent`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RobotsMeta {
+    /// matched like in robots.txt against the agents directives name
+    pub user_agent: String,
+    /// store `noindex` pages marked as such instead of skipping them
+    pub save_noindex: bool,
+}
+
+impl RobotsMeta {
+    /// directives of the `X-Robots-Tag` of `headers`\
+    /// a value may start with the agent it is for, `otherbot: noindex`
+    pub fn from_headers(&self, headers: &HeaderMap) -> Directives {
+        let mut directives = Directives::default();
+        for value in headers.get_all(X_ROBOTS_TAG) {
+            let value = match value.to_str() {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+            // a single word before `:` names an agent,
+            // so `unavailable_after: date` is ignored too
+            match value.split_once(':') {
+                Some((agent, rest)) if !agent.contains([',', ' ']) => {
+                    if self.is_for(agent) {
+                        directives.add(rest);
+                    }
+                }
+                _ => directives.add(value),
+            }
+        }
+        directives
+    }
+
+    /// directives of the `<meta name="robots">` of `html`,
+    /// or those named after the agent
+    pub fn from_html(&self, html: &str) -> Directives {
+        let mut directives = Directives::default();
+        for meta in Document::from(html).find(Name("meta")) {
+            let name = meta.attr("name").unwrap_or_default();
+            if name.eq_ignore_ascii_case("robots") || self.is_for(name) {
+                directives.add(meta.attr("content").unwrap_or_default());
+            }
+        }
+        directives
+    }
+
+    /// whether directives for `agent` apply to `user_agent`
+    fn is_for(&self, agent: &str) -> bool {
+        let agent = agent.trim().to_lowercase();
+        !agent.is_empty() && self.user_agent.to_lowercase().contains(&agent)
+    }
+}
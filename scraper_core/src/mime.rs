@@ -0,0 +1,287 @@
+use reqwest::{
+    header::{HeaderMap, CONTENT_DISPOSITION, CONTENT_TYPE},
+    Url,
+};
+use std::collections::HashMap;
+
+use crate::canonical::percent_decode;
+
+/// MIME type and extension of the files saved with their own extension\
+/// the first extension of a type is the one saved,
+/// the first type of an extension the one it is read as
+const TYPES: &[(&str, &str)] = &[
+    ("text/html", "html"),
+    ("text/html", "htm"),
+    ("application/xhtml+xml", "xhtml"),
+    ("text/plain", "txt"),
+    ("text/css", "css"),
+    ("text/csv", "csv"),
+    ("text/javascript", "js"),
+    ("application/javascript", "js"),
+    ("application/json", "json"),
+    ("application/xml", "xml"),
+    ("text/xml", "xml"),
+    ("application/rss+xml", "rss"),
+    ("application/atom+xml", "atom"),
+    ("application/pdf", "pdf"),
+    ("application/postscript", "ps"),
+    ("application/rtf", "rtf"),
+    ("application/zip", "zip"),
+    ("application/x-zip-compressed", "zip"),
+    ("application/gzip", "gz"),
+    ("application/x-rar-compressed", "rar"),
+    ("application/x-7z-compressed", "7z"),
+    ("application/msword", "doc"),
+    (
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "docx",
+    ),
+    ("application/vnd.ms-excel", "xls"),
+    (
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "xlsx",
+    ),
+    ("application/vnd.ms-powerpoint", "ppt"),
+    (
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "pptx",
+    ),
+    ("application/vnd.oasis.opendocument.text", "odt"),
+    ("application/vnd.oasis.opendocument.spreadsheet", "ods"),
+    ("application/vnd.oasis.opendocument.presentation", "odp"),
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/jpeg", "jpeg"),
+    ("image/jpg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/svg+xml", "svg"),
+    ("image/bmp", "bmp"),
+    ("image/tiff", "tif"),
+    ("image/tiff", "tiff"),
+    ("image/x-icon", "ico"),
+    ("image/vnd.microsoft.icon", "ico"),
+    ("audio/mpeg", "mp3"),
+    ("audio/wav", "wav"),
+    ("audio/ogg", "ogg"),
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("video/quicktime", "mov"),
+    ("font/woff", "woff"),
+    ("font/woff2", "woff2"),
+];
+
+/// type saying nothing about the content
+const GENERIC_TYPES: [&str; 3] = [
+    "application/octet-stream",
+    "binary/octet-stream",
+    "application/unknown",
+];
+
+/// saved extension of a body nothing is known about
+pub const UNKNOWN_EXTENSION: &str = "bin";
+
+/// `type/subtype` of the `Content-Type` of `headers`, lowercase,
+/// without parameters
+pub fn content_type(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(CONTENT_TYPE)?.to_str().ok()?;
+    let essence = value.split(';').next().unwrap_or_default().trim();
+    if essence.is_empty() {
+        return None;
+    }
+    Some(essence.to_ascii_lowercase())
+}
+
+/// value of `table` for `mime` by `type/subtype`, otherwise `type/*`
+pub fn lookup<'a, T>(table: &'a HashMap<String, T>, mime: &str) -> Option<&'a T> {
+    let wildcard = format!("{}/*", mime.split('/').next().unwrap_or_default());
+    table.get(mime).or_else(|| table.get(&wildcard))
+}
+
+/// whether `pattern` is `type/subtype` or `type/*`
+pub fn is_type_pattern(pattern: &str) -> bool {
+    match pattern.split_once('/') {
+        Some((kind, subtype)) => {
+            !kind.is_empty() && kind != "*" && !subtype.is_empty() && !subtype.contains('/')
+        }
+        None => false,
+    }
+}
+
+/// extension saved for `mime`, parameters ignored
+pub fn mime_extension(mime: &str) -> Option<&'static str> {
+    let essence = mime.split(';').next().unwrap_or_default().trim();
+    TYPES
+        .iter()
+        .find(|(t, _)| t.eq_ignore_ascii_case(essence))
+        .map(|(_, e)| *e)
+}
+
+/// MIME type of a file with `extension`, `.` excluded
+pub fn extension_mime(extension: &str) -> Option<&'static str> {
+    TYPES
+        .iter()
+        .find(|(_, e)| e.eq_ignore_ascii_case(extension))
+        .map(|(t, _)| *t)
+}
+
+/// known extension of the file `name`, lowercase\
+/// only what follows the last `/` and the last `.` counts
+pub fn name_extension(name: &str) -> Option<&'static str> {
+    let name = name.rsplit('/').next().unwrap_or_default();
+    let (_, extension) = name.rsplit_once('.')?;
+    TYPES
+        .iter()
+        .find(|(_, e)| e.eq_ignore_ascii_case(extension))
+        .map(|(_, e)| *e)
+}
+
+/// known extension of the last segment of the path of `url`
+pub fn url_extension(url: &Url) -> Option<&'static str> {
+    name_extension(url.path())
+}
+
+/// MIME type of `body` guessed from its first bytes
+pub fn sniff(body: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"%PDF-", "application/pdf"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"II*\x00", "image/tiff"),
+        (b"MM\x00*", "image/tiff"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"Rar!\x1a\x07", "application/x-rar-compressed"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"{\\rtf", "application/rtf"),
+        (b"%!PS", "application/postscript"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+    ];
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(s, _)| body.starts_with(s)) {
+        return Some(mime);
+    }
+    if body.len() >= 12 && body.starts_with(b"RIFF") {
+        match &body[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            _ => {}
+        }
+    }
+    if body.len() >= 12 && &body[4..8] == b"ftyp" {
+        return Some(if &body[8..10] == b"qt" {
+            "video/quicktime"
+        } else {
+            "video/mp4"
+        });
+    }
+    sniff_markup(body)
+}
+
+/// MIME type of markup `body`, after any byte order mark and whitespace
+fn sniff_markup(body: &[u8]) -> Option<&'static str> {
+    // the tags HTML documents start with, as browsers sniff them
+    const HTML_TAGS: [&str; 17] = [
+        "<!doctype html",
+        "<html",
+        "<head",
+        "<body",
+        "<script",
+        "<iframe",
+        "<h1",
+        "<div",
+        "<font",
+        "<table",
+        "<a",
+        "<style",
+        "<title",
+        "<b",
+        "<br",
+        "<p",
+        "<!--",
+    ];
+    let body = body.strip_prefix(b"\xef\xbb\xbf").unwrap_or(body);
+    let start = body.iter().position(|b| !b.is_ascii_whitespace())?;
+    let head = &body[start..body.len().min(start + 512)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+
+    if head.starts_with("<?xml") {
+        if head.contains("<svg") {
+            return Some("image/svg+xml");
+        }
+        if head.contains("<html") {
+            return Some("application/xhtml+xml");
+        }
+        return Some("application/xml");
+    }
+    if head.starts_with("<svg") {
+        return Some("image/svg+xml");
+    }
+    let is_html = HTML_TAGS.iter().any(|tag| {
+        let next = head.strip_prefix(tag).and_then(|rest| rest.bytes().next());
+        // the tag ends, `<b` is not `<button`
+        matches!(next, Some(b) if b == b' ' || b == b'>' || *tag == "<!--")
+    });
+    is_html.then_some("text/html")
+}
+
+/// file name of a `Content-Disposition` header `value`\
+/// `filename*=UTF-8''…` preferred over `filename=…`
+pub fn disposition_filename(value: &str) -> Option<String> {
+    let mut plain = None;
+    for param in value.split(';').skip(1) {
+        let (name, value) = match param.split_once('=') {
+            Some(p) => p,
+            None => continue,
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "filename*" => {
+                // charset'language'percent-encoded
+                let encoded = value.splitn(3, '\'').nth(2)?;
+                return Some(percent_decode(encoded));
+            }
+            "filename" => {
+                let value = value.trim_matches('"');
+                plain = Some(value.replace("\\\"", "\""));
+            }
+            _ => {}
+        }
+    }
+    plain.filter(|name| !name.is_empty())
+}
+
+/// extension to save `body` with, `.` included, first known of
+/// 1. the file name of `Content-Disposition`
+/// 2. `Content-Type`, unless `application/octet-stream` or alike
+/// 3. the first bytes of `body`
+/// 4. the path of `url`
+/// 5. `.bin`
+///
+/// only extensions of `TYPES` are used, so never anything unsafe to save
+pub fn choose_extension(headers: &HeaderMap, url: &Url, body: &[u8]) -> String {
+    let from_disposition = || {
+        let value = headers.get(CONTENT_DISPOSITION)?.to_str().ok()?;
+        name_extension(&disposition_filename(value)?)
+    };
+    let from_type = || {
+        let mime = content_type(headers)?;
+        if GENERIC_TYPES.contains(&mime.as_str()) {
+            return None;
+        }
+        mime_extension(&mime)
+    };
+    let extension = from_disposition()
+        .or_else(from_type)
+        .or_else(|| sniff(body).and_then(mime_extension))
+        .or_else(|| url_extension(url))
+        .unwrap_or(UNKNOWN_EXTENSION);
+    format!(".{}", extension)
+}
@@ -0,0 +1,220 @@
+use reqwest::Url;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt,
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::Mutex,
+};
+
+/// words hashed together into one feature of `simhash`
+const SHINGLE: usize = 3;
+
+/// how near pages must be to count as near-duplicates
+/// and how many of them a link family may yield
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NearDupConfig {
+    /// largest number of `simhash` bits that differ, below 32
+    pub max_distance: u32,
+    /// near-duplicates after which the links of a family are not followed
+    pub family_limit: usize,
+}
+
+impl Default for NearDupConfig {
+    fn default() -> NearDupConfig {
+        NearDupConfig {
+            max_distance: 3,
+            family_limit: 5,
+        }
+    }
+}
+
+/// `MAX_DISTANCE` or `MAX_DISTANCE:FAMILY_LIMIT`
+impl FromStr for NearDupConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<NearDupConfig, String> {
+        let (distance, limit) = match s.split_once(':') {
+            Some((distance, limit)) => (distance, Some(limit)),
+            None => (s, None),
+        };
+        let max_distance = match distance.parse() {
+            Ok(distance) if distance < 32 => distance,
+            _ => return Err(format!("{} is not a distance below 32", distance)),
+        };
+        let family_limit = match limit.map(str::parse) {
+            None => NearDupConfig::default().family_limit,
+            Some(Ok(limit)) if limit > 0 => limit,
+            Some(_) => return Err(format!("{} is not a positive limit", limit.unwrap())),
+        };
+        Ok(NearDupConfig {
+            max_distance,
+            family_limit,
+        })
+    }
+}
+
+impl fmt::Display for NearDupConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.max_distance, self.family_limit)
+    }
+}
+
+/// 64-bit SimHash of the lowercased words of `text`\
+/// features are runs of `SHINGLE` words, so reordering counts too\
+/// texts differing in a few words differ in a few bits
+pub fn simhash(text: &str) -> u64 {
+    let words: Vec<String> = text.split_whitespace().map(str::to_lowercase).collect();
+    let mut counts = [0i64; 64];
+    for shingle in words.windows(SHINGLE.min(words.len()).max(1)) {
+        let mut hasher = DefaultHasher::new();
+        shingle.hash(&mut hasher);
+        let hash = hasher.finish();
+        for (bit, count) in counts.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *count += 1;
+            } else {
+                *count -= 1;
+            }
+        }
+    }
+    counts
+        .iter()
+        .enumerate()
+        .filter(|(_, count)| **count > 0)
+        .fold(0, |hash, (bit, _)| hash | (1 << bit))
+}
+
+/// the URL likely generated by the same template as `url`:
+/// host, path with digit runs as `#` and query parameter names\
+/// the last path segment is dropped unless it is the only one
+pub fn link_family(url: &Url) -> String {
+    let mut segments: Vec<&str> = url.path().split('/').collect();
+    if segments.len() > 2 {
+        segments.pop();
+    }
+    let mut family = url.host_str().unwrap_or_default().to_owned();
+    let mut in_digits = false;
+    for c in segments.join("/").chars() {
+        if c.is_ascii_digit() {
+            if !in_digits {
+                family.push('#');
+            }
+            in_digits = true;
+        } else {
+            family.push(c);
+            in_digits = false;
+        }
+    }
+
+    let mut names: Vec<String> = url
+        .query_pairs()
+        .map(|(name, _)| name.into_owned())
+        .collect();
+    if !names.is_empty() {
+        names.sort();
+        names.dedup();
+        family.push('?');
+        family.push_str(&names.join("&"));
+    }
+    family
+}
+
+/// what `NearDuplicates` remembers
+#[derive(Debug, Default)]
+struct Seen {
+    /// fingerprint and URL of every page observed
+    pages: Vec<(u64, Url)>,
+    /// index of `pages` by band number and bits of the band
+    bands: HashMap<(usize, u64), Vec<usize>>,
+    /// near-duplicates found in each link family
+    families: HashMap<String, usize>,
+}
+
+/// near-duplicate pages found during a crawl\
+/// fingerprints within `max_distance` bits share at least one of
+/// `max_distance + 1` bands, so only pages sharing a band are compared
+#[derive(Debug)]
+pub struct NearDuplicates {
+    config: NearDupConfig,
+    seen: Mutex<Seen>,
+}
+
+impl NearDuplicates {
+    pub fn new(config: NearDupConfig) -> NearDuplicates {
+        NearDuplicates {
+            config,
+            seen: Mutex::new(Seen::default()),
+        }
+    }
+
+    pub fn config(&self) -> NearDupConfig {
+        self.config
+    }
+
+    /// bits of every band of `fingerprint`
+    fn bands(&self, fingerprint: u64) -> impl Iterator<Item = (usize, u64)> {
+        let count = self.config.max_distance as usize + 1;
+        (0..count).map(move |band| {
+            let start = band * 64 / count;
+            let end = (band + 1) * 64 / count;
+            let mask = if end - start == 64 {
+                u64::MAX
+            } else {
+                (1 << (end - start)) - 1
+            };
+            (band, (fingerprint >> start) & mask)
+        })
+    }
+
+    /// record `url` with visible `text`\
+    /// a near-duplicate counts against the link family of `url`
+    /// # return
+    /// an earlier URL with nearly the same text, if any
+    pub fn observe(&self, url: &Url, text: &str) -> Option<Url> {
+        let fingerprint = simhash(text);
+        let mut seen = self.seen.lock().unwrap();
+
+        let near = self
+            .bands(fingerprint)
+            .filter_map(|band| seen.bands.get(&band))
+            .flatten()
+            .map(|i| &seen.pages[*i])
+            .find(|(other, other_url)| {
+                (fingerprint ^ other).count_ones() <= self.config.max_distance && other_url != url
+            })
+            .map(|(_, other_url)| other_url.clone());
+        if near.is_some() {
+            *seen.families.entry(link_family(url)).or_default() += 1;
+        }
+
+        let index = seen.pages.len();
+        seen.pages.push((fingerprint, url.clone()));
+        for band in self.bands(fingerprint) {
+            seen.bands.entry(band).or_default().push(index);
+        }
+        near
+    }
+
+    /// whether the link family of `url` yielded `family_limit` near-duplicates
+    pub fn is_exhausted(&self, url: &Url) -> bool {
+        let seen = self.seen.lock().unwrap();
+        matches!(
+            seen.families.get(&link_family(url)),
+            Some(count) if *count >= self.config.family_limit
+        )
+    }
+
+    /// link families not followed any more and their near-duplicates, sorted
+    pub fn exhausted_families(&self) -> Vec<(String, usize)> {
+        let seen = self.seen.lock().unwrap();
+        let mut families: Vec<(String, usize)> = seen
+            .families
+            .iter()
+            .filter(|(_, count)| **count >= self.config.family_limit)
+            .map(|(family, count)| (family.clone(), *count))
+            .collect();
+        families.sort();
+        families
+    }
+}
@@ -0,0 +1,138 @@
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode, Url,
+};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+/// whether a failed fetch may succeed later
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailureKind {
+    /// timeouts, dropped connections, 408, 425, 429 and most 5xx
+    Retryable,
+    /// everything else, retrying would fail the same way
+    Permanent,
+}
+
+/// classify a response with an error `status`
+pub fn classify_status(status: StatusCode) -> FailureKind {
+    match status.as_u16() {
+        408 | 425 | 429 => FailureKind::Retryable,
+        // the server will not support the request later either
+        501 | 505 => FailureKind::Permanent,
+        500..=599 => FailureKind::Retryable,
+        _ => FailureKind::Permanent,
+    }
+}
+
+/// classify a request that got no response, or whose body broke off
+pub fn classify_error(err: &reqwest::Error) -> FailureKind {
+    if let Some(status) = err.status() {
+        return classify_status(status);
+    }
+    if err.is_timeout() || err.is_connect() || err.is_request() || err.is_body() {
+        FailureKind::Retryable
+    } else {
+        // builder, redirect loop or undecodable body
+        FailureKind::Permanent
+    }
+}
+
+/// `Retry-After` of `headers` as seconds or HTTP date\
+/// a date in the past is zero
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(secs) = value.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// how often and how long after failing a URL is fetched again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// fetches of a URL in total, 1 to never retry
+    pub attempts: usize,
+    /// delay after the first failure, doubled after each
+    pub base_delay: Duration,
+    /// longest delay, a longer `Retry-After` gives up
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// exponential backoff after `failures` failed fetches\
+    /// randomly between half and all of `base_delay * 2^(failures - 1)`
+    /// so the retries of many URL spread out
+    pub fn backoff(&self, failures: usize) -> Duration {
+        let exponent = failures.saturating_sub(1).min(31) as u32;
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.pow(exponent))
+            .min(self.max_delay);
+        delay.mul_f64(0.5 + fastrand::f64() / 2.0)
+    }
+
+    /// delay before fetching again after `failures` failed fetches,
+    /// `retry_after` if the server sent one
+    /// # return
+    /// `None` if out of attempts or asked to wait longer than `max_delay`
+    pub fn next_delay(&self, failures: usize, retry_after: Option<Duration>) -> Option<Duration> {
+        if failures >= self.attempts {
+            return None;
+        }
+        match retry_after {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay),
+            None => Some(self.backoff(failures)),
+        }
+    }
+}
+
+/// failures of the URL being retried during a crawl
+#[derive(Debug, Default)]
+pub struct Retries {
+    failures: Mutex<HashMap<Url, usize>>,
+}
+
+impl Retries {
+    /// record a failed fetch of `url`
+    /// # return
+    /// failed fetches of `url` so far
+    pub fn fail(&self, url: &Url) -> usize {
+        let mut failures = self.failures.lock().unwrap();
+        let count = failures.entry(url.clone()).or_default();
+        *count += 1;
+        *count
+    }
+
+    /// forget the failures of `url` once it is done with
+    pub fn forget(&self, url: &Url) {
+        self.failures.lock().unwrap().remove(url);
+    }
+
+    /// URL failed and not done with yet
+    pub fn len(&self) -> usize {
+        self.failures.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,302 @@
+use reqwest::{Client, Url};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{sync::OnceCell, time::sleep};
+
+use crate::retry::RetryPolicy;
+
+/// user agent sent with every request and matched against robots.txt
+pub const USER_AGENT: &str = "search_so_scraper";
+
+/// one `Allow` or `Disallow` line
+#[derive(Debug, Clone)]
+struct Rule {
+    allow: bool,
+    pattern: String,
+}
+
+/// rules under one or more consecutive `User-agent` lines
+#[derive(Debug, Clone, Default)]
+struct Group {
+    agents: Vec<String>,
+    rules: Vec<Rule>,
+    crawl_delay: Option<Duration>,
+}
+
+/// parsed robots.txt of one host
+#[derive(Debug, Clone, Default)]
+pub struct Robots {
+    groups: Vec<Group>,
+    disallow_all: bool,
+}
+
+impl Robots {
+    /// parse the content of a robots.txt\
+    /// unknown fields and malformed lines are ignored
+    pub fn parse(content: &str) -> Robots {
+        let mut groups: Vec<Group> = Vec::new();
+        // whether the last group can still take more `User-agent` lines
+        let mut in_agent_lines = false;
+
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let (key, value) = match line.split_once(':') {
+                Some((key, value)) => (key.trim().to_lowercase(), value.trim()),
+                None => continue,
+            };
+
+            match key.as_str() {
+                "user-agent" => {
+                    if !in_agent_lines {
+                        groups.push(Group::default());
+                        in_agent_lines = true;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.agents.push(value.to_lowercase());
+                    }
+                }
+                "allow" | "disallow" => {
+                    in_agent_lines = false;
+                    // an empty `Disallow` allows everything
+                    if value.is_empty() {
+                        continue;
+                    }
+                    if let Some(group) = groups.last_mut() {
+                        group.rules.push(Rule {
+                            allow: key == "allow",
+                            pattern: value.to_owned(),
+                        });
+                    }
+                }
+                "crawl-delay" => {
+                    in_agent_lines = false;
+                    if let (Some(group), Ok(secs)) = (groups.last_mut(), value.parse::<f64>()) {
+                        if secs.is_finite() && secs >= 0.0 {
+                            group.crawl_delay = Some(Duration::from_secs_f64(secs));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Robots {
+            groups,
+            disallow_all: false,
+        }
+    }
+
+    /// a robots.txt that allows everything\
+    /// used when the host has none
+    pub fn allow_all() -> Robots {
+        Robots::default()
+    }
+
+    /// a robots.txt that disallows everything\
+    /// used when the host's robots.txt is unreachable
+    pub fn disallow_all() -> Robots {
+        Robots {
+            groups: Vec::new(),
+            disallow_all: true,
+        }
+    }
+
+    /// find the group for `user_agent`\
+    /// the longest matching agent name wins, `*` as fallback
+    fn group_for(&self, user_agent: &str) -> Option<&Group> {
+        let user_agent = user_agent.to_lowercase();
+        let mut best: Option<(usize, &Group)> = None;
+        let mut fallback = None;
+
+        for group in &self.groups {
+            for agent in &group.agents {
+                if agent == "*" {
+                    fallback.get_or_insert(group);
+                } else if user_agent.contains(agent.as_str())
+                    && !matches!(best, Some((len, _)) if agent.len() <= len)
+                {
+                    best = Some((agent.len(), group));
+                }
+            }
+        }
+
+        best.map(|(_, group)| group).or(fallback)
+    }
+
+    /// check if `user_agent` may fetch `url`\
+    /// the longest matching pattern decides, `Allow` wins a tie
+    pub fn is_allowed(&self, user_agent: &str, url: &Url) -> bool {
+        if self.disallow_all {
+            return url.path() == "/robots.txt";
+        }
+        let group = match self.group_for(user_agent) {
+            Some(group) => group,
+            None => return true,
+        };
+
+        let path = match url.query() {
+            Some(query) => format!("{}?{}", url.path(), query),
+            None => url.path().to_owned(),
+        };
+
+        let mut decision: Option<(usize, bool)> = None;
+        for rule in &group.rules {
+            if !pattern_match(&rule.pattern, &path) {
+                continue;
+            }
+            let len = rule.pattern.len();
+            match decision {
+                Some((best_len, best_allow))
+                    if best_len > len || (best_len == len && (best_allow || !rule.allow)) => {}
+                _ => decision = Some((len, rule.allow)),
+            }
+        }
+
+        !matches!(decision, Some((_, false)))
+    }
+
+    /// `Crawl-delay` for `user_agent` if any
+    pub fn crawl_delay(&self, user_agent: &str) -> Option<Duration> {
+        self.group_for(user_agent)
+            .and_then(|group| group.crawl_delay)
+    }
+}
+
+/// match robots.txt `pattern` against the start of `path`\
+/// `*` matches any sequence, a trailing `$` anchors the end
+fn pattern_match(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+    let mut pieces = pattern.split('*');
+
+    // the first piece must match at the very start
+    let first = pieces.next().unwrap_or_default();
+    if !path.starts_with(first) {
+        return false;
+    }
+    let mut rest = &path[first.len()..];
+    let pieces: Vec<&str> = pieces.collect();
+
+    for (i, piece) in pieces.iter().enumerate() {
+        if anchored && i == pieces.len() - 1 {
+            // the last piece must match at the very end
+            return rest.ends_with(piece);
+        }
+        match rest.find(piece) {
+            Some(at) => rest = &rest[at + piece.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+/// robots.txt of every host seen, fetched once per host
+pub struct RobotsCache {
+    client: Client,
+    user_agent: String,
+    /// fetches of a robots.txt unreachable or answered with 5xx
+    retry: RetryPolicy,
+    /// by origin, set once fetched
+    cache: Mutex<HashMap<String, Arc<OnceCell<Arc<Robots>>>>>,
+}
+
+impl RobotsCache {
+    /// construct a new `RobotsCache` fetching with `client`,
+    /// see `Config::robots_cache`
+    pub fn new(client: Client, user_agent: &str, retry: RetryPolicy) -> RobotsCache {
+        RobotsCache {
+            client,
+            user_agent: user_agent.to_owned(),
+            retry,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// set the robots.txt for the host of `url` without fetching
+    pub fn insert(&self, url: &Url, robots: Robots) {
+        let mut cache = self.cache.lock().unwrap();
+        let cell = OnceCell::new_with(Some(Arc::new(robots)));
+        cache.insert(url.origin().ascii_serialization(), Arc::new(cell));
+    }
+
+    /// get the robots.txt for the host of `url`\
+    /// fetch and cache it if not cached, other lookups of the host waiting for it\
+    /// disallow all without caching if it cannot be fetched,
+    /// so the next lookup tries again
+    pub async fn get(&self, url: &Url) -> Arc<Robots> {
+        let origin = url.origin().ascii_serialization();
+        let cell = {
+            let mut cache = self.cache.lock().unwrap();
+            Arc::clone(cache.entry(origin).or_default())
+        }; // cache unlock
+
+        match cell.get_or_try_init(|| self.fetch_retrying(url)).await {
+            Ok(robots) => Arc::clone(robots),
+            Err(()) => Arc::new(Robots::disallow_all()),
+        }
+    }
+
+    /// check if `url` may be crawled
+    pub async fn is_allowed(&self, url: &Url) -> bool {
+        self.get(url).await.is_allowed(&self.user_agent, url)
+    }
+
+    /// `Crawl-delay` for the host of `url` if any
+    pub async fn crawl_delay(&self, url: &Url) -> Option<Duration> {
+        self.get(url).await.crawl_delay(&self.user_agent)
+    }
+
+    /// `fetch` until it succeeds or `retry` runs out of attempts
+    async fn fetch_retrying(&self, url: &Url) -> Result<Arc<Robots>, ()> {
+        let mut failures = 0;
+        loop {
+            match self.fetch(url).await {
+                Ok(robots) => return Ok(Arc::new(robots)),
+                Err(err) => println!("robots.txt: {}", err),
+            }
+            failures += 1;
+            match self.retry.next_delay(failures, None) {
+                Some(delay) => sleep(delay).await,
+                None => return Err(()),
+            }
+        }
+    }
+
+    /// fetch robots.txt for the host of `url`
+    /// # return
+    /// the parsed robots.txt for 2xx\
+    /// allow all for 4xx or if `url` has no host\
+    /// the error for 5xx or if unreachable
+    async fn fetch(&self, url: &Url) -> Result<Robots, String> {
+        let robots_url = match url.join("/robots.txt") {
+            Ok(u) if url.has_host() => u,
+            _ => return Ok(Robots::allow_all()),
+        };
+
+        let response = self
+            .client
+            .get(robots_url.clone())
+            .send()
+            .await
+            .map_err(|err| format!("{} | {}", err, robots_url))?;
+
+        let status = response.status();
+        if status.is_client_error() {
+            return Ok(Robots::allow_all());
+        }
+        if !status.is_success() {
+            return Err(format!("status {} | {}", status, robots_url));
+        }
+
+        match response.text().await {
+            Ok(content) => Ok(Robots::parse(&content)),
+            Err(err) => Err(format!("{} | {}", err, robots_url)),
+        }
+    }
+}
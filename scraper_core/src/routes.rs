@@ -0,0 +1,76 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use crate::mime::lookup;
+
+/// what the crawler does with a response other than HTML
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// store the body as is
+    Save,
+    /// drop it without downloading the body
+    Skip,
+    /// store it and follow the URL in it, `url()` and `@import` of CSS
+    Links,
+    /// store it with its text, see `extract::body_text`
+    Text,
+    /// hand it to the image pipeline if any, otherwise store it
+    Image,
+}
+
+impl Action {
+    const ALL: [Action; 5] = [
+        Action::Save,
+        Action::Skip,
+        Action::Links,
+        Action::Text,
+        Action::Image,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Save => "save",
+            Action::Skip => "skip",
+            Action::Links => "links",
+            Action::Text => "text",
+            Action::Image => "image",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Action, String> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<&str> = Action::ALL.iter().map(|a| a.name()).collect();
+                format!("unknown action `{}`, expected {}", s, names.join(", "))
+            })
+    }
+}
+
+/// action by `type/subtype`, `type/*` or `*/*`, the most specific first\
+/// a response without `Content-Type` is `application/octet-stream`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Routes {
+    pub actions: HashMap<String, Action>,
+}
+
+impl Routes {
+    /// action for `mime`, `Save` if no pattern matches
+    pub fn action(&self, mime: Option<&str>) -> Action {
+        let mime = mime.unwrap_or("application/octet-stream");
+        lookup(&self.actions, mime)
+            .or_else(|| self.actions.get("*/*"))
+            .copied()
+            .unwrap_or(Action::Save)
+    }
+}
@@ -0,0 +1,185 @@
+use regex::{escape, Regex, RegexSet};
+use reqwest::Url;
+use std::{fmt, fs::read_to_string, path::Path};
+
+/// how the pattern of a `Rule` matches URL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuleKind {
+    /// regex found anywhere in the URL
+    Regex,
+    /// the whole URL, `*` within a path segment, `**` across, `?` one character
+    Glob,
+    /// the host or any of its subdomains
+    Host,
+    /// the start of the path, from the `/` after the host
+    Prefix,
+}
+
+impl RuleKind {
+    const ALL: [RuleKind; 4] = [
+        RuleKind::Regex,
+        RuleKind::Glob,
+        RuleKind::Host,
+        RuleKind::Prefix,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            RuleKind::Regex => "regex",
+            RuleKind::Glob => "glob",
+            RuleKind::Host => "host",
+            RuleKind::Prefix => "prefix",
+        }
+    }
+}
+
+/// one rule and where it was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rule {
+    pub kind: RuleKind,
+    pub pattern: String,
+    /// `FILE:LINE` or config key
+    pub origin: String,
+}
+
+impl Rule {
+    /// parse `KIND:PATTERN`, a line without a known kind is a regex
+    /// # return
+    /// the problem with the pattern, if any
+    pub fn parse(line: &str, origin: String) -> Result<Rule, String> {
+        let line = line.trim();
+        let (kind, pattern) = RuleKind::ALL
+            .iter()
+            .find_map(|kind| {
+                let pattern = line.strip_prefix(kind.name())?.strip_prefix(':')?;
+                Some((*kind, pattern))
+            })
+            .unwrap_or((RuleKind::Regex, line));
+        if pattern.is_empty() {
+            return Err(format!("empty {} rule", kind.name()));
+        }
+        if kind == RuleKind::Prefix && !pattern.starts_with('/') {
+            return Err(format!("prefix `{}` does not start with `/`", pattern));
+        }
+        let rule = Rule {
+            kind,
+            pattern: pattern.to_owned(),
+            origin,
+        };
+        // the regex error spans several lines
+        Regex::new(&rule.to_regex()).map_err(|e| e.to_string().replace('\n', " "))?;
+        Ok(rule)
+    }
+
+    /// regex matching the same URL
+    pub fn to_regex(&self) -> String {
+        match self.kind {
+            RuleKind::Regex => self.pattern.clone(),
+            RuleKind::Glob => {
+                let mut regex = String::from("^");
+                let mut chars = self.pattern.chars().peekable();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '*' if chars.peek() == Some(&'*') => {
+                            chars.next();
+                            regex.push_str(".*");
+                        }
+                        '*' => regex.push_str("[^/]*"),
+                        '?' => regex.push_str("[^/]"),
+                        c => regex.push_str(&escape(&c.to_string())),
+                    }
+                }
+                regex.push('$');
+                regex
+            }
+            RuleKind::Host => format!(
+                r"^[^:]+://(?:[^/?#@]*@)?(?:[^/?#@]*\.)?{}(?::\d+)?(?:[/?#]|$)",
+                escape(&self.pattern.to_lowercase())
+            ),
+            RuleKind::Prefix => format!(r"^[^:]+://[^/?#]*{}", escape(&self.pattern)),
+        }
+    }
+}
+
+/// `ORIGIN: KIND:PATTERN`
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}:{}", self.origin, self.kind.name(), self.pattern)
+    }
+}
+
+/// parse one rule each line of `text`\
+/// empty lines and lines starting with `#` are skipped
+/// # return
+/// every problem found as `NAME:LINE: problem`, one each line
+pub fn parse_rules(text: &str, name: &str) -> Result<Vec<Rule>, String> {
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let origin = format!("{}:{}", name, i + 1);
+        match Rule::parse(line, origin.clone()) {
+            Ok(rule) => rules.push(rule),
+            Err(e) => errors.push(format!("{}: {}", origin, e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(rules)
+    } else {
+        Err(errors.join("\n"))
+    }
+}
+
+/// `parse_rules` of the file at `path`
+pub fn read_rules(path: &Path) -> Result<Vec<Rule>, String> {
+    let text = read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    parse_rules(&text, &path.display().to_string())
+}
+
+/// rules matched together by one `RegexSet`
+#[derive(Debug, Clone)]
+pub struct RuleList {
+    rules: Vec<Rule>,
+    set: RegexSet,
+}
+
+impl Default for RuleList {
+    fn default() -> RuleList {
+        RuleList {
+            rules: Vec::new(),
+            set: RegexSet::empty(),
+        }
+    }
+}
+
+impl RuleList {
+    /// fails only if the set grows too large
+    pub fn new(rules: Vec<Rule>) -> Result<RuleList, regex::Error> {
+        let set = RegexSet::new(rules.iter().map(Rule::to_regex))?;
+        Ok(RuleList { rules, set })
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn is_match(&self, url: &Url) -> bool {
+        self.set.is_match(url.as_str())
+    }
+
+    /// every rule matching `url`, in order
+    pub fn matches(&self, url: &Url) -> Vec<&Rule> {
+        self.set
+            .matches(url.as_str())
+            .into_iter()
+            .map(|i| &self.rules[i])
+            .collect()
+    }
+}
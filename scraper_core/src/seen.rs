@@ -0,0 +1,365 @@
+use reqwest::Url;
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    fmt,
+    hash::{Hash, Hasher},
+    mem::size_of,
+    str::FromStr,
+    sync::{Mutex, MutexGuard},
+};
+
+/// number of independently locked parts of a `SeenSet`
+const SHARDS: usize = 64;
+
+/// URL each shard's first Bloom filter holds before a larger one is added
+const BLOOM_INITIAL_CAPACITY: usize = 1024;
+
+/// how a `SeenSet` remembers checked URL
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SeenBackend {
+    /// every URL exactly, in memory
+    #[default]
+    Exact,
+    /// checked URL in a scalable Bloom filter, unchecked ones exactly\
+    /// a new URL is taken as checked with probability `false_positive`\
+    /// checkpoints keep only the unchecked URL, so an interrupted crawl
+    /// cannot be resumed\
+    /// only the URL seen are bounded, the pages stored and their bodies
+    /// are still remembered exactly
+    Bloom { false_positive: f64 },
+}
+
+/// `exact`, `bloom` or `bloom:FALSE_POSITIVE_RATE`
+impl FromStr for SeenBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SeenBackend, String> {
+        match s.split_once(':') {
+            None if s == "exact" => Ok(SeenBackend::Exact),
+            None if s == "bloom" => Ok(SeenBackend::Bloom {
+                false_positive: 0.001,
+            }),
+            Some(("bloom", rate)) => match rate.parse() {
+                Ok(rate) if rate > 0.0 && rate < 1.0 => Ok(SeenBackend::Bloom {
+                    false_positive: rate,
+                }),
+                _ => Err(format!("{} is not a rate between 0 and 1", rate)),
+            },
+            _ => Err(format!(
+                "{} is not exact, bloom or bloom:FALSE_POSITIVE_RATE",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SeenBackend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SeenBackend::Exact => write!(f, "exact"),
+            SeenBackend::Bloom { false_positive } => write!(f, "bloom:{}", false_positive),
+        }
+    }
+}
+
+/// fixed-size Bloom filter
+#[derive(Debug)]
+struct BloomFilter {
+    bits: Vec<u64>,
+    /// number of hash functions
+    hashes: u64,
+    capacity: usize,
+    len: usize,
+}
+
+impl BloomFilter {
+    /// sized for `capacity` items at `false_positive` rate
+    fn new(capacity: usize, false_positive: f64) -> BloomFilter {
+        let ln2 = std::f64::consts::LN_2;
+        let bit_count = (-(capacity as f64) * false_positive.ln() / (ln2 * ln2)).ceil() as usize;
+        let words = bit_count.max(64) / 64 + 1;
+        let hashes = ((words * 64) as f64 / capacity as f64 * ln2)
+            .round()
+            .max(1.0) as u64;
+        BloomFilter {
+            bits: vec![0; words],
+            hashes,
+            capacity,
+            len: 0,
+        }
+    }
+
+    /// bit positions of the item hashed to `(h1, h2)`, by double hashing
+    fn positions(&self, (h1, h2): (u64, u64)) -> impl Iterator<Item = usize> {
+        let bit_count = self.bits.len() as u64 * 64;
+        (0..self.hashes).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bit_count) as usize)
+    }
+
+    fn contains(&self, hash: (u64, u64)) -> bool {
+        self.positions(hash)
+            .all(|p| self.bits[p / 64] & (1 << (p % 64)) != 0)
+    }
+
+    fn insert(&mut self, hash: (u64, u64)) {
+        for p in self.positions(hash).collect::<Vec<_>>() {
+            self.bits[p / 64] |= 1 << (p % 64);
+        }
+        self.len += 1;
+    }
+}
+
+/// Bloom filters added twice as large and with half the rate when full\
+/// so the total rate stays under `false_positive`
+#[derive(Debug)]
+struct ScalableBloom {
+    filters: Vec<BloomFilter>,
+    false_positive: f64,
+}
+
+impl ScalableBloom {
+    fn new(false_positive: f64) -> ScalableBloom {
+        ScalableBloom {
+            filters: vec![BloomFilter::new(
+                BLOOM_INITIAL_CAPACITY,
+                false_positive / 2.0,
+            )],
+            false_positive,
+        }
+    }
+
+    fn hash(url: &Url) -> (u64, u64) {
+        let hash = |seed: u8| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            url.as_str().hash(&mut hasher);
+            hasher.finish()
+        };
+        // odd step so every position can be reached
+        (hash(0), hash(1) | 1)
+    }
+
+    fn contains(&self, url: &Url) -> bool {
+        let hash = ScalableBloom::hash(url);
+        self.filters.iter().any(|f| f.contains(hash))
+    }
+
+    /// add `url` unless it is held already, so it is counted once
+    fn insert(&mut self, url: &Url) {
+        if self.contains(url) {
+            return;
+        }
+        let last = self.filters.last().unwrap();
+        if last.len >= last.capacity {
+            let rate = self.false_positive / 2f64.powi(self.filters.len() as i32 + 1);
+            let filter = BloomFilter::new(last.capacity * 2, rate);
+            self.filters.push(filter);
+        }
+        self.filters
+            .last_mut()
+            .unwrap()
+            .insert(ScalableBloom::hash(url));
+    }
+
+    fn len(&self) -> usize {
+        self.filters.iter().map(|f| f.len).sum()
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.filters.iter().map(|f| f.bits.len() * 8).sum()
+    }
+}
+
+/// one independently locked part of a `SeenSet`
+#[derive(Debug)]
+enum Shard {
+    Exact(HashMap<Url, bool>),
+    Bloom {
+        checked: ScalableBloom,
+        unchecked: HashSet<Url>,
+    },
+}
+
+impl Shard {
+    fn new(backend: SeenBackend) -> Shard {
+        match backend {
+            SeenBackend::Exact => Shard::Exact(HashMap::new()),
+            SeenBackend::Bloom { false_positive } => Shard::Bloom {
+                checked: ScalableBloom::new(false_positive),
+                unchecked: HashSet::new(),
+            },
+        }
+    }
+
+    fn get(&self, url: &Url) -> Option<bool> {
+        match self {
+            Shard::Exact(map) => map.get(url).copied(),
+            Shard::Bloom { checked, unchecked } => {
+                if unchecked.contains(url) {
+                    Some(false)
+                } else if checked.contains(url) {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn check(&mut self, url: &Url) -> Option<bool> {
+        match self {
+            Shard::Exact(map) => map.insert(url.clone(), true),
+            Shard::Bloom { checked, unchecked } => {
+                let was_unchecked = unchecked.remove(url);
+                let was_checked = checked.contains(url);
+                checked.insert(url);
+                if was_unchecked {
+                    Some(false)
+                } else if was_checked {
+                    Some(true)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, url: Url, is_checked: bool) {
+        match self {
+            Shard::Exact(map) => {
+                map.insert(url, is_checked);
+            }
+            Shard::Bloom { checked, unchecked } => {
+                if is_checked {
+                    unchecked.remove(&url);
+                    checked.insert(&url);
+                } else {
+                    unchecked.insert(url);
+                }
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Shard::Exact(map) => map.len(),
+            // unchecked again, in the filter already
+            Shard::Bloom { checked, unchecked } => {
+                checked.len() + unchecked.iter().filter(|u| !checked.contains(u)).count()
+            }
+        }
+    }
+
+    /// approximate heap size
+    fn memory_bytes(&self) -> usize {
+        match self {
+            Shard::Exact(map) => {
+                map.capacity() * (size_of::<(Url, bool)>() + 1)
+                    + map.keys().map(|u| u.as_str().len()).sum::<usize>()
+            }
+            Shard::Bloom { checked, unchecked } => {
+                checked.memory_bytes()
+                    + unchecked.capacity() * (size_of::<Url>() + 1)
+                    + unchecked.iter().map(|u| u.as_str().len()).sum::<usize>()
+            }
+        }
+    }
+}
+
+/// every URL seen, `true` once checked\
+/// split into shards by URL hash so workers rarely wait for each other
+#[derive(Debug)]
+pub struct SeenSet {
+    backend: SeenBackend,
+    shards: Vec<Mutex<Shard>>,
+}
+
+impl SeenSet {
+    /// construct a `SeenSet` on `backend` holding `known_url`
+    pub fn new(backend: SeenBackend, known_url: HashMap<Url, bool>) -> SeenSet {
+        let seen = SeenSet {
+            backend,
+            shards: (0..SHARDS)
+                .map(|_| Mutex::new(Shard::new(backend)))
+                .collect(),
+        };
+        for (url, checked) in known_url {
+            seen.shard(&url).insert(url, checked);
+        }
+        seen
+    }
+
+    fn shard(&self, url: &Url) -> MutexGuard<'_, Shard> {
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+        self.shards[hasher.finish() as usize % SHARDS]
+            .lock()
+            .unwrap()
+    }
+
+    pub fn backend(&self) -> SeenBackend {
+        self.backend
+    }
+
+    /// `Some(checked)` if `url` was seen
+    pub fn get(&self, url: &Url) -> Option<bool> {
+        self.shard(url).get(url)
+    }
+
+    /// mark `url` as checked, seen or not
+    /// # return
+    /// what `get` returned before
+    pub fn check(&self, url: &Url) -> Option<bool> {
+        self.shard(url).check(url)
+    }
+
+    /// record `url` with `checked` if it was not seen
+    /// # return
+    /// `true` if `url` is new
+    pub fn insert_new(&self, url: &Url, checked: bool) -> bool {
+        let mut shard = self.shard(url);
+        if shard.get(url).is_some() {
+            return false;
+        }
+        shard.insert(url.clone(), checked);
+        true
+    }
+
+    /// mark `url` as not checked so it is fetched again
+    pub fn uncheck(&self, url: &Url) {
+        self.shard(url).insert(url.clone(), false);
+    }
+
+    /// number of URL seen
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.lock().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// approximate heap size in bytes
+    pub fn memory_bytes(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap().memory_bytes())
+            .sum()
+    }
+
+    /// copy of every URL seen, unchecked ones only with `SeenBackend::Bloom`\
+    /// locks the shards one after the other
+    pub fn snapshot(&self) -> HashMap<Url, bool> {
+        let mut known_url = HashMap::new();
+        for shard in &self.shards {
+            match &*shard.lock().unwrap() {
+                Shard::Exact(map) => {
+                    known_url.extend(map.iter().map(|(u, c)| (u.clone(), *c)));
+                }
+                Shard::Bloom { unchecked, .. } => {
+                    known_url.extend(unchecked.iter().map(|u| (u.clone(), false)));
+                }
+            }
+        }
+        known_url
+    }
+}
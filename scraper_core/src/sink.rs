@@ -0,0 +1,112 @@
+use async_trait::async_trait;
+use reqwest::{header::HeaderMap, StatusCode, Url};
+use std::path::{Path, PathBuf};
+use tokio::{
+    fs::{metadata, rename, write},
+    time::{sleep, Duration},
+};
+
+use crate::download::{Aborted, Body};
+
+/// a successful response ready to be stored
+#[derive(Debug, Clone, Copy)]
+pub struct Fetched<'a> {
+    /// URL requested
+    pub url: &'a Url,
+    /// URL after potential redirection
+    pub final_url: &'a Url,
+    pub status: StatusCode,
+    pub headers: &'a HeaderMap,
+    /// `.html` for HTML, otherwise `mime::choose_extension`
+    pub extension: &'a str,
+    /// temporary file removed after `save` unless moved
+    pub body: &'a Body,
+    /// text extracted from `body`, see `routes::Action::Text`
+    pub text: Option<&'a str>,
+    /// robots directives say `noindex`, see `RobotsMeta::save_noindex`
+    pub noindex: bool,
+}
+
+/// stores what the crawler fetched
+#[async_trait]
+pub trait StorageSink: Send + Sync {
+    /// store a successful response
+    async fn save(&self, page: &Fetched<'_>) -> Result<(), String>;
+
+    /// `url` answered 304 Not Modified
+    async fn not_modified(&self, _url: &Url) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// `url` answered an error `status`
+    async fn failed(&self, _url: &Url, _status: StatusCode) {}
+
+    /// the body of `url` answering `status` was not downloaded in full
+    async fn aborted(&self, _url: &Url, _status: StatusCode, _reason: Aborted) {}
+}
+
+/// appended to the path of a saved file for the text extracted from it
+pub const TEXT_SUFFIX: &str = ".text";
+
+/// where the text extracted from the file at `path` is saved
+pub fn text_path(path: &Path) -> PathBuf {
+    let mut text_path = path.as_os_str().to_owned();
+    text_path.push(TEXT_SUFFIX);
+    PathBuf::from(text_path)
+}
+
+/// save every body once as `sha256(body).ext` in `dir`\
+/// and output `"final_url": "path"` to stdout for every response\
+/// with the text extracted from it in `text_path(path)` if any
+#[derive(Debug, Clone)]
+pub struct HashedFileSink {
+    pub dir: PathBuf,
+    /// attempts to write each file
+    pub attempts: usize,
+    /// pause between two attempts
+    pub retry_delay: Duration,
+}
+
+impl HashedFileSink {
+    /// save in `dir`, 3 attempts 5 sec apart
+    pub fn new(dir: PathBuf) -> HashedFileSink {
+        HashedFileSink {
+            dir,
+            attempts: 3,
+            retry_delay: Duration::from_secs(5),
+        }
+    }
+}
+
+#[async_trait]
+impl StorageSink for HashedFileSink {
+    async fn save(&self, page: &Fetched<'_>) -> Result<(), String> {
+        let path = page.body.content_hash.clone() + page.extension;
+        let file = self.dir.join(&path);
+        if let Some(text) = page.text {
+            write(text_path(&file), text)
+                .await
+                .map_err(|e| format!("{} saving the text of {}", e, path))?;
+        }
+        // identical content saved already
+        if metadata(&file).await.is_ok() {
+            println!("\"{}\": \"{}\"", page.final_url, path);
+            return Ok(());
+        }
+        // try saving file `attempts` times, sleep `retry_delay` if fail each time
+        for attempt in 0..self.attempts {
+            if attempt > 0 {
+                sleep(self.retry_delay).await;
+            }
+            match rename(&page.body.path, &file).await {
+                Ok(()) => {
+                    // output `"final_url": "path"`
+                    println!("\"{}\": \"{}\"", page.final_url, path);
+                    return Ok(());
+                }
+                Err(err) => println!("{} saving {}", err, path),
+            }
+        }
+        Err(format!("failed to save {}", path))
+    }
+}
@@ -0,0 +1,1122 @@
+use reqwest::{
+    header::{HeaderMap, HeaderValue, RETRY_AFTER},
+    StatusCode, Url,
+};
+use std::{
+    collections::HashMap,
+    env::temp_dir,
+    fs::{create_dir_all, remove_dir_all, write},
+    process,
+    time::{Duration, SystemTime},
+};
+use tokio::time::Instant;
+
+use crate::{
+    canonical::{percent_decode, Canonicalizer},
+    cli::Workdir,
+    config::{Config, CONFIG_FILE},
+    content::{content_hash, report, ContentHasher, ContentIndex},
+    download::{ByteBudget, SizeLimits},
+    extract::{body_text, visible_text},
+    filter::{AllowAll, Filter, RuleFilter},
+    frontier::{host_key, Frontier, Pop, ShardedFrontier},
+    links::{
+        canonical_link, css_links, text_links, AllLinks, AnchorsAndImages, Images, Link,
+        LinkExtractor, LinkKind,
+    },
+    meta_robots::{Directives, X_ROBOTS_TAG},
+    mime::{choose_extension, extension_mime, url_extension},
+    near_dup::{link_family, simhash, NearDupConfig, NearDuplicates},
+    retry::{classify_status, retry_after, FailureKind, Retries, RetryPolicy},
+    robots::{Robots, USER_AGENT},
+    routes::{Action, Routes},
+    rules::{parse_rules, RuleKind, RuleList},
+    seen::{SeenBackend, SeenSet},
+};
+
+const DKU_ROBOTS: &str = include_str!("../../DKU website/robots.txt");
+
+#[test]
+fn test_robots_dku() {
+    let robots = Robots::parse(DKU_ROBOTS);
+    let allowed = |u: &str| robots.is_allowed(USER_AGENT, &Url::parse(u).unwrap());
+
+    assert!(allowed("https://dukekunshan.edu.cn/"));
+    assert!(allowed("https://dukekunshan.edu.cn/en/about"));
+    assert!(!allowed("https://dukekunshan.edu.cn/admin/config"));
+    assert!(!allowed("https://dukekunshan.edu.cn/en/node/123"));
+    assert!(!allowed(
+        "https://dukekunshan.edu.cn/zh/global-health/people"
+    ));
+    // `*` wildcard
+    assert!(!allowed(
+        "https://dukekunshan.edu.cn/en/1-second-every-day?width=800px&height=650px"
+    ));
+    assert!(!allowed("https://dukekunshan.edu.cn/node/42/track/"));
+    // longest match: `Allow: /misc/*.css$` beats `Disallow: /misc/`
+    assert!(allowed("https://dukekunshan.edu.cn/misc/drupal.css"));
+    assert!(allowed("https://dukekunshan.edu.cn/misc/drupal.css?v=1"));
+    assert!(!allowed("https://dukekunshan.edu.cn/misc/drupal.css.map"));
+    assert!(!allowed("https://dukekunshan.edu.cn/misc/README.txt"));
+
+    assert_eq!(
+        robots.crawl_delay(USER_AGENT),
+        Some(Duration::from_secs(10))
+    );
+    // the `Baiduspider` group has no `Crawl-delay`
+    assert_eq!(robots.crawl_delay("Baiduspider"), None);
+    assert!(robots.is_allowed(
+        "Baiduspider",
+        &Url::parse("https://dukekunshan.edu.cn/admin/").unwrap()
+    ));
+}
+
+#[test]
+fn test_robots_precedence() {
+    let robots = Robots::parse(
+        "User-agent: *\nDisallow: /\n\nUser-agent: search_so\nDisallow: /private\nAllow: /private/open$\nDisallow: /p\nAllow: /p\n",
+    );
+    let allowed = |u: &str| robots.is_allowed(USER_AGENT, &Url::parse(u).unwrap());
+
+    // the group of the most specific user agent is used instead of `*`
+    assert!(allowed("https://example.com/public"));
+    assert!(!allowed("https://example.com/private/secret"));
+    assert!(allowed("https://example.com/private/open"));
+    assert!(!allowed("https://example.com/private/open/more"));
+    // `Allow` wins a tie
+    assert!(allowed("https://example.com/p"));
+    assert!(!robots.is_allowed("other", &Url::parse("https://example.com/public").unwrap()));
+
+    assert!(
+        Robots::allow_all().is_allowed(USER_AGENT, &Url::parse("https://example.com/x").unwrap())
+    );
+    assert!(!Robots::disallow_all()
+        .is_allowed(USER_AGENT, &Url::parse("https://example.com/x").unwrap()));
+}
+
+#[tokio::main]
+#[test]
+async fn test_robots_cache() {
+    let cache = Config::default().robots_cache().unwrap();
+    let home = Url::parse("https://dukekunshan.edu.cn/").unwrap();
+    cache.insert(&home, Robots::parse(DKU_ROBOTS));
+
+    assert!(cache.is_allowed(&home).await);
+    assert!(!cache.is_allowed(&home.join("/search/x").unwrap()).await);
+    assert_eq!(
+        cache.crawl_delay(&home).await,
+        Some(Duration::from_secs(10))
+    );
+}
+
+#[test]
+fn test_frontier_crawl_delay() {
+    let a = Url::parse("https://a.example.com/").unwrap();
+    let b = Url::parse("https://b.example.com/").unwrap();
+    let mut frontier = Frontier::new(None);
+    frontier.set_crawl_delay(&a, Some(Duration::from_secs(10)));
+    frontier.push_back(a.join("/1").unwrap());
+    frontier.push_back(a.join("/2").unwrap());
+    frontier.push_back(b.join("/1").unwrap());
+    assert_eq!(frontier.len(), 3);
+
+    let now = Instant::now();
+    // one URL from each host before `a` has cooled down
+    let mut popped = Vec::new();
+    for _ in 0..2 {
+        match frontier.pop(now, |_| false) {
+            Pop::Ready(url) => popped.push(url),
+            pop => panic!("expected a ready URL, got {:?}", pop),
+        }
+    }
+    popped.sort();
+    assert_eq!(popped, vec![a.join("/1").unwrap(), b.join("/1").unwrap()]);
+    assert_eq!(
+        frontier.pop(now, |_| false),
+        Pop::Wait {
+            host: host_key(&a),
+            until: now + Duration::from_secs(10)
+        }
+    );
+    frontier.add_wait(&host_key(&a), Duration::from_secs(10));
+    assert_eq!(
+        frontier.pop(now + Duration::from_secs(10), |_| false),
+        Pop::Ready(a.join("/2").unwrap())
+    );
+    assert_eq!(frontier.pop(now, |_| false), Pop::Empty);
+    assert!(frontier.is_empty());
+    // popped URL are in flight until finished
+    assert!(!frontier.is_drained());
+    for url in popped.iter().chain([&a.join("/2").unwrap()]) {
+        frontier.finish(url);
+    }
+    assert!(frontier.is_drained());
+    assert_eq!(
+        frontier.wait_times(),
+        vec![(host_key(&a), Duration::from_secs(10))]
+    );
+}
+
+#[test]
+fn test_frontier_override_and_skip() {
+    let a = Url::parse("https://a.example.com/").unwrap();
+    let mut frontier = Frontier::new(Some(Duration::from_secs(1)));
+    frontier.set_crawl_delay(&a, Some(Duration::from_secs(10)));
+    frontier.push_back(a.join("/checked").unwrap());
+    frontier.push_back(a.join("/new").unwrap());
+
+    let now = Instant::now();
+    // skipped URL does not count as a fetch
+    assert_eq!(
+        frontier.pop(now, |u| u.path() == "/checked"),
+        Pop::Ready(a.join("/new").unwrap())
+    );
+    frontier.push_back(a.join("/next").unwrap());
+    // the override replaces `Crawl-delay`
+    assert_eq!(
+        frontier.pop(now, |_| false),
+        Pop::Wait {
+            host: host_key(&a),
+            until: now + Duration::from_secs(1)
+        }
+    );
+}
+
+#[test]
+fn test_frontier_requeued_in_flight() {
+    let url = Url::parse("https://a.example.com/page").unwrap();
+    let mut frontier = Frontier::default();
+    frontier.push_back(url.clone());
+    let now = Instant::now();
+    assert_eq!(frontier.pop(now, |_| false), Pop::Ready(url.clone()));
+    // requeued at once and popped by another worker before the first finishes
+    frontier.push_retry(url.clone(), Duration::ZERO);
+    let later = Instant::now();
+    assert_eq!(frontier.pop(later, |_| false), Pop::Ready(url.clone()));
+    frontier.finish(&url);
+    assert_eq!(frontier.snapshot(), vec![url.clone()]);
+    assert!(!frontier.is_drained());
+    frontier.finish(&url);
+    assert!(frontier.is_drained());
+}
+
+#[test]
+fn test_sharded_frontier() {
+    let hosts: Vec<Url> = (0..40)
+        .map(|i| Url::parse(&format!("https://{}.example.com/", i)).unwrap())
+        .collect();
+    let frontier = ShardedFrontier::default();
+    for host in &hosts {
+        frontier.set_crawl_delay(host, Some(Duration::from_secs(10)));
+        frontier.push_back(host.join("/1").unwrap());
+        frontier.push_back(host.join("/2").unwrap());
+    }
+    assert_eq!(frontier.len(), 80);
+
+    // one URL of every host, from every shard, whatever the first shard
+    let now = Instant::now();
+    let mut popped = Vec::new();
+    let skip = |u: &Url| u.host_str() == Some("0.example.com");
+    for first in 0..hosts.len() - 1 {
+        match frontier.pop(now, first, skip) {
+            Pop::Ready(url) => popped.push(url),
+            pop => panic!("expected a ready URL, got {:?}", pop),
+        }
+    }
+    assert!(matches!(
+        frontier.pop(now, 0, skip),
+        Pop::Wait { until, .. } if until == now + Duration::from_secs(10)
+    ));
+    // both URL of the skipped host are dropped
+    assert_eq!(frontier.len(), 39);
+    assert_eq!(frontier.snapshot().len(), 78);
+
+    for url in &popped {
+        frontier.finish(url);
+    }
+    assert!(!frontier.is_drained());
+    let later = now + Duration::from_secs(10);
+    while let Pop::Ready(url) = frontier.pop(later, 0, |_| false) {
+        frontier.finish(&url);
+    }
+    assert!(frontier.is_empty());
+    assert!(frontier.is_drained());
+}
+
+#[test]
+fn test_seen_set() {
+    let url = |p: &str| Url::parse("https://example.com/").unwrap().join(p).unwrap();
+    let seen = SeenSet::new(
+        SeenBackend::Exact,
+        HashMap::from([(url("/a"), false), (url("/b"), true)]),
+    );
+
+    assert_eq!(seen.get(&url("/a")), Some(false));
+    assert_eq!(seen.get(&url("/c")), None);
+    assert_eq!(seen.check(&url("/a")), Some(false));
+    assert_eq!(seen.check(&url("/a")), Some(true));
+    assert_eq!(seen.check(&url("/c")), None);
+    assert!(seen.insert_new(&url("/d"), false));
+    assert!(!seen.insert_new(&url("/d"), true));
+    assert_eq!(seen.len(), 4);
+    assert_eq!(
+        seen.snapshot(),
+        HashMap::from([
+            (url("/a"), true),
+            (url("/b"), true),
+            (url("/c"), true),
+            (url("/d"), false)
+        ])
+    );
+}
+
+#[test]
+fn test_seen_set_bloom() {
+    let url = |i: usize| Url::parse(&format!("https://example.com/page/{}", i)).unwrap();
+    let bloom = SeenBackend::Bloom {
+        false_positive: 0.01,
+    };
+    let seen = SeenSet::new(bloom, HashMap::from([(url(0), false), (url(1), true)]));
+    assert_eq!(seen.get(&url(0)), Some(false));
+    assert_eq!(seen.get(&url(1)), Some(true));
+    assert_eq!(seen.check(&url(0)), Some(false));
+    assert_eq!(seen.get(&url(0)), Some(true));
+    // fetched again, counted once
+    seen.uncheck(&url(1));
+    assert_eq!(seen.get(&url(1)), Some(false));
+    assert_eq!(seen.len(), 2);
+    assert_eq!(seen.check(&url(1)), Some(false));
+    assert_eq!(seen.check(&url(1)), Some(true));
+    assert_eq!(seen.len(), 2);
+
+    // no false negative while growing past the first filters
+    let exact = SeenSet::new(SeenBackend::Exact, HashMap::new());
+    let mut taken_as_seen = 0;
+    for i in 2..50_000 {
+        exact.insert_new(&url(i), i % 2 == 0);
+        if !seen.insert_new(&url(i), i % 2 == 0) {
+            taken_as_seen += 1;
+        } else {
+            assert_eq!(seen.get(&url(i)), Some(i % 2 == 0));
+        }
+    }
+    assert!(taken_as_seen < 500, "{} false positives", taken_as_seen);
+    assert!((2..50_000).all(|i| seen.get(&url(i)).is_some()));
+    let false_positives = (50_000..100_000)
+        .filter(|i| seen.get(&url(*i)).is_some())
+        .count();
+    assert!(false_positives < 500, "{} false positives", false_positives);
+    assert!(seen.memory_bytes() < exact.memory_bytes() / 2);
+    // only the unchecked URL can be saved
+    assert_eq!(seen.snapshot().len(), 24_999 - taken_as_seen);
+    assert!(seen.snapshot().values().all(|checked| !checked));
+
+    assert_eq!("bloom:0.01".parse(), Ok(bloom));
+    assert_eq!("exact".parse(), Ok(SeenBackend::Exact));
+    assert!("bloom:2".parse::<SeenBackend>().is_err());
+    assert_eq!(bloom.to_string(), "bloom:0.01");
+}
+
+#[test]
+fn test_link_extractors() {
+    let base = Url::parse("https://example.com/dir/page.html").unwrap();
+    let html = r#"<a href="b.html">b</a><img src="/img/2.png"><a href="https://other.com/">o</a>
+        <img src="1.jpg"><a href="b.html#top">b</a><a>no href</a><img src="http://[bad">"#;
+    let url = |u: &str| Url::parse(u).unwrap();
+    let urls = |links: Vec<Link>| -> Vec<Url> { links.into_iter().map(|l| l.url).collect() };
+
+    assert_eq!(
+        AnchorsAndImages.extract(&base, html),
+        vec![
+            Link::new(url("https://example.com/dir/1.jpg"), LinkKind::Image),
+            Link::new(url("https://example.com/dir/b.html"), LinkKind::Anchor),
+            Link::new(url("https://example.com/dir/b.html#top"), LinkKind::Anchor),
+            Link::new(url("https://example.com/img/2.png"), LinkKind::Image),
+            Link::new(url("https://other.com/"), LinkKind::Anchor),
+        ]
+    );
+    // document order, images only
+    assert_eq!(
+        urls(Images.extract(&base, html)),
+        vec![
+            url("https://example.com/img/2.png"),
+            url("https://example.com/dir/1.jpg"),
+        ]
+    );
+
+    let html = r#"<head><base href="/root/"><link rel="stylesheet" href="s.css">
+        <link rel="canonical" href="/canonical"><link rel="alternate" hreflang="fr" href="fr/">
+        <link rel="icon" href="/favicon.ico"><script src="app.js"></script><script>inline()</script>
+        <meta http-equiv="Refresh" content="5; URL='next.html'"></head>
+        <body><img srcset="small.jpg 1x, large,big.jpg 2x, data:image/png;base64,AA, last.jpg">
+        <picture><source srcset="wide.webp 800w"></picture>
+        <video><source src="clip.mp4"></video><iframe src="https://ads.example.org/"></iframe>
+        <map><area href="area.html"></map><form action="/search"></form>
+        <form method="POST" action="/login"></form><form method="get"></form></body>"#;
+    let links = AllLinks.extract(&base, html);
+    let mut found: Vec<(String, LinkKind)> = links
+        .iter()
+        .filter(|l| l.url.scheme() != "data")
+        .map(|l| (l.url.to_string(), l.kind))
+        .collect();
+    found.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+    let root = "https://example.com/root/";
+    let expected = [
+        (format!("{}area.html", root), LinkKind::Area),
+        (format!("{}large,big.jpg", root), LinkKind::Image),
+        (format!("{}last.jpg", root), LinkKind::Image),
+        (format!("{}small.jpg", root), LinkKind::Image),
+        (format!("{}wide.webp", root), LinkKind::Image),
+        (format!("{}clip.mp4", root), LinkKind::Media),
+        (format!("{}s.css", root), LinkKind::Stylesheet),
+        (
+            "https://example.com/canonical".to_owned(),
+            LinkKind::Canonical,
+        ),
+        (format!("{}fr/", root), LinkKind::Alternate),
+        ("https://example.com/favicon.ico".to_owned(), LinkKind::Link),
+        (format!("{}app.js", root), LinkKind::Script),
+        ("https://ads.example.org/".to_owned(), LinkKind::Frame),
+        ("https://example.com/search".to_owned(), LinkKind::Form),
+        (format!("{}next.html", root), LinkKind::Refresh),
+    ];
+    assert_eq!(found, expected);
+    assert_eq!(
+        canonical_link(&base, html),
+        Some(url("https://example.com/canonical"))
+    );
+
+    assert_eq!("frame".parse::<LinkKind>(), Ok(LinkKind::Frame));
+    assert!("iframe".parse::<LinkKind>().is_err());
+    let config = Config::parse(r#"link_kinds = ["anchor", "script"]"#).unwrap();
+    let filter = config.link_kinds(AllowAll);
+    assert!(filter.follows(LinkKind::Script));
+    assert!(!filter.follows(LinkKind::Image));
+    let filter = Config::default().link_kinds(AllowAll);
+    assert!(filter.follows(LinkKind::Image));
+    assert!(!filter.follows(LinkKind::Script));
+    let error = Config::parse(r#"link_kinds = ["anchor", "iframe"]"#).unwrap_err();
+    assert!(
+        error.starts_with("link_kinds[1]: unknown link kind `iframe`"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_url_extension() {
+    let extension = |u: &str| url_extension(&Url::parse(u).unwrap());
+
+    assert_eq!(extension("https://example.com/a/file.pdf"), Some("pdf"));
+    assert_eq!(
+        extension("https://example.com/a.PNG?width=800"),
+        Some("png")
+    );
+    // no extension after the last `/`
+    assert_eq!(extension("https://example.com/a/file"), None);
+    assert_eq!(extension("https://dukekunshan.edu.cn/"), None);
+    assert_eq!(
+        extension("https://dukekunshan.edu.cn/en/printpdf/5638"),
+        None
+    );
+    // unknown extensions say nothing about the content
+    assert_eq!(extension("https://example.com/download.php?id=3"), None);
+}
+
+#[test]
+fn test_choose_extension() {
+    let url = Url::parse("https://example.com/download.php?id=3").unwrap();
+    let choose = |headers: &[(&'static str, &'static str)], body: &[u8]| {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.insert(*name, HeaderValue::from_static(value));
+        }
+        choose_extension(&map, &url, body)
+    };
+
+    // content type, parameters and case ignored
+    assert_eq!(choose(&[("content-type", "application/pdf")], b""), ".pdf");
+    assert_eq!(
+        choose(&[("content-type", "Text/Plain; charset=UTF-8")], b"%PDF-"),
+        ".txt"
+    );
+    assert_eq!(
+        choose(
+            &[(
+                "content-type",
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+            )],
+            b"PK\x03\x04"
+        ),
+        ".docx"
+    );
+    // the file name beats the content type
+    assert_eq!(
+        choose(
+            &[
+                ("content-type", "application/zip"),
+                (
+                    "content-disposition",
+                    "attachment; filename=\"Report 2022.XLSX\""
+                ),
+            ],
+            b"PK\x03\x04"
+        ),
+        ".xlsx"
+    );
+    assert_eq!(
+        choose(
+            &[(
+                "content-disposition",
+                "attachment; filename=\"a.txt\"; filename*=UTF-8''%E6%8A%A5%E5%91%8A.pdf"
+            )],
+            b""
+        ),
+        ".pdf"
+    );
+    // generic or missing type: sniffed
+    assert_eq!(
+        choose(&[("content-type", "application/octet-stream")], b"%PDF-1.4"),
+        ".pdf"
+    );
+    assert_eq!(choose(&[], b"\x89PNG\r\n\x1a\n"), ".png");
+    assert_eq!(choose(&[], b"\xff\xd8\xff\xe0"), ".jpg");
+    assert_eq!(choose(&[], b"RIFF\x00\x00\x00\x00WEBPVP8 "), ".webp");
+    assert_eq!(
+        choose(&[], b"\xef\xbb\xbf\n <!DOCTYPE html><html>"),
+        ".html"
+    );
+    assert_eq!(choose(&[], b"<?xml version=\"1.0\"?><svg>"), ".svg");
+    // unsafe file names and unknown bodies
+    assert_eq!(
+        choose(
+            &[("content-disposition", "attachment; filename=\"../../x.sh\"")],
+            b"#!/bin/sh"
+        ),
+        ".bin"
+    );
+    assert_eq!(choose(&[], b"<button>"), ".bin");
+    // finally the URL
+    let url = Url::parse("https://example.com/files/slides.pptx").unwrap();
+    assert_eq!(choose_extension(&HeaderMap::new(), &url, b"?"), ".pptx");
+
+    assert_eq!(extension_mime("JPEG"), Some("image/jpeg"));
+    assert_eq!(extension_mime("exe"), None);
+}
+
+#[test]
+fn test_percent_decode() {
+    assert_eq!(percent_decode("%E6%8A%A5%e5%91%8a.pdf"), "报告.pdf");
+    // only two hex digits are an escape
+    assert_eq!(percent_decode("a%+1b%2"), "a%+1b%2");
+    assert_eq!(percent_decode("%zz%41"), "%zzA");
+}
+
+#[test]
+fn test_canonicalizer() {
+    let canonicalizer = Canonicalizer::default();
+    let canonical = |u: &str| {
+        canonicalizer
+            .canonicalize(&Url::parse(u).unwrap())
+            .to_string()
+    };
+
+    assert_eq!(
+        canonical("HTTPS://Example.COM:443/a/./b/../c/#top"),
+        "https://example.com/a/c"
+    );
+    assert_eq!(canonical("https://example.com/"), "https://example.com/");
+    // tracking parameters dropped, the others sorted
+    assert_eq!(
+        canonical("https://example.com/p?utm_source=x&b=2&fbclid=y&a=1&&utm%5Fmedium=z"),
+        "https://example.com/p?a=1&b=2"
+    );
+    assert_eq!(
+        canonical("https://example.com/p?utm_campaign=x"),
+        "https://example.com/p"
+    );
+    // unreserved characters decoded, other escapes uppercased
+    assert_eq!(
+        canonical("https://example.com/%7euser/a%2fb?q=%41%3d%zz"),
+        "https://example.com/~user/a%2Fb?q=A%3D%zz"
+    );
+
+    let url = Url::parse("https://example.com/a/?utm_source=x#top").unwrap();
+    assert_eq!(Canonicalizer::identity().canonicalize(&url), url);
+
+    // settings for every host, replaced for some
+    let config = Config::parse(
+        r#"
+        strip_trailing_slash = false
+        [hosts."Legacy.example.com"]
+        lowercase_path = true
+        strip_fragment = false
+        drop_params = "^session$"
+        "#,
+    )
+    .unwrap();
+    let canonicalizer = config.canonicalizer();
+    let canonical = |u: &str| {
+        canonicalizer
+            .canonicalize(&Url::parse(u).unwrap())
+            .to_string()
+    };
+    assert_eq!(
+        canonical("https://example.com/A/?session=1#top"),
+        "https://example.com/A/?session=1"
+    );
+    assert_eq!(
+        canonical("https://legacy.example.com/A%2f/?session=1&utm_source=x#top"),
+        "https://legacy.example.com/a%2F/?utm_source=x#top"
+    );
+    let error = Config::parse("[hosts.\"example.com\"]\ndrop_params = \"(\"").unwrap_err();
+    assert!(
+        error.starts_with("hosts.\"example.com\".drop_params: "),
+        "{}",
+        error
+    );
+
+    let base = Url::parse("https://example.com/print?page=1").unwrap();
+    assert_eq!(
+        canonical_link(
+            &base,
+            r#"<link rel="stylesheet" href="/s.css"><link rel="Canonical alternate" href="/page">"#
+        ),
+        Some(Url::parse("https://example.com/page").unwrap())
+    );
+    assert_eq!(
+        canonical_link(&base, r#"<a rel="canonical" href="/x">"#),
+        None
+    );
+}
+
+#[test]
+fn test_content_index() {
+    let url = |p: &str| Url::parse("https://example.com/").unwrap().join(p).unwrap();
+    let index = ContentIndex::default();
+    let page = content_hash(b"<html>page</html>");
+    let image = content_hash(b"image");
+    assert_eq!(page.len(), 64);
+    // same hash in chunks
+    let mut hasher = ContentHasher::default();
+    hasher.update(b"<html>pa");
+    hasher.update(b"ge</html>");
+    assert_eq!(hasher.finish(), page);
+
+    assert!(!index.insert(&page, &url("/b")));
+    assert!(!index.insert(&image, &url("/image.png")));
+    assert!(index.insert(&page, &url("/a")));
+    assert!(index.insert(&page, &url("/c")));
+    // the first URL serving it stays the original
+    assert!(!index.insert(&page, &url("/b")));
+    assert_eq!(index.len(), 2);
+
+    let clusters = index.clusters();
+    assert_eq!(clusters.len(), 1);
+    assert_eq!(clusters[0].urls, vec![url("/a"), url("/b"), url("/c")]);
+    assert_eq!(
+        report(&clusters),
+        format!(
+            "{} (3 URL)\n  https://example.com/a\n  https://example.com/b\n  https://example.com/c\n",
+            page
+        )
+    );
+}
+
+#[test]
+fn test_near_duplicates() {
+    let event = |day: usize| {
+        let mut text: Vec<String> = (0..300).map(|i| format!("word{}", i)).collect();
+        text[150] = format!("2021-05-{:02}", day);
+        text.join(" ")
+    };
+    let distance = |a: &str, b: &str| (simhash(a) ^ simhash(b)).count_ones();
+    assert_eq!(distance(&event(1), &event(1).to_uppercase()), 0);
+    assert!(distance(&event(1), &event(2)) <= 3);
+    assert!(distance(&event(1), "a completely different page") > 10);
+
+    let family = |u: &str| link_family(&Url::parse(u).unwrap());
+    assert_eq!(
+        family("https://example.com/events/2021-05-03/talk"),
+        "example.com/events/#-#-#"
+    );
+    assert_eq!(
+        family("https://example.com/event-list?page=3&type=x&page=4"),
+        "example.com/event-list?page&type"
+    );
+    assert_eq!(family("https://example.com/about"), "example.com/about");
+
+    let url = |p: &str| Url::parse("https://example.com/").unwrap().join(p).unwrap();
+    let near_duplicates = NearDuplicates::new("3:2".parse().unwrap());
+    assert_eq!(
+        near_duplicates.observe(&url("/events/1/a"), &event(1)),
+        None
+    );
+    assert_eq!(near_duplicates.observe(&url("/news/1"), "other news"), None);
+    assert_eq!(
+        near_duplicates.observe(&url("/events/2/b"), &event(2)),
+        Some(url("/events/1/a"))
+    );
+    assert!(!near_duplicates.is_exhausted(&url("/events/3/c")));
+    assert!(near_duplicates
+        .observe(&url("/events/3/c"), &event(3))
+        .is_some());
+    assert!(near_duplicates.is_exhausted(&url("/events/9/z")));
+    assert!(!near_duplicates.is_exhausted(&url("/news/2")));
+    assert_eq!(
+        near_duplicates.exhausted_families(),
+        vec![("example.com/events/#".to_owned(), 2)]
+    );
+
+    assert_eq!(
+        "4".parse(),
+        Ok(NearDupConfig {
+            max_distance: 4,
+            family_limit: 5
+        })
+    );
+    assert!("32".parse::<NearDupConfig>().is_err());
+    assert!("3:0".parse::<NearDupConfig>().is_err());
+    assert_eq!(NearDupConfig::default().to_string(), "3:5");
+}
+
+#[tokio::main]
+#[test]
+async fn test_config() {
+    let config = Config::parse(
+        r#"
+        seeds = ["https://dukekunshan.edu.cn/"]
+        include = ['dukekunshan\.edu\.cn']
+        exclude = ['prefix:/node', 'print/']
+        concurrency = 8
+        request_timeout_secs = 30
+        seen = "bloom:0.01"
+
+        [hosts."dukekunshan.edu.cn"]
+        crawl_delay_secs = 2.5
+        exclude = ['glob:https://*/zh/**']
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.concurrency, 8);
+    assert_eq!(config.save_attempts, 3);
+    assert_eq!(config.request_timeout_secs, Some(30.0));
+    assert_eq!(
+        config.seed_urls(),
+        vec![Url::parse("https://dukekunshan.edu.cn/").unwrap()]
+    );
+    assert_eq!(
+        config.seen_backend(),
+        SeenBackend::Bloom {
+            false_positive: 0.01
+        }
+    );
+
+    let url = |u: &str| Url::parse(u).unwrap();
+    let filter = config.rule_filter().unwrap();
+    assert!(!filter.is_filtered(&url("https://dukekunshan.edu.cn/en/about")));
+    assert!(filter.is_filtered(&url("https://dukekunshan.edu.cn/node/1")));
+    assert!(filter.is_filtered(&url("https://example.com/")));
+    let filter = config.host_rules(AllowAll);
+    assert!(
+        !filter
+            .allows(&url("https://dukekunshan.edu.cn/zh/about"))
+            .await
+    );
+    assert!(filter.allows(&url("https://example.com/zh/about")).await);
+    assert_eq!(
+        filter
+            .crawl_delay(&url("https://dukekunshan.edu.cn/"))
+            .await,
+        Some(Duration::from_secs_f64(2.5))
+    );
+    assert_eq!(filter.crawl_delay(&url("https://example.com/")).await, None);
+    // empty rules crawl everything
+    assert!(!Config::default()
+        .rule_filter()
+        .unwrap()
+        .is_filtered(&url("https://example.com/")));
+
+    // every problem is reported
+    let errors = Config::parse(
+        r#"
+        seeds = ["not a url"]
+        exclude = ['(']
+        concurrency = 0
+        connect_timeout_secs = -1
+        near_dup = "99"
+        "#,
+    )
+    .unwrap_err();
+    let errors: Vec<&str> = errors.lines().collect();
+    assert_eq!(errors.len(), 5, "{:?}", errors);
+    assert!(errors[0].starts_with("concurrency: "));
+    assert!(errors[1].starts_with("connect_timeout_secs: -1 "));
+    assert!(errors[2].starts_with("exclude[0]: "));
+    assert!(errors[3].starts_with("near_dup: "));
+    assert!(errors[4].starts_with("seeds[0] `not a url`: "));
+    // typos and wrong types are rejected
+    let error = Config::parse("concurrency = 4\nconcurency = 8\n").unwrap_err();
+    assert!(error.contains("unknown field `concurency`"), "{}", error);
+    assert!(Config::parse("concurrency = \"8\"").is_err());
+    // durations too large for `Duration` and host rules are checked too
+    let errors = Config::parse(
+        r#"
+        retry_max_secs = 1e300
+        [hosts."example.com"]
+        crawl_delay_secs = 1e20
+        exclude = ['(']
+        "#,
+    )
+    .unwrap_err();
+    let errors: Vec<&str> = errors.lines().collect();
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors[0].starts_with("hosts.\"example.com\".crawl_delay_secs: "));
+    assert!(errors[0].ends_with(" is too large"));
+    assert!(errors[1].starts_with("hosts.\"example.com\".exclude[0]: "));
+    assert!(errors[2].starts_with("retry_max_secs: "));
+}
+
+#[test]
+fn test_workdir_config() {
+    let dir = temp_dir().join(format!("workdir_config_{}", process::id()));
+    let _ = remove_dir_all(&dir);
+    create_dir_all(&dir).unwrap();
+    let workdir = Workdir {
+        dir: dir.clone(),
+        config: None,
+    };
+
+    // no config file: the defaults, saving in the workdir
+    assert_eq!(workdir.load_config().unwrap().output_dir, dir.join("."));
+    // rule files are read from the workdir, not the current directory
+    write(dir.join("rules.txt"), "/private\n").unwrap();
+    write(
+        dir.join(CONFIG_FILE),
+        "output_dir = \"out\"\nexclude_files = [\"rules.txt\"]\n",
+    )
+    .unwrap();
+    let config = workdir.load_config().unwrap();
+    assert_eq!(config.output_dir, dir.join("out"));
+    assert_eq!(config.exclude_files, vec![dir.join("rules.txt")]);
+
+    let missing = Workdir {
+        dir: dir.join("missing"),
+        config: None,
+    };
+    assert!(missing.load_config().is_err());
+    remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_rules() {
+    let url = |u: &str| Url::parse(u).unwrap();
+    let rules = parse_rules(
+        "# comment\n\
+        \n\
+        host:Example.com  \n\
+        glob:https://*/files/**.pdf\n\
+        prefix:/news/\n\
+        ^https://a\\.org/\\d+$\n\
+        regex:print\n",
+        "rules.txt",
+    )
+    .unwrap();
+    assert_eq!(
+        rules.iter().map(|r| r.kind).collect::<Vec<_>>(),
+        vec![
+            RuleKind::Host,
+            RuleKind::Glob,
+            RuleKind::Prefix,
+            RuleKind::Regex,
+            RuleKind::Regex
+        ]
+    );
+    assert_eq!(rules[0].to_string(), "rules.txt:3: host:Example.com");
+    let list = RuleList::new(rules).unwrap();
+    let origins = |u: &str| -> Vec<String> {
+        list.matches(&url(u))
+            .iter()
+            .map(|r| r.origin.clone())
+            .collect()
+    };
+
+    // host and subdomains, not hosts ending the same
+    assert_eq!(origins("http://example.com"), vec!["rules.txt:3"]);
+    assert_eq!(
+        origins("https://www.example.com:8080/a"),
+        vec!["rules.txt:3"]
+    );
+    assert!(origins("https://notexample.com/").is_empty());
+    assert!(origins("https://a.org/?q=example.com").is_empty());
+    // `*` stays within a segment, `**` does not
+    assert_eq!(origins("https://b.org/files/x/y.pdf"), vec!["rules.txt:4"]);
+    assert!(origins("https://b.org/c/files/y.pdf").is_empty());
+    assert!(origins("https://b.org/files/y.pdf?download").is_empty());
+    // prefix of the path only
+    assert_eq!(origins("https://b.org/news/1"), vec!["rules.txt:5"]);
+    assert!(origins("https://b.org/en/news/1").is_empty());
+    assert_eq!(origins("https://a.org/12"), vec!["rules.txt:6"]);
+    assert_eq!(
+        origins("https://example.com/news/print"),
+        vec!["rules.txt:3", "rules.txt:5", "rules.txt:7"]
+    );
+
+    // every bad line is reported
+    let errors = parse_rules("(\nprefix:news\nglob:\nhost:a.org\n", "bad.txt").unwrap_err();
+    let errors: Vec<&str> = errors.lines().collect();
+    assert_eq!(errors.len(), 3, "{:?}", errors);
+    assert!(errors[0].starts_with("bad.txt:1: "));
+    assert_eq!(
+        errors[1],
+        "bad.txt:2: prefix `news` does not start with `/`"
+    );
+    assert_eq!(errors[2], "bad.txt:3: empty glob rule");
+
+    let filter = RuleFilter {
+        include: RuleList::new(parse_rules("host:a.org", "include").unwrap()).unwrap(),
+        exclude: RuleList::new(parse_rules("prefix:/private", "exclude").unwrap()).unwrap(),
+    };
+    let explanation = filter.explain(&url("https://a.org/private/1"));
+    assert!(!explanation.is_allowed());
+    assert!(filter.is_filtered(&url("https://a.org/private/1")));
+    assert_eq!(
+        explanation.to_string(),
+        "rejected\n  excluded by exclude:1: prefix:/private\n  included by include:1: host:a.org"
+    );
+    assert!(filter.explain(&url("https://a.org/")).is_allowed());
+    assert_eq!(
+        filter.explain(&url("https://b.org/")).to_string(),
+        "rejected\n  matches no include rule"
+    );
+    assert_eq!(
+        RuleFilter::default()
+            .explain(&url("https://b.org/"))
+            .to_string(),
+        "allowed"
+    );
+}
+
+#[test]
+fn test_size_limits() {
+    let config = Config::parse(
+        r#"
+        max_body_bytes = 1000
+        byte_budget = 1500
+
+        [max_body_bytes_by_type]
+        "Video/*" = 10
+        "video/mp4" = 100
+        "#,
+    )
+    .unwrap();
+    let limits = config.size_limits();
+    assert_eq!(limits.limit(Some("video/mp4")), 100);
+    assert_eq!(limits.limit(Some("video/webm")), 10);
+    assert_eq!(limits.limit(Some("text/html")), 1000);
+    assert_eq!(limits.limit(None), 1000);
+    // HTML has a lower limit by default
+    let limits = SizeLimits::default();
+    assert!(limits.limit(Some("text/html")) < limits.limit(Some("application/pdf")));
+
+    let budget = ByteBudget::new(config.byte_budget);
+    assert!(budget.take(1000));
+    assert!(budget.take(500));
+    assert!(!budget.is_exhausted());
+    assert!(!budget.take(1));
+    assert!(budget.is_exhausted());
+    assert_eq!(budget.used(), 1500);
+    let unlimited = ByteBudget::new(None);
+    assert!(unlimited.take(u32::MAX as u64));
+    assert!(!unlimited.is_exhausted());
+
+    let errors = Config::parse(
+        r#"
+        byte_budget = 0
+        [max_body_bytes_by_type]
+        video = 10
+        "#,
+    )
+    .unwrap_err();
+    let errors: Vec<&str> = errors.lines().collect();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0].starts_with("byte_budget: "));
+    assert!(errors[1].starts_with("max_body_bytes_by_type.\"video\": "));
+}
+
+#[test]
+fn test_routes() {
+    let config = Config::parse(
+        r#"
+        [routes]
+        "Text/CSS" = "links"
+        "text/*" = "text"
+        "application/javascript" = "skip"
+        "image/*" = "image"
+        "*/*" = "save"
+        "#,
+    )
+    .unwrap();
+    let routes = config.routes();
+    assert_eq!(routes.action(Some("text/css")), Action::Links);
+    assert_eq!(routes.action(Some("text/plain")), Action::Text);
+    assert_eq!(routes.action(Some("application/javascript")), Action::Skip);
+    assert_eq!(routes.action(Some("image/png")), Action::Image);
+    assert_eq!(routes.action(Some("application/pdf")), Action::Save);
+    assert_eq!(routes.action(None), Action::Save);
+    // no route saves everything
+    assert_eq!(Routes::default().action(Some("text/css")), Action::Save);
+    assert_eq!("text".parse::<Action>(), Ok(Action::Text));
+    assert!("print".parse::<Action>().is_err());
+
+    let errors = Config::parse(
+        r#"
+        [routes]
+        "*/html" = "save"
+        "text/plain" = "print"
+        "#,
+    )
+    .unwrap_err();
+    let errors: Vec<&str> = errors.lines().collect();
+    assert_eq!(errors.len(), 2, "{:?}", errors);
+    assert!(errors[0].starts_with("routes.\"*/html\": "));
+    assert!(errors[1].starts_with("routes.\"text/plain\": unknown action `print`"));
+
+    let url = |u: &str| Url::parse(u).unwrap();
+    let base = url("https://example.com/css/site.css");
+    let css = r#"@import "print.css"; @import url('/fonts.css');
+        body { background: url(../img/bg.png) } i { background: url("data:image/png;base64,AA") }
+        b { background: url( ../img/bg.png ) }"#;
+    assert_eq!(
+        css_links(&base, css),
+        vec![
+            url("https://example.com/css/print.css"),
+            url("https://example.com/fonts.css"),
+            url("https://example.com/img/bg.png"),
+        ]
+    );
+    assert_eq!(
+        text_links("see https://example.com/a, or (http://example.org/b).\nftp://x/"),
+        vec![url("http://example.org/b"), url("https://example.com/a")]
+    );
+
+    let html = "<p>Hi <script>x()</script>there</p>";
+    assert_eq!(
+        body_text("text/html", html.as_bytes()),
+        Some(visible_text(html))
+    );
+    assert_eq!(
+        body_text("application/json", b"{\"a\": 1}").as_deref(),
+        Some("{\"a\": 1}")
+    );
+    assert_eq!(body_text("application/pdf", b"%PDF-broken"), None);
+    assert_eq!(body_text("image/png", b"\x89PNG"), None);
+}
+
+#[test]
+fn test_meta_robots() {
+    let mut directives = Directives::default();
+    directives.add("index, NoFollow, max-snippet:50");
+    assert_eq!(
+        directives,
+        Directives {
+            noindex: false,
+            nofollow: true
+        }
+    );
+    directives.add("none");
+    assert!(directives.noindex);
+
+    let robots_meta = Config::default().robots_meta().unwrap();
+    assert!(!robots_meta.save_noindex);
+    let headers = |values: &[&str]| {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(X_ROBOTS_TAG, HeaderValue::from_str(value).unwrap());
+        }
+        robots_meta.from_headers(&headers)
+    };
+    assert_eq!(headers(&[]), Directives::default());
+    assert!(headers(&["noindex"]).noindex);
+    assert!(headers(&["noindex, unavailable_after: 25 Jun 2010"]).noindex);
+    // for other crawlers only
+    assert_eq!(headers(&["otherbot: noindex"]), Directives::default());
+    assert_eq!(
+        headers(&["unavailable_after: 25 Jun 2010"]),
+        Directives::default()
+    );
+    let directives = headers(&["Search_So_Scraper: nofollow", "noindex"]);
+    assert!(directives.noindex && directives.nofollow);
+
+    let html = r#"<head><meta name="ROBOTS" content="noindex">
+        <meta name="search_so_scraper" content="nofollow">
+        <meta name="otherbot" content="none"></head>"#;
+    let directives = robots_meta.from_html(html);
+    assert!(directives.noindex && directives.nofollow);
+    assert_eq!(
+        robots_meta.from_html("<meta name=\"otherbot\" content=\"none\">"),
+        Directives::default()
+    );
+
+    let base = Url::parse("https://example.com/").unwrap();
+    let html = r#"<a href="/a" rel="external NoFollow">a</a><a href="/b">b</a>
+        <link rel="nofollow alternate" href="/c">"#;
+    let links = AllLinks.extract(&base, html);
+    let nofollow: Vec<(&str, bool)> = links.iter().map(|l| (l.url.path(), l.nofollow)).collect();
+    assert_eq!(nofollow, vec![("/a", true), ("/b", false), ("/c", true)]);
+
+    let config = Config::parse("robots_meta = false").unwrap();
+    assert_eq!(config.robots_meta(), None);
+}
+
+#[test]
+fn test_retry_policy() {
+    let kind = |status| classify_status(StatusCode::from_u16(status).unwrap());
+    for status in [408, 425, 429, 500, 502, 503, 504] {
+        assert_eq!(kind(status), FailureKind::Retryable, "{}", status);
+    }
+    for status in [400, 403, 404, 410, 501, 505] {
+        assert_eq!(kind(status), FailureKind::Permanent, "{}", status);
+    }
+
+    // seconds or HTTP date
+    let after = |value: &str| {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(value).unwrap());
+        retry_after(&headers)
+    };
+    assert_eq!(after("120"), Some(Duration::from_secs(120)));
+    let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+    assert!(after(&date).unwrap() > Duration::from_secs(25));
+    assert_eq!(after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+    assert_eq!(after("soon"), None);
+    assert_eq!(retry_after(&HeaderMap::new()), None);
+
+    // doubled each failure with jitter, up to `max_delay`
+    let policy = RetryPolicy {
+        attempts: 4,
+        base_delay: Duration::from_secs(2),
+        max_delay: Duration::from_secs(5),
+    };
+    for _ in 0..20 {
+        let first = policy.backoff(1);
+        assert!(first >= Duration::from_secs(1) && first <= Duration::from_secs(2));
+        let second = policy.backoff(2);
+        assert!(second >= Duration::from_secs(2) && second <= Duration::from_secs(4));
+        assert!(policy.backoff(10) <= Duration::from_secs(5));
+    }
+    assert!(policy.next_delay(3, None).is_some());
+    assert_eq!(policy.next_delay(4, None), None);
+    assert_eq!(
+        policy.next_delay(1, Some(Duration::from_secs(3))),
+        Some(Duration::from_secs(3))
+    );
+    assert_eq!(policy.next_delay(1, Some(Duration::from_secs(6))), None);
+
+    let url = Url::parse("https://a.example.com/").unwrap();
+    let retries = Retries::default();
+    assert_eq!(retries.fail(&url), 1);
+    assert_eq!(retries.fail(&url), 2);
+    retries.forget(&url);
+    assert!(retries.is_empty());
+}
@@ -1,17 +1,31 @@
+use clap::Parser;
+use scraper_core::cli::{exit_with, join, Workdir};
 use selfcontained_scraper::crawl_links_r;
 use url::Url;
 
+/// crawl from one URL with the settings of the config
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    #[command(flatten)]
+    workdir: Workdir,
+    /// URL to start from
+    url: Url,
+}
+
 #[tokio::main]
 async fn main() {
     // let url = Url::parse("https://dukekunshan.edu.cn").unwrap();
     // let url = Url::parse("http://mp.weixin.qq.com/mp/homepage?__biz=MzU3ODg4NTQxMg==&hid=1&sn=97027463b2605823ee4aefdea01efaf9&scene=18#wechat_redirect").unwrap();
 
-    let mut args = std::env::args();
-    args.next();
-    let url_string = args.next().unwrap();
-    let url = Url::parse(&url_string).unwrap();
+    let cli = Cli::parse();
+    let config = cli.workdir.load_config().unwrap_or_else(|e| exit_with(&e));
 
-    let handle = tokio::task::spawn_blocking(move || crawl_links_r(url).unwrap());
-    let _all_urls_wrap = handle.await;
+    let url = cli.url;
+    let handle =
+        tokio::task::spawn_blocking(move || crawl_links_r(url, &config).map_err(|e| e.to_string()));
+    if let Err(e) = join(handle).await {
+        exit_with(&e);
+    }
     eprintln!("\n\nEnd");
 }